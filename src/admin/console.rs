@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+use crate::admin::AdminRequest;
+
+// Slows down password guessing
+const FAILED_LOGIN_DELAY: Duration = Duration::from_secs(2);
+const MAX_ADMIN_LINE_LENGTH: usize = 1024;
+const MAX_ADMIN_SESSIONS_PER_IP: usize = 2;
+// After this many wrong passwords an address is locked out...
+const MAX_FAILED_LOGINS: u32 = 5;
+// ...until this long after its last failed attempt
+const FAILED_LOGIN_LOCKOUT: Duration = Duration::from_secs(300);
+
+/// Open sessions and failed logins per address, shared by every remote admin connection
+#[derive(Default)]
+struct AdminLimits {
+    sessions: HashMap<IpAddr, usize>,
+    failed_logins: HashMap<IpAddr, (u32, Instant)>,
+}
+
+impl AdminLimits {
+    /// Counts a new session unless the address has too many open or is locked out
+    fn try_open(&mut self, ip: IpAddr) -> Result<(), &'static str> {
+        if let Some((failures, last)) = self.failed_logins.get(&ip) {
+            if last.elapsed() >= FAILED_LOGIN_LOCKOUT {
+                self.failed_logins.remove(&ip);
+            } else if *failures >= MAX_FAILED_LOGINS {
+                return Err("Too many failed logins, try again later\n");
+            }
+        }
+
+        let sessions = self.sessions.entry(ip).or_default();
+        if *sessions >= MAX_ADMIN_SESSIONS_PER_IP {
+            return Err("Too many admin sessions from this address\n");
+        }
+        *sessions += 1;
+        Ok(())
+    }
+
+    fn close(&mut self, ip: IpAddr) {
+        if let Some(sessions) = self.sessions.get_mut(&ip) {
+            *sessions = sessions.saturating_sub(1);
+            if *sessions == 0 {
+                self.sessions.remove(&ip);
+            }
+        }
+    }
+
+    fn login_failed(&mut self, ip: IpAddr) {
+        let entry = self.failed_logins.entry(ip).or_insert((0, Instant::now()));
+        entry.0 += 1;
+        entry.1 = Instant::now();
+    }
+
+    fn login_succeeded(&mut self, ip: IpAddr) {
+        self.failed_logins.remove(&ip);
+    }
+}
+
+/// Reads one line without buffering more than MAX_ADMIN_LINE_LENGTH, longer lines are an error
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> std::io::Result<Option<String>> {
+    let mut buf = Vec::new();
+    let read = reader.take(MAX_ADMIN_LINE_LENGTH as u64 + 1).read_until(b'\n', &mut buf).await?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if buf.last() == Some(&b'\n') {
+        buf.pop();
+    } else if buf.len() > MAX_ADMIN_LINE_LENGTH {
+        return Err(Error::new(ErrorKind::InvalidData, "line too long"));
+    }
+    if buf.last() == Some(&b'\r') {
+        buf.pop();
+    }
+    Ok(Some(String::from_utf8_lossy(&buf).into_owned()))
+}
+
+/// Hands a line to the game loop and waits for its answer
+async fn run_line(requests: &Sender<AdminRequest>, source: &str, line: String) -> Option<String> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    requests
+        .send(AdminRequest {
+            source: source.to_string(),
+            line,
+            reply: reply_tx,
+        })
+        .await
+        .ok()?;
+    reply_rx.await.ok()
+}
+
+/// Reads commands from the server's terminal until stdin closes
+pub async fn start_stdin_console(requests: Sender<AdminRequest>) {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    println!("Server console ready, type help for a list of commands");
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match run_line(&requests, "console", line).await {
+            Some(response) => println!("{}", response),
+            None => break,
+        }
+    }
+}
+
+/// Accepts line based remote admin sessions. The first line has to be the password.
+pub async fn start_remote_admin(bind_addr: SocketAddr, password: String, requests: Sender<AdminRequest>) -> std::io::Result<()> {
+    let listener = TcpListener::bind(bind_addr).await?;
+    println!("Remote admin listening on {}", bind_addr);
+    let limits = Arc::new(Mutex::new(AdminLimits::default()));
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((mut stream, addr)) => {
+                    let ip = addr.ip().to_canonical();
+                    if let Err(reason) = limits.lock().unwrap().try_open(ip) {
+                        println!("Refused remote admin connection from {}: {}", addr, reason.trim_end());
+                        tokio::spawn(async move {
+                            let _ = stream.write_all(reason.as_bytes()).await;
+                        });
+                        continue;
+                    }
+
+                    let requests = requests.clone();
+                    let password = password.clone();
+                    let limits = limits.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = remote_admin_session(stream, addr, &password, requests, &limits).await {
+                            println!("Remote admin session from {} ended: {}", addr, e);
+                        }
+                        limits.lock().unwrap().close(ip);
+                    });
+                }
+                Err(e) => eprintln!("Couldn't accept remote admin connection: {e}"),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+async fn remote_admin_session(
+    stream: TcpStream,
+    addr: SocketAddr,
+    password: &str,
+    requests: Sender<AdminRequest>,
+    limits: &Mutex<AdminLimits>,
+) -> std::io::Result<()> {
+    let ip = addr.ip().to_canonical();
+    let (read_half, mut write_half) = stream.into_split();
+    let mut reader = BufReader::new(read_half);
+
+    write_half.write_all(b"Password: ").await?;
+    // An overlong or missing password counts as a wrong one
+    let attempt = read_line(&mut reader).await;
+    if !matches!(&attempt, Ok(Some(a)) if constant_time_eq(a.as_bytes(), password.as_bytes())) {
+        println!("Failed remote admin login from {}", addr);
+        limits.lock().unwrap().login_failed(ip);
+        tokio::time::sleep(FAILED_LOGIN_DELAY).await;
+        write_half.write_all(b"Wrong password\n").await?;
+        return attempt.map(|_| ());
+    }
+    limits.lock().unwrap().login_succeeded(ip);
+
+    println!("Remote admin logged in from {}", addr);
+    let source = format!("remote {}", addr);
+    write_half.write_all(b"Logged in, type help for a list of commands\n> ").await?;
+
+    while let Some(line) = read_line(&mut reader).await? {
+        if matches!(line.trim(), "quit" | "exit") {
+            break;
+        }
+        if !line.trim().is_empty() {
+            let Some(response) = run_line(&requests, &source, line).await else {
+                break;
+            };
+            write_half.write_all(response.as_bytes()).await?;
+            write_half.write_all(b"\n").await?;
+        }
+        write_half.write_all(b"> ").await?;
+    }
+
+    println!("Remote admin from {} logged out", addr);
+    Ok(())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,350 @@
+pub mod console;
+pub mod plugin;
+
+use bevy::prelude::{Fixed, Query, Res, ResMut, Resource, Time, With};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::oneshot;
+use crate::components::chat_command::{kick, reply};
+use crate::components::common::Id;
+use crate::components::game_mode::Match;
+use crate::components::lobby::{InLobby, Lobby, AVAILABLE_MAPS};
+use crate::components::player::PlayerMarker;
+use crate::components::profile::PlayerProfiles;
+use crate::config::{AdminSettings, ServerConfig, MAX_TICK_RATE, MIN_TICK_RATE};
+use crate::network::net_bans::{BanList, BanTarget};
+use crate::network::net_manage::TcpConnection;
+use crate::network::net_message::{NetworkMessage, STcpType};
+use crate::server_log::entry::LogEvent;
+use crate::server_log::ServerLog;
+
+pub const DEFAULT_REMOTE_ADMIN_PORT: u16 = 4445;
+// The remote admin endpoint stays off unless this is set
+pub const ADMIN_PASSWORD_ENV: &str = "MPCLIENT_ADMIN_PASSWORD";
+
+#[derive(Resource, Clone)]
+pub struct AdminConfig {
+    /// Reads commands from the terminal the server was started in
+    pub stdin_console: bool,
+    pub remote_address: SocketAddr,
+    /// Remote admin is disabled without a password
+    pub remote_password: Option<String>,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            stdin_console: true,
+            remote_address: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), DEFAULT_REMOTE_ADMIN_PORT),
+            remote_password: std::env::var(ADMIN_PASSWORD_ENV).ok().filter(|p| !p.is_empty()),
+        }
+    }
+}
+
+impl AdminConfig {
+    /// The password from the environment wins over the one in the config file
+    pub fn from_settings(settings: &AdminSettings) -> Self {
+        let defaults = Self::default();
+        Self {
+            stdin_console: settings.console,
+            remote_address: settings.remote_address,
+            remote_password: defaults.remote_password.or_else(|| settings.remote_password.clone().filter(|p| !p.is_empty())),
+        }
+    }
+}
+
+/// A line typed into the console or sent by a remote admin, answered through `reply`
+pub struct AdminRequest {
+    /// Who sent it, for the log
+    pub source: String,
+    pub line: String,
+    pub reply: oneshot::Sender<String>,
+}
+
+#[derive(Resource)]
+pub struct AdminConsole {
+    pub requests: Receiver<AdminRequest>,
+}
+
+#[derive(Clone, Debug)]
+pub enum AdminCommand {
+    Help,
+    Status,
+    Kick { player: Id, reason: Option<String> },
+    Ban { target: BanTarget, minutes: Option<u64>, reason: Option<String> },
+    Unban { target: BanTarget },
+    Bans,
+    Say { text: String },
+    ChangeMap { lobby: Id, map: String },
+    SetTickRate { hz: f64 },
+}
+
+const ADMIN_COMMANDS: [(&str, &str); 9] = [
+    ("help", "Lists the console commands"),
+    ("status", "Shows the tick rate, lobbies and connected players"),
+    ("kick <id> [reason]", "Disconnects a player"),
+    ("ban <ip|ip/prefix|name:player> [minutes] [reason]", "Disconnects matching players and keeps them out, for good if no time is given"),
+    ("unban <ip|ip/prefix|name:player>", "Lifts a ban"),
+    ("bans", "Lists the active bans"),
+    ("say <text>", "Sends a server message to every player"),
+    ("changemap <lobby id> <map>", "Switches a lobby's map and restarts its match"),
+    ("settickrate <hz>", "Changes how often the server simulates per second"),
+];
+
+impl AdminCommand {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AdminCommand::Help => "help",
+            AdminCommand::Status => "status",
+            AdminCommand::Kick { .. } => "kick",
+            AdminCommand::Ban { .. } => "ban",
+            AdminCommand::Unban { .. } => "unban",
+            AdminCommand::Bans => "bans",
+            AdminCommand::Say { .. } => "say",
+            AdminCommand::ChangeMap { .. } => "changemap",
+            AdminCommand::SetTickRate { .. } => "settickrate",
+        }
+    }
+
+    /// Commands that only read state aren't written to the server log
+    fn changes_state(&self) -> bool {
+        !matches!(self, AdminCommand::Help | AdminCommand::Status | AdminCommand::Bans)
+    }
+}
+
+fn usage(name: &str) -> String {
+    match ADMIN_COMMANDS.iter().find(|(usage, _)| usage.split(' ').next() == Some(name)) {
+        Some((usage, _)) => format!("Usage: {}", usage),
+        None => format!("Unknown command: {}", name),
+    }
+}
+
+pub fn parse_admin_command(line: &str) -> Result<AdminCommand, String> {
+    let line = line.trim();
+    let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let rest = rest.trim();
+    let name = name.to_lowercase();
+
+    let parse_id = |s: &str| s.parse::<u32>().map(Id).map_err(|_| usage(&name));
+    let parse_target = |s: &str| s.parse::<BanTarget>().map_err(|e| format!("{}\n{}", e, usage(&name)));
+
+    match name.as_str() {
+        "help" | "?" => Ok(AdminCommand::Help),
+        "status" => Ok(AdminCommand::Status),
+        "kick" => {
+            let (id, reason) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            Ok(AdminCommand::Kick {
+                player: parse_id(id)?,
+                reason: (!reason.trim().is_empty()).then(|| reason.trim().to_string()),
+            })
+        }
+        "ban" => {
+            let (target, rest) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            let rest = rest.trim();
+            let (minutes, reason) = match rest.split_once(char::is_whitespace).unwrap_or((rest, "")) {
+                (m, reason) if m.parse::<u64>().is_ok() => (m.parse::<u64>().ok(), reason.trim()),
+                _ => (None, rest),
+            };
+            Ok(AdminCommand::Ban {
+                target: parse_target(target)?,
+                minutes,
+                reason: (!reason.is_empty()).then(|| reason.to_string()),
+            })
+        }
+        "unban" => Ok(AdminCommand::Unban { target: parse_target(rest)? }),
+        "bans" => Ok(AdminCommand::Bans),
+        "say" if !rest.is_empty() => Ok(AdminCommand::Say { text: rest.to_string() }),
+        "changemap" => {
+            let (lobby, map) = rest.split_once(char::is_whitespace).ok_or_else(|| usage(&name))?;
+            Ok(AdminCommand::ChangeMap {
+                lobby: parse_id(lobby)?,
+                map: map.trim().to_string(),
+            })
+        }
+        "settickrate" => {
+            let hz = rest.parse::<f64>().map_err(|_| usage(&name))?;
+            Ok(AdminCommand::SetTickRate { hz })
+        }
+        "" => Err("Type help for a list of commands".to_string()),
+        _ => Err(usage(&name)),
+    }
+}
+
+/// Runs the commands queued by the console and remote admin tasks and sends back what happened
+pub fn run_admin_commands(
+    mut console: ResMut<AdminConsole>,
+    mut connections: Query<&mut TcpConnection<STcpType>>,
+    mut lobbies: Query<(&mut Lobby, &mut Match)>,
+    players: Query<(&Id, &InLobby), With<PlayerMarker>>,
+    profiles: Res<PlayerProfiles>,
+    mut bans: ResMut<BanList>,
+    mut fixed_time: ResMut<Time<Fixed>>,
+    mut server_config: ResMut<ServerConfig>,
+    time: Res<Time>,
+    mut log: ResMut<ServerLog>,
+) {
+    loop {
+        let request = match console.requests.try_recv() {
+            Ok(r) => r,
+            Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => break,
+        };
+
+        let command = match parse_admin_command(&request.line) {
+            Ok(c) => c,
+            Err(e) => {
+                let _ = request.reply.send(e);
+                continue;
+            }
+        };
+
+        let response = match &command {
+            AdminCommand::Help => ADMIN_COMMANDS
+                .iter()
+                .map(|(usage, description)| format!("{:<28} {}", usage, description))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            AdminCommand::Status => {
+                let mut lines = vec![format!(
+                    "Up {:.0}s, {:.0} ticks per second, {} connection(s)",
+                    time.elapsed_secs(),
+                    1.0 / fixed_time.timestep().as_secs_f64(),
+                    connections.iter().count(),
+                )];
+                for (lobby, game_match) in lobbies.iter() {
+                    lines.push(format!(
+                        "Lobby {} \"{}\" on {}, {} {:?}, {}/{} players",
+                        lobby.id.0,
+                        lobby.name,
+                        lobby.map,
+                        game_match.mode.name(),
+                        game_match.phase,
+                        lobby.player_count(),
+                        lobby.max_players,
+                    ));
+                }
+                for c in connections.iter() {
+                    let Some(player_id) = c.player_id else {
+                        continue;
+                    };
+                    let address = c
+                        .stream
+                        .as_ref()
+                        .and_then(|s| s.peer_addr().ok())
+                        .map(|a| a.to_string())
+                        .unwrap_or_default();
+                    let lobby = players
+                        .iter()
+                        .find(|(id, _)| **id == player_id)
+                        .map(|(_, l)| l.0 .0.to_string())
+                        .unwrap_or_else(|| "-".to_string());
+                    lines.push(format!(
+                        "  {:>5} {:<18} {:<22} lobby {:<4} ping {}",
+                        player_id.0,
+                        profiles.display_name(player_id),
+                        address,
+                        lobby,
+                        c.ping,
+                    ));
+                }
+                lines.join("\n")
+            }
+            AdminCommand::Kick { player, reason } => {
+                match connections.iter_mut().find(|c| c.player_id == Some(*player)) {
+                    Some(mut c) => {
+                        kick(&mut c, reason.clone().unwrap_or_else(|| "Kicked by the server".to_string()));
+                        format!("Kicked {}", profiles.display_name(*player))
+                    }
+                    None => format!("No player with id {}", player.0),
+                }
+            }
+            AdminCommand::Ban { target, minutes, reason } => {
+                let ban = bans.ban(
+                    target.clone(),
+                    reason.clone().unwrap_or_else(|| "Banned by the server".to_string()),
+                    minutes.map(|m| Duration::from_secs(m * 60)),
+                );
+                let message = ban.message();
+
+                let mut kicked = 0;
+                for mut c in connections.iter_mut() {
+                    let ip_matches = c
+                        .stream
+                        .as_ref()
+                        .and_then(|s| s.peer_addr().ok())
+                        .is_some_and(|a| target.matches_ip(a.ip()));
+                    let name_matches = c.player_id.is_some_and(|id| target.matches_name(&profiles.display_name(id)));
+                    if ip_matches || name_matches {
+                        kick(&mut c, message.clone());
+                        kicked += 1;
+                    }
+                }
+                format!("Banned {}, disconnected {} player(s)", target, kicked)
+            }
+            AdminCommand::Unban { target } => match bans.unban(target) {
+                true => format!("Unbanned {}", target),
+                false => format!("{} isn't banned", target),
+            },
+            AdminCommand::Bans => {
+                let lines: Vec<String> = bans
+                    .bans()
+                    .map(|b| format!("{:<24} {}", b.target.to_string(), b.message()))
+                    .collect();
+                if lines.is_empty() {
+                    "No active bans".to_string()
+                } else {
+                    lines.join("\n")
+                }
+            }
+            AdminCommand::Say { text } => {
+                for mut c in connections.iter_mut() {
+                    reply(&mut c, format!("[Server] {}", text));
+                }
+                format!("Sent to {} player(s)", connections.iter().count())
+            }
+            AdminCommand::ChangeMap { lobby: lobby_id, map } => {
+                match AVAILABLE_MAPS.iter().find(|m| m.eq_ignore_ascii_case(map)) {
+                    None => format!("Unknown map {}, available maps: {}", map, AVAILABLE_MAPS.join(", ")),
+                    Some(map) => match lobbies.iter_mut().find(|(l, _)| l.id == *lobby_id) {
+                        Some((mut lobby, mut game_match)) => {
+                            lobby.map = map.to_string();
+                            lobby.ready.clear();
+                            game_match.restart();
+                            for mut c in connections.iter_mut() {
+                                if c.player_id.is_some_and(|id| lobby.members.contains(&id)) {
+                                    reply(&mut c, format!("[Server] Changing map to {}", map));
+                                }
+                            }
+                            format!("Lobby {} is now on {}", lobby_id.0, map)
+                        }
+                        None => format!("No lobby with id {}", lobby_id.0),
+                    },
+                }
+            }
+            AdminCommand::SetTickRate { hz } => {
+                if !(MIN_TICK_RATE..=MAX_TICK_RATE).contains(hz) {
+                    format!("Tick rate has to be between {} and {}", MIN_TICK_RATE, MAX_TICK_RATE)
+                } else {
+                    fixed_time.set_timestep_hz(*hz);
+                    // Players joining later get it with their id
+                    server_config.simulation.tick_rate = *hz;
+                    for mut c in connections.iter_mut() {
+                        c.add_message(NetworkMessage(STcpType::TickRate { tick_rate: *hz }));
+                    }
+                    format!("Simulating at {} ticks per second", hz)
+                }
+            }
+        };
+
+        if command.changes_state() {
+            log.write(LogEvent::Admin {
+                admin: 0,
+                admin_name: request.source.clone(),
+                command: command.name().to_string(),
+                details: request.line.trim().to_string(),
+            });
+        }
+        let _ = request.reply.send(response);
+    }
+}
@@ -0,0 +1,46 @@
+use bevy::app::{App, Plugin, Startup, Update};
+use bevy::prelude::{Commands, Res};
+use bevy_tokio_tasks::TokioTasksRuntime;
+use tokio::sync::mpsc;
+use crate::admin::console::{start_remote_admin, start_stdin_console};
+use crate::admin::{run_admin_commands, AdminConfig, AdminConsole, AdminRequest};
+
+/// Server console on stdin plus the optional remote admin endpoint. Needs the tokio runtime from the `NetworkPlugin`.
+pub struct AdminPlugin;
+
+impl Plugin for AdminPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AdminConfig>();
+        app.add_systems(Startup, setup_admin_console);
+        app.add_systems(Update, run_admin_commands);
+    }
+}
+
+fn setup_admin_console(
+    mut commands: Commands,
+    config: Res<AdminConfig>,
+    runtime: Res<TokioTasksRuntime>,
+) {
+    let (requests_tx, requests_rx) = mpsc::channel::<AdminRequest>(64);
+
+    if config.stdin_console {
+        let requests = requests_tx.clone();
+        runtime.spawn_background_task(|_| async move {
+            start_stdin_console(requests).await;
+        });
+    }
+
+    match config.remote_password.clone() {
+        Some(password) => {
+            let addr = config.remote_address;
+            runtime.spawn_background_task(move |_| async move {
+                if let Err(e) = start_remote_admin(addr, password, requests_tx).await {
+                    eprintln!("Couldn't start remote admin on {}: {}", addr, e);
+                }
+            });
+        }
+        None => println!("Remote admin disabled, no password set"),
+    }
+
+    commands.insert_resource(AdminConsole { requests: requests_rx });
+}
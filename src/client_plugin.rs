@@ -1,9 +1,8 @@
-use std::collections::VecDeque;
 use std::f32::consts::PI;
 use avian3d::debug_render::PhysicsDebugPlugin;
 use avian3d::PhysicsPlugins;
 use avian3d::prelude::{Collider, CollisionLayers, LayerMask, Physics, RigidBody};
-use bevy::app::{App, FixedUpdate, Startup, Update};
+use bevy::app::{App, Startup, Update};
 use bevy::asset::{AssetServer, Assets, Handle};
 use bevy::color::Color;
 use bevy::core_pipeline::Skybox;
@@ -12,24 +11,37 @@ use bevy::dev_tools::fps_overlay::FpsOverlayPlugin;
 use bevy::image::Image;
 use bevy::math::{Quat, Vec3};
 use bevy::pbr::{MeshMaterial3d, StandardMaterial};
-use bevy::prelude::{default, Camera3d, Commands, Cuboid, DirectionalLight, Fixed, Font, Mesh, Mesh3d, Msaa, Node, Plugin, PositionType, Query, Res, ResMut, Resource, Text, TextFont, Time, Transform, Val};
+use bevy::prelude::{default, in_state, not, BackgroundColor, IntoScheduleConfigs, Camera3d, Commands, Component, Cuboid, DirectionalLight, Fixed, Font, Local, Mesh, Mesh3d, Msaa, Node, Plugin, PositionType, Query, Res, ResMut, Resource, Single, Text, TextFont, Time, Transform, UiRect, Val, Visibility, With};
 use bevy::render::render_resource::{TextureViewDescriptor, TextureViewDimension};
 use bevy::text::FontSmoothing;
 use bevy_inspector_egui::bevy_egui::EguiPlugin;
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
 use crate::components::player::plugin::PlayerPlugin;
-use crate::components::chat::{chat_window, Chat};
+use crate::components::chat::{chat_window, Chat, ChatInput};
 use crate::components::CollisionLayer;
-use crate::components::hud::Hud;
-use crate::components::lobby::join_lobby;
+use crate::components::game_mode::{match_hud, MatchStatus};
+use crate::components::hud::{kill_feed_window, Hud, KillFeed};
+use crate::components::menu::MenuState;
+use crate::components::menu::plugin::MenuPlugin;
+use crate::components::profile::{send_profile_on_connect, update_label_names, LocalProfile, PlayerProfiles};
+use crate::components::settings::{apply_client_settings, ClientSettings};
+use crate::components::spectator::{spectator_hud, SpectatorStatus};
+use crate::components::scoreboard::{scoreboard_overlay, Scoreboard};
 use crate::components::weapon::Weapon;
+use crate::components::player::PlayerInfo;
+use crate::config::DEFAULT_GROUND_SIZE;
 use crate::network::net_plugin::{NetworkConfig, NetworkPlugin, RemoteAddress};
-use crate::network::net_plugin::HostType::Client;
 
 #[derive(Resource)]
 pub struct DefaultFont(pub Handle<Font>);
 
-pub struct ClientPlugin;
+#[derive(Component)]
+struct Ground;
+
+pub struct ClientPlugin {
+    /// Server to connect to, "host" or "host:port". The last server from the settings is used without one.
+    pub remote_address: Option<String>,
+}
 
 impl Plugin for ClientPlugin {
     fn build(&self, app: &mut App) {
@@ -40,25 +52,67 @@ impl Plugin for ClientPlugin {
             WorldInspectorPlugin::new(),
             FpsOverlayPlugin::default(),
             PhysicsDebugPlugin::default(),
-            NetworkPlugin::new(NetworkConfig{ host_type: Client }),
-            PlayerPlugin
+            NetworkPlugin::new(NetworkConfig::client()),
+            PlayerPlugin,
+            MenuPlugin,
         ));
+        // Replaced by the server's tick rate once connected
         app.insert_resource(Time::<Fixed>::from_hz(60.0));
         app.insert_resource(Time::<Physics>::default());
         app.insert_resource(DefaultFont(Handle::default()));
-        app.insert_resource(RemoteAddress("127.0.0.1:4444".to_string()/*remote_address.clone()*/));
+        let mut settings = ClientSettings::load();
+        let remote_address = self.remote_address.clone().unwrap_or_else(|| settings.last_server.clone());
+        if settings.last_server != remote_address {
+            settings.last_server = remote_address.clone();
+            settings.save();
+        }
+
+        app.init_resource::<PlayerProfiles>();
+        app.insert_resource(LocalProfile(settings.profile()));
+        app.init_resource::<ChatInput>();
+        app.insert_resource(RemoteAddress(remote_address));
+        app.insert_resource(settings);
         app.add_systems(Startup, setup);
         app.add_systems(Update, asset_loaded);
         app.add_systems(
-            FixedUpdate,
+            Update,
             (
-                join_lobby,
-                chat_window
+                chat_window.run_if(
+                    not(in_state(MenuState::CreateLobby))
+                        .and(not(in_state(MenuState::EditProfile)))
+                        .and(not(in_state(MenuState::Options)))
+                ),
+                scoreboard_overlay,
+                match_hud,
+                kill_feed_window,
+                spectator_hud,
+                send_profile_on_connect,
+                update_label_names,
+                apply_client_settings,
+                resize_ground,
             )
         );
     }
 }
 
+/// Rebuilds the floor when the server's ground isn't the size it was built with, so prediction hits the same edges
+fn resize_ground(
+    player_info: Res<PlayerInfo>,
+    ground: Single<(&mut Collider, &mut Mesh3d), With<Ground>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut built: Local<Option<[f32; 2]>>,
+) {
+    let size = player_info.ground_size;
+    if *built == Some(size) {
+        return;
+    }
+
+    let (mut collider, mut mesh) = ground.into_inner();
+    *collider = Collider::cuboid(size[0], 0.5, size[1]);
+    mesh.0 = meshes.add(Cuboid::new(size[0], 0.5, size[1]));
+    *built = Some(size);
+}
+
 fn setup(
     mut default_font: ResMut<DefaultFont>,
     mut commands: Commands,
@@ -91,12 +145,13 @@ fn setup(
         image_handle: skybox_handle,
     });
 
-    // Ground Plane
+    // Ground Plane, resized to the server's once connected
     commands.spawn((
+        Ground,
         RigidBody::Static,
-        Collider::cuboid(40.0, 0.5, 40.0),
+        Collider::cuboid(DEFAULT_GROUND_SIZE[0], 0.5, DEFAULT_GROUND_SIZE[1]),
         CollisionLayers::new(CollisionLayer::Ground, [LayerMask::ALL]),
-        Mesh3d(meshes.add(Cuboid::new(40.0,0.5,40.0))),
+        Mesh3d(meshes.add(Cuboid::new(DEFAULT_GROUND_SIZE[0], 0.5, DEFAULT_GROUND_SIZE[1]))),
         MeshMaterial3d(materials.add(Color::WHITE)),
         Transform::from_xyz(0.0, 0.0, 0.0),
     ));
@@ -131,9 +186,7 @@ fn setup(
 
     // Chat Window
     commands.spawn((
-        Chat {
-            chat_history: VecDeque::new(),
-        },
+        Chat::default(),
         Text::new(""),
         TextFont {
             font: default_font.0.clone(),
@@ -149,7 +202,82 @@ fn setup(
         },
     ));
 
-    commands.spawn(Weapon{ damage: 10, range: 100.0 });
+    // Scoreboard, shown while Tab is held
+    commands.spawn((
+        Scoreboard::default(),
+        Text::new(""),
+        TextFont {
+            font: default_font.0.clone(),
+            font_size: 20.0,
+            font_smoothing: FontSmoothing::None,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Percent(20.0),
+            left: Val::Percent(35.0),
+            padding: UiRect::all(Val::Px(8.0)),
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.6)),
+        Visibility::Hidden,
+    ));
+
+    // Match phase and clock
+    commands.spawn((
+        MatchStatus::default(),
+        Text::new(""),
+        TextFont {
+            font: default_font.0.clone(),
+            font_size: 24.0,
+            font_smoothing: FontSmoothing::None,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(0.5),
+            left: Val::Percent(45.0),
+            ..default()
+        },
+    ));
+
+    // Kill Feed
+    commands.spawn((
+        KillFeed::default(),
+        Text::new(""),
+        TextFont {
+            font: default_font.0.clone(),
+            font_size: 20.0,
+            font_smoothing: FontSmoothing::None,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(0.5),
+            right: Val::Px(0.5),
+            ..default()
+        },
+    ));
+
+    // Respawn countdown and who is being watched
+    commands.spawn((
+        SpectatorStatus,
+        Text::new(""),
+        TextFont {
+            font: default_font.0.clone(),
+            font_size: 24.0,
+            font_smoothing: FontSmoothing::None,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Percent(15.0),
+            left: Val::Percent(40.0),
+            ..default()
+        },
+    ));
+
+    commands.spawn(Weapon::rifle());
 }
 
 #[derive(Resource)]
@@ -0,0 +1,364 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::{ButtonInput, Gamepad, GamepadButton, KeyCode, MouseButton, Query, Res, Vec2};
+use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use crate::components::settings::ClientSettings;
+
+bitflags! {
+    /// Gameplay actions held during a tick, sent to the server with every input message
+    #[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+    #[serde(transparent)]
+    pub struct ActionFlags: u16 {
+        const MOVE_FORWARD = 1 << 0;
+        const MOVE_BACK = 1 << 1;
+        const MOVE_RIGHT = 1 << 2;
+        const MOVE_LEFT = 1 << 3;
+        const JUMP = 1 << 4;
+        const SPRINT = 1 << 5;
+        const CROUCH = 1 << 6;
+        const FIRE = 1 << 7;
+        const RELOAD = 1 << 8;
+    }
+}
+
+impl ActionFlags {
+    /// Direction the movement keys point in, x is right and y is forward
+    pub fn move_direction(&self) -> Vec2 {
+        let mut direction = Vec2::ZERO;
+        if self.contains(ActionFlags::MOVE_FORWARD) {
+            direction.y += 1.0;
+        }
+        if self.contains(ActionFlags::MOVE_BACK) {
+            direction.y -= 1.0;
+        }
+        if self.contains(ActionFlags::MOVE_RIGHT) {
+            direction.x += 1.0;
+        }
+        if self.contains(ActionFlags::MOVE_LEFT) {
+            direction.x -= 1.0;
+        }
+        direction.normalize_or_zero()
+    }
+}
+
+/// Everything a key or mouse button can be bound to
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum InputAction {
+    MoveForward,
+    MoveBack,
+    MoveLeft,
+    MoveRight,
+    Jump,
+    Sprint,
+    Crouch,
+    Fire,
+    Reload,
+    Chat,
+    TeamChat,
+    Scoreboard,
+    ToggleCursor,
+    ToggleView,
+}
+
+impl InputAction {
+    pub const ALL: [InputAction; 14] = [
+        InputAction::MoveForward,
+        InputAction::MoveBack,
+        InputAction::MoveLeft,
+        InputAction::MoveRight,
+        InputAction::Jump,
+        InputAction::Sprint,
+        InputAction::Crouch,
+        InputAction::Fire,
+        InputAction::Reload,
+        InputAction::Chat,
+        InputAction::TeamChat,
+        InputAction::Scoreboard,
+        InputAction::ToggleCursor,
+        InputAction::ToggleView,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            InputAction::MoveForward => "Forward",
+            InputAction::MoveBack => "Back",
+            InputAction::MoveLeft => "Left",
+            InputAction::MoveRight => "Right",
+            InputAction::Jump => "Jump",
+            InputAction::Sprint => "Sprint",
+            InputAction::Crouch => "Crouch",
+            InputAction::Fire => "Fire",
+            InputAction::Reload => "Reload",
+            InputAction::Chat => "Chat",
+            InputAction::TeamChat => "Team chat",
+            InputAction::Scoreboard => "Scoreboard",
+            InputAction::ToggleCursor => "Free cursor",
+            InputAction::ToggleView => "Switch view",
+        }
+    }
+
+    /// The flag sent to the server, None for actions that only matter to the client
+    pub fn flag(&self) -> Option<ActionFlags> {
+        match self {
+            InputAction::MoveForward => Some(ActionFlags::MOVE_FORWARD),
+            InputAction::MoveBack => Some(ActionFlags::MOVE_BACK),
+            InputAction::MoveLeft => Some(ActionFlags::MOVE_LEFT),
+            InputAction::MoveRight => Some(ActionFlags::MOVE_RIGHT),
+            InputAction::Jump => Some(ActionFlags::JUMP),
+            InputAction::Sprint => Some(ActionFlags::SPRINT),
+            InputAction::Crouch => Some(ActionFlags::CROUCH),
+            InputAction::Fire => Some(ActionFlags::FIRE),
+            InputAction::Reload => Some(ActionFlags::RELOAD),
+            InputAction::Chat | InputAction::TeamChat | InputAction::Scoreboard | InputAction::ToggleCursor | InputAction::ToggleView => None,
+        }
+    }
+}
+
+/// Stick direction quantized to what goes over the network, so client and server move by exactly the same amount
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct AnalogMove {
+    /// Right is positive
+    pub x: i8,
+    /// Forward is positive
+    pub y: i8,
+}
+
+impl AnalogMove {
+    pub fn from_vec2(direction: Vec2) -> Self {
+        let direction = direction.clamp_length_max(1.0) * i8::MAX as f32;
+        Self {
+            x: direction.x.round() as i8,
+            y: direction.y.round() as i8,
+        }
+    }
+
+    pub fn to_vec2(&self) -> Vec2 {
+        (Vec2::new(self.x as f32, self.y as f32) / i8::MAX as f32).clamp_length_max(1.0)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.x == 0 && self.y == 0
+    }
+}
+
+/// Scales a stick so the dead zone reads as zero and the rest of the range still reaches full deflection
+pub fn apply_dead_zone(stick: Vec2, dead_zone: f32) -> Vec2 {
+    let length = stick.length();
+    if length <= dead_zone || dead_zone >= 1.0 {
+        return Vec2::ZERO;
+    }
+    let scaled = ((length - dead_zone) / (1.0 - dead_zone)).min(1.0);
+    stick / length * scaled
+}
+
+/// A key or mouse button, written as "KeyW" or "Left" in the settings file
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum InputBinding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+impl fmt::Display for InputBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputBinding::Key(key) => {
+                // "KeyW" reads better as "W"
+                let name = format!("{:?}", key);
+                let short = name.strip_prefix("Key").or_else(|| name.strip_prefix("Digit")).unwrap_or(&name);
+                write!(f, "{}", short)
+            }
+            InputBinding::Mouse(MouseButton::Other(button)) => write!(f, "Mouse {}", button),
+            InputBinding::Mouse(button) => write!(f, "Mouse {:?}", button),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct InputBindings {
+    pub move_forward: InputBinding,
+    pub move_back: InputBinding,
+    pub move_left: InputBinding,
+    pub move_right: InputBinding,
+    pub jump: InputBinding,
+    pub sprint: InputBinding,
+    pub crouch: InputBinding,
+    pub fire: InputBinding,
+    pub reload: InputBinding,
+    pub chat: InputBinding,
+    pub team_chat: InputBinding,
+    pub scoreboard: InputBinding,
+    pub toggle_cursor: InputBinding,
+    pub toggle_view: InputBinding,
+}
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        Self {
+            move_forward: InputBinding::Key(KeyCode::KeyW),
+            move_back: InputBinding::Key(KeyCode::KeyS),
+            move_left: InputBinding::Key(KeyCode::KeyA),
+            move_right: InputBinding::Key(KeyCode::KeyD),
+            jump: InputBinding::Key(KeyCode::Space),
+            sprint: InputBinding::Key(KeyCode::ShiftLeft),
+            crouch: InputBinding::Key(KeyCode::KeyC),
+            fire: InputBinding::Mouse(MouseButton::Left),
+            reload: InputBinding::Key(KeyCode::KeyR),
+            chat: InputBinding::Key(KeyCode::KeyT),
+            team_chat: InputBinding::Key(KeyCode::KeyY),
+            scoreboard: InputBinding::Key(KeyCode::Tab),
+            // Tab is held for the scoreboard, so the cursor lock lives on left alt
+            toggle_cursor: InputBinding::Key(KeyCode::AltLeft),
+            toggle_view: InputBinding::Key(KeyCode::KeyV),
+        }
+    }
+}
+
+impl InputBindings {
+    pub fn get(&self, action: InputAction) -> InputBinding {
+        *self.slot(action)
+    }
+
+    /// Swaps with whatever action already used the binding, so nothing ends up bound twice
+    pub fn set(&mut self, action: InputAction, binding: InputBinding) {
+        let previous = self.get(action);
+        if let Some(other) = InputAction::ALL.into_iter().find(|a| *a != action && self.get(*a) == binding) {
+            *self.slot_mut(other) = previous;
+        }
+        *self.slot_mut(action) = binding;
+    }
+
+    fn slot(&self, action: InputAction) -> &InputBinding {
+        match action {
+            InputAction::MoveForward => &self.move_forward,
+            InputAction::MoveBack => &self.move_back,
+            InputAction::MoveLeft => &self.move_left,
+            InputAction::MoveRight => &self.move_right,
+            InputAction::Jump => &self.jump,
+            InputAction::Sprint => &self.sprint,
+            InputAction::Crouch => &self.crouch,
+            InputAction::Fire => &self.fire,
+            InputAction::Reload => &self.reload,
+            InputAction::Chat => &self.chat,
+            InputAction::TeamChat => &self.team_chat,
+            InputAction::Scoreboard => &self.scoreboard,
+            InputAction::ToggleCursor => &self.toggle_cursor,
+            InputAction::ToggleView => &self.toggle_view,
+        }
+    }
+
+    fn slot_mut(&mut self, action: InputAction) -> &mut InputBinding {
+        match action {
+            InputAction::MoveForward => &mut self.move_forward,
+            InputAction::MoveBack => &mut self.move_back,
+            InputAction::MoveLeft => &mut self.move_left,
+            InputAction::MoveRight => &mut self.move_right,
+            InputAction::Jump => &mut self.jump,
+            InputAction::Sprint => &mut self.sprint,
+            InputAction::Crouch => &mut self.crouch,
+            InputAction::Fire => &mut self.fire,
+            InputAction::Reload => &mut self.reload,
+            InputAction::Chat => &mut self.chat,
+            InputAction::TeamChat => &mut self.team_chat,
+            InputAction::Scoreboard => &mut self.scoreboard,
+            InputAction::ToggleCursor => &mut self.toggle_cursor,
+            InputAction::ToggleView => &mut self.toggle_view,
+        }
+    }
+}
+
+/// A gamepad button mapped onto an action, several buttons can share one action
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GamepadBinding {
+    pub action: InputAction,
+    pub button: GamepadButton,
+}
+
+pub fn default_gamepad_bindings() -> Vec<GamepadBinding> {
+    [
+        (InputAction::Jump, GamepadButton::South),
+        (InputAction::Crouch, GamepadButton::East),
+        (InputAction::Reload, GamepadButton::West),
+        (InputAction::Sprint, GamepadButton::LeftThumb),
+        (InputAction::Fire, GamepadButton::RightTrigger2),
+        (InputAction::Scoreboard, GamepadButton::Select),
+        (InputAction::ToggleCursor, GamepadButton::Start),
+        (InputAction::ToggleView, GamepadButton::North),
+        (InputAction::MoveForward, GamepadButton::DPadUp),
+        (InputAction::MoveBack, GamepadButton::DPadDown),
+        (InputAction::MoveLeft, GamepadButton::DPadLeft),
+        (InputAction::MoveRight, GamepadButton::DPadRight),
+    ]
+    .into_iter()
+    .map(|(action, button)| GamepadBinding { action, button })
+    .collect()
+}
+
+/// Reads actions through the player's bindings instead of asking for specific keys or buttons
+#[derive(SystemParam)]
+pub struct Actions<'w, 's> {
+    keys: Res<'w, ButtonInput<KeyCode>>,
+    mouse: Res<'w, ButtonInput<MouseButton>>,
+    gamepads: Query<'w, 's, &'static Gamepad>,
+    settings: Res<'w, ClientSettings>,
+}
+
+impl Actions<'_, '_> {
+    fn gamepad_buttons(&self, action: InputAction) -> impl Iterator<Item = GamepadButton> + '_ {
+        let enabled = self.settings.controller.enabled;
+        self.settings
+            .controller
+            .bindings
+            .iter()
+            .filter(move |b| enabled && b.action == action)
+            .map(|b| b.button)
+    }
+
+    pub fn pressed(&self, action: InputAction) -> bool {
+        let bound = match self.settings.key_bindings.get(action) {
+            InputBinding::Key(key) => self.keys.pressed(key),
+            InputBinding::Mouse(button) => self.mouse.pressed(button),
+        };
+        bound || self.gamepad_buttons(action).any(|b| self.gamepads.iter().any(|g| g.pressed(b)))
+    }
+
+    pub fn just_pressed(&self, action: InputAction) -> bool {
+        let bound = match self.settings.key_bindings.get(action) {
+            InputBinding::Key(key) => self.keys.just_pressed(key),
+            InputBinding::Mouse(button) => self.mouse.just_pressed(button),
+        };
+        bound || self.gamepad_buttons(action).any(|b| self.gamepads.iter().any(|g| g.just_pressed(b)))
+    }
+
+    /// Left stick of the first connected gamepad after the dead zone, zero without one
+    pub fn move_stick(&self) -> Vec2 {
+        match self.gamepads.iter().next() {
+            Some(gamepad) if self.settings.controller.enabled => {
+                apply_dead_zone(gamepad.left_stick(), self.settings.controller.move_dead_zone)
+            }
+            _ => Vec2::ZERO,
+        }
+    }
+
+    /// Right stick of the first connected gamepad after the dead zone, zero without one
+    pub fn look_stick(&self) -> Vec2 {
+        match self.gamepads.iter().next() {
+            Some(gamepad) if self.settings.controller.enabled => {
+                apply_dead_zone(gamepad.right_stick(), self.settings.controller.look_dead_zone)
+            }
+            _ => Vec2::ZERO,
+        }
+    }
+
+    /// Every held action that the server needs to know about
+    pub fn flags(&self) -> ActionFlags {
+        InputAction::ALL
+            .into_iter()
+            .filter(|a| self.pressed(*a))
+            .filter_map(|a| a.flag())
+            .collect()
+    }
+}
@@ -1,17 +1,52 @@
-use avian3d::prelude::Position;
-use bevy::input::ButtonState;
-use bevy::input::keyboard::KeyboardInput;
+use avian3d::prelude::{Collider, LayerMask, Position, ShapeCastConfig, SpatialQueryFilter, SpatialQueryPipeline};
 use bevy::input::mouse::MouseWheel;
-use bevy::prelude::{Camera3d, Component, KeyCode, Local, MessageReader, Quat, Query, Res, Single, Time, Transform, Vec3, Window, With, Without};
+use bevy::prelude::{Camera3d, Children, Component, Dir3, Local, MessageReader, Quat, Query, Res, ResMut, Resource, SceneRoot, Single, State, Time, Transform, Vec3, Visibility, Window, With, Without};
 use bevy::prelude::EulerRot::YXZ;
 use bevy::window::{CursorGrabMode, CursorOptions, PrimaryWindow};
+use crate::components::actions::{Actions, InputAction};
 use crate::components::common::{Id, Vec2};
 use crate::components::player::{PlayerInfo, PlayerMarker, PredictedPlayerState};
+use crate::components::chat::ChatInput;
+use crate::components::menu::MenuState;
+use crate::components::settings::ClientSettings;
+use crate::components::spectator::Spectator;
+use crate::components::CollisionLayer;
 
 const LOOK_SENSITIVITY: (f32, f32) = (0.001, 0.001);
-const CAM_SPACE: f32 = 10.0;
-const CAMERA_HEIGHT: f32 = 0.75;
-const CAMERA_FORWARD: f32 = 0.5;
+pub(crate) const CAMERA_HEIGHT: f32 = 0.75;
+// First person eyes sit on the front of the capsule
+pub(crate) const CAMERA_FORWARD: f32 = 0.5;
+// Third person
+const DEFAULT_ZOOM: f32 = 4.0;
+const CAMERA_RADIUS: f32 = 0.2;
+// How quickly the boom extends again after being pulled in, per second
+const BOOM_SMOOTHING: f32 = 6.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ViewMode {
+    FirstPerson,
+    ThirdPerson,
+}
+
+/// The local player's view, switched at runtime
+#[derive(Resource, Debug)]
+pub struct PlayerCamera {
+    pub view: ViewMode,
+    /// Boom length asked for with the scroll wheel
+    pub zoom: f32,
+    /// Boom length after collision and smoothing
+    boom: f32,
+}
+
+impl Default for PlayerCamera {
+    fn default() -> Self {
+        Self {
+            view: ViewMode::ThirdPerson,
+            zoom: DEFAULT_ZOOM,
+            boom: DEFAULT_ZOOM,
+        }
+    }
+}
 
 #[derive(Component, Default, Debug)]
 pub struct CameraInfo {
@@ -23,59 +58,138 @@ pub fn apply_player_camera_input (
     mouse_delta: Vec2,
     predicted_player_state: &mut PredictedPlayerState,
 ) {
-    predicted_player_state.predicted_yaw += -1.0 * LOOK_SENSITIVITY.0 * mouse_delta.x;
-    predicted_player_state.predicted_pitch += 1.0 * LOOK_SENSITIVITY.1 * mouse_delta.y;
+    apply_look_input(mouse_delta, &mut predicted_player_state.predicted_yaw, &mut predicted_player_state.predicted_pitch);
+}
+
+/// Shared with the server so it turns players the same way they predicted
+pub fn apply_look_input(mouse_delta: Vec2, yaw: &mut f32, pitch: &mut f32) {
+    *yaw += -1.0 * LOOK_SENSITIVITY.0 * mouse_delta.x;
+    *pitch += 1.0 * LOOK_SENSITIVITY.1 * mouse_delta.y;
 
-    predicted_player_state.predicted_pitch = predicted_player_state.predicted_pitch.clamp(-90.0f32.to_radians(), 90.0f32.to_radians());
+    *pitch = pitch.clamp(-90.0f32.to_radians(), 90.0f32.to_radians());
 }
 
 pub(crate) fn camera_controller(
     mut camera: Query<&mut Transform, (With<Camera3d>, Without<PlayerMarker>)>,
-    mut player: Query<(&Id, &Position, &mut PredictedPlayerState), (With<PlayerMarker>, Without<Camera3d>)>,
+    mut player: Query<(&Id, &Position, &mut PredictedPlayerState, &Children), (With<PlayerMarker>, Without<Camera3d>)>,
+    mut player_models: Query<&mut Visibility, With<SceneRoot>>,
     mut mouse_wheel: MessageReader<MouseWheel>,
+    actions: Actions,
+    spatial_query: Res<SpatialQueryPipeline>,
     player_info: Res<PlayerInfo>,
-    mut zoom: Local<f32>
+    settings: Res<ClientSettings>,
+    chat_input: Res<ChatInput>,
+    menu_state: Res<State<MenuState>>,
+    time: Res<Time>,
+    spectator: Res<Spectator>,
+    mut player_camera: ResMut<PlayerCamera>,
 ) {
+    // The spectator camera has it while there's nothing of our own to control
+    if spectator.active() {
+        return;
+    }
+
+    // The wheel scrolls the chat log while it's open
+    if chat_input.active {
+        mouse_wheel.clear();
+    }
     for ev in mouse_wheel.read() {
-        *zoom -= ev.y;
-        *zoom = zoom.clamp(-0.2, 10.0);
+        player_camera.zoom -= ev.y;
+    }
+    player_camera.zoom = player_camera.zoom.clamp(settings.camera.min_zoom, settings.camera.max_zoom);
+
+    // The key is only a view switch while playing, not while typing or rebinding it
+    if actions.just_pressed(InputAction::ToggleView) && !chat_input.active && *menu_state.get() != MenuState::Options {
+        player_camera.view = match player_camera.view {
+            ViewMode::FirstPerson => ViewMode::ThirdPerson,
+            ViewMode::ThirdPerson => ViewMode::FirstPerson,
+        };
     }
-    
-    for (id, position, mut predicted_state) in player.iter_mut() {
-        if *id == player_info.current_player_id {
-            apply_player_camera_input(player_info.mouse_delta.into(), &mut predicted_state);
-
-            for mut cam in camera.iter_mut() {
-                cam.rotation = Quat::from_euler(YXZ, predicted_state.predicted_yaw, -predicted_state.predicted_pitch, 0.0);
-
-                let pivot_shift = position.0 + Vec3::new(0.0, CAMERA_HEIGHT, 0.0);
-                
-                if CAM_SPACE == 0. {
-                    cam.translation = pivot_shift + Vec3::new(0.0, 0.0, *zoom); // 0.0, 0.5, 2.0
+
+    for (id, position, mut predicted_state, children) in player.iter_mut() {
+        if *id != player_info.current_player_id {
+            continue;
+        }
+
+        apply_player_camera_input(player_info.mouse_delta.into(), &mut predicted_state);
+
+        // Our own model would fill the screen in first person
+        let visibility = match player_camera.view {
+            ViewMode::FirstPerson => Visibility::Hidden,
+            ViewMode::ThirdPerson => Visibility::Inherited,
+        };
+        for child in children.iter() {
+            if let Ok(mut model) = player_models.get_mut(child) {
+                model.set_if_neq(visibility);
+            }
+        }
+
+        let rotation = Quat::from_euler(YXZ, predicted_state.predicted_yaw, -predicted_state.predicted_pitch, 0.0);
+        let pivot = position.0 + Vec3::new(0.0, CAMERA_HEIGHT, 0.0);
+
+        let translation = match player_camera.view {
+            ViewMode::FirstPerson => {
+                player_camera.boom = 0.0;
+                pivot + rotation * Vec3::new(0.0, 0.0, -CAMERA_FORWARD)
+            }
+            ViewMode::ThirdPerson => {
+                let target = boom_length(&spatial_query, pivot, rotation, player_camera.zoom);
+                // Pulled in straight away so the camera never ends up inside a wall, eased back out once it's clear
+                player_camera.boom = if target < player_camera.boom {
+                    target
                 } else {
-                    cam.translation = pivot_shift + cam.rotation * Vec3::new(0.0, 0.0, *zoom); // 0.0, 0.5, 2.0
-                }
+                    player_camera.boom + (target - player_camera.boom) * (1.0 - (-BOOM_SMOOTHING * time.delta_secs()).exp())
+                };
+                pivot + rotation * Vec3::new(0.0, 0.0, player_camera.boom)
             }
+        };
+
+        for mut cam in camera.iter_mut() {
+            cam.rotation = rotation;
+            cam.translation = translation;
         }
     }
 }
 
+/// How far the camera can sit behind the pivot before it would hit the level
+pub(crate) fn boom_length(spatial_query: &SpatialQueryPipeline, pivot: Vec3, rotation: Quat, zoom: f32) -> f32 {
+    // In front of the pivot there is nothing to run into
+    if zoom <= 0.0 {
+        return zoom;
+    }
+
+    let players = LayerMask::from(CollisionLayer::Player) | LayerMask::from(CollisionLayer::Enemy);
+    spatial_query
+        .cast_shape(
+            &Collider::sphere(CAMERA_RADIUS),
+            pivot,
+            Quat::IDENTITY,
+            rotation * Dir3::Z,
+            &ShapeCastConfig {
+                max_distance: zoom,
+                target_distance: 0.0,
+                compute_contact_on_penetration: false,
+                ignore_origin_penetration: true,
+            },
+            &SpatialQueryFilter::from_mask(!players),
+        )
+        .map_or(zoom, |hit| hit.distance)
+}
+
 pub fn lock_cursor_system(
     mut cursor_options: Single<&mut CursorOptions>,
-    mut keyboard_input: MessageReader<KeyboardInput>,
+    actions: Actions,
     mut toggle_cursor_lock: Local<bool>,
 ) {
-   for ev in keyboard_input.read() {
-       if ev.state == ButtonState::Pressed && ev.key_code == KeyCode::Tab {
-           if *toggle_cursor_lock {
-               cursor_options.grab_mode = CursorGrabMode::Locked;
-               cursor_options.visible = false;
-           } else {
-               cursor_options.grab_mode = CursorGrabMode::None;
-               cursor_options.visible = true;
-           }
-
-           *toggle_cursor_lock = !*toggle_cursor_lock;
-       }
-   } 
+    if actions.just_pressed(InputAction::ToggleCursor) {
+        if *toggle_cursor_lock {
+            cursor_options.grab_mode = CursorGrabMode::Locked;
+            cursor_options.visible = false;
+        } else {
+            cursor_options.grab_mode = CursorGrabMode::None;
+            cursor_options.visible = true;
+        }
+
+        *toggle_cursor_lock = !*toggle_cursor_lock;
+    }
 }
\ No newline at end of file
@@ -1,136 +1,553 @@
-use crate::components::player::PlayerInfo;
 use crate::network::net_manage::TcpConnection;
 use crate::network::net_message::{NetworkMessage, CTcpType, STcpType};
 use bevy::input::keyboard::{Key, KeyboardInput};
 use bevy::input::ButtonState;
-use bevy::prelude::{Changed, Component, KeyCode, Local, MessageReader, Query, Res, ResMut, Single, Text, With};
+use bevy::input::mouse::MouseWheel;
+use bevy::prelude::{Alpha, ButtonInput, Changed, Children, Color, Commands, Component, DetectChangesMut, Entity, KeyCode, Local, MessageReader, Query, Res, ResMut, Resource, Single, TextColor, TextFont, TextSpan, Time, With};
+use chrono::{Local as LocalTime, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
+use crate::components::actions::InputBinding;
+use crate::components::chat_moderation::MAX_CHAT_MESSAGE_LENGTH;
 use crate::components::common::Id;
+use crate::components::game_mode::Team;
+use crate::components::lobby::Lobby;
+use crate::components::player::{PlayerInfo, PlayerMarker};
+use crate::components::profile::PlayerProfiles;
+use crate::components::settings::ClientSettings;
 
-const CHAT_HISTORY_LEN: usize = 10;
-const MAX_CHAT_MESSAGE_LENGTH: usize = 50;
+pub const CHAT_HISTORY_LEN: usize = 10;
+// How many messages each lobby keeps around for players that join later
+const CHAT_BACKLOG_LEN: usize = 50;
+// Sent lines remembered for Up/Down
+const INPUT_HISTORY_LEN: usize = 20;
+// Seconds without activity before the chat starts fading, and how long the fade takes
+const CHAT_FADE_DELAY: f32 = 8.0;
+const CHAT_FADE_SECONDS: f32 = 2.0;
 
-#[derive(Component)]
+const GLOBAL_CHAT_COLOR: Color = Color::WHITE;
+const TEAM_CHAT_COLOR: Color = Color::srgb(0.45, 0.85, 1.0);
+const DIRECT_CHAT_COLOR: Color = Color::srgb(0.95, 0.55, 0.95);
+const NOTICE_COLOR: Color = Color::srgb(0.95, 0.85, 0.4);
+
+#[derive(Component, Default)]
 pub struct Chat {
-    pub chat_history: VecDeque<(Id, ChatMessage)>,
+    pub chat_history: VecDeque<ChatMessage>,
+    /// Lines only this client sees, like command replies. Always empty on the server.
+    pub notices: VecDeque<String>,
+    /// Id of the newest message already sent to the lobby's members
+    pub last_sent_id: u64,
+}
+
+/// Hands out chat message ids, shared by every lobby so ids only ever go up
+#[derive(Resource, Default)]
+pub struct ChatSequence {
+    last_id: u64,
+}
+
+impl ChatSequence {
+    pub fn next_message(&mut self, sender: Id, sender_name: String, channel: ChatChannel, message: String) -> ChatMessage {
+        self.last_id += 1;
+        ChatMessage {
+            id: self.last_id,
+            timestamp: Utc::now().timestamp_millis(),
+            sender,
+            sender_name,
+            channel,
+            message,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChatChannel {
+    Global,
+    /// Only seen by players on the team the sender was on when they sent it
+    Team(Team),
+    Direct { to: Id },
+}
+
+/// Where a client wants its message to go. The server works out the actual channel.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChatTarget {
+    #[default]
+    All,
+    Team,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ChatMessage {
+    pub id: u64,
+    /// Unix time in milliseconds, set by the server
+    pub timestamp: i64,
+    pub sender: Id,
+    /// Kept with the message so it still reads right after the sender disconnects
+    pub sender_name: String,
+    pub channel: ChatChannel,
     pub message: String,
 }
 
+impl ChatMessage {
+    pub fn visible_to(&self, player_id: Id, team: Team) -> bool {
+        match self.channel {
+            ChatChannel::Global => true,
+            ChatChannel::Team(t) => self.sender == player_id || t == team,
+            ChatChannel::Direct { to } => self.sender == player_id || to == player_id,
+        }
+    }
+}
+
+/// What the player is typing into the chat. Kept as a resource so other input can be ignored while it's open.
+#[derive(Resource, Default)]
+pub struct ChatInput {
+    pub active: bool,
+    pub target: ChatTarget,
+    buffer: String,
+    /// Caret position in characters, not bytes
+    caret: usize,
+    /// Sent lines, oldest first
+    history: Vec<String>,
+    /// Which history entry is shown while browsing with Up/Down
+    history_index: Option<usize>,
+    /// What was typed before browsing history, restored when going past the newest entry
+    draft: String,
+    /// How many lines the log is scrolled up from the newest
+    scroll: usize,
+    /// Time in seconds of the last new line or keypress, the chat fades out some time after it
+    last_activity: f32,
+}
+
+impl ChatInput {
+    pub(crate) fn open(&mut self, target: ChatTarget, initial: &str) {
+        self.active = true;
+        self.target = target;
+        self.buffer = initial.to_string();
+        self.caret = initial.chars().count();
+        self.history_index = None;
+    }
+
+    pub(crate) fn close(&mut self) {
+        self.active = false;
+        self.buffer.clear();
+        self.caret = 0;
+        self.history_index = None;
+        self.scroll = 0;
+    }
+
+    fn byte_index(&self, caret: usize) -> usize {
+        self.buffer.char_indices().nth(caret).map(|(i, _)| i).unwrap_or(self.buffer.len())
+    }
+
+    fn len(&self) -> usize {
+        self.buffer.chars().count()
+    }
+
+    pub(crate) fn insert(&mut self, text: &str) {
+        let room = MAX_CHAT_MESSAGE_LENGTH.saturating_sub(self.len());
+        let text: String = text.chars().filter(|c| !c.is_control()).take(room).collect();
+        let index = self.byte_index(self.caret);
+        self.buffer.insert_str(index, &text);
+        self.caret += text.chars().count();
+    }
+
+    /// Start of the word left of the caret, skipping any spaces right before it
+    fn word_start(&self) -> usize {
+        let chars: Vec<char> = self.buffer.chars().collect();
+        let mut i = self.caret;
+        while i > 0 && chars[i - 1].is_whitespace() {
+            i -= 1;
+        }
+        while i > 0 && !chars[i - 1].is_whitespace() {
+            i -= 1;
+        }
+        i
+    }
+
+    /// End of the word right of the caret, skipping any spaces right after it
+    fn word_end(&self) -> usize {
+        let chars: Vec<char> = self.buffer.chars().collect();
+        let mut i = self.caret;
+        while i < chars.len() && chars[i].is_whitespace() {
+            i += 1;
+        }
+        while i < chars.len() && !chars[i].is_whitespace() {
+            i += 1;
+        }
+        i
+    }
+
+    fn delete_range(&mut self, from: usize, to: usize) {
+        let (from, to) = (self.byte_index(from), self.byte_index(to));
+        self.buffer.replace_range(from..to, "");
+    }
+
+    /// Deletes left of the caret, a whole word with ctrl held
+    pub(crate) fn backspace(&mut self, word: bool) {
+        let from = if word { self.word_start() } else { self.caret.saturating_sub(1) };
+        self.delete_range(from, self.caret);
+        self.caret = from;
+    }
+
+    /// Deletes right of the caret, a whole word with ctrl held
+    pub(crate) fn delete(&mut self, word: bool) {
+        let to = if word { self.word_end() } else { (self.caret + 1).min(self.len()) };
+        self.delete_range(self.caret, to);
+    }
+
+    pub(crate) fn move_left(&mut self, word: bool) {
+        self.caret = if word { self.word_start() } else { self.caret.saturating_sub(1) };
+    }
+
+    pub(crate) fn move_right(&mut self, word: bool) {
+        self.caret = if word { self.word_end() } else { (self.caret + 1).min(self.len()) };
+    }
+
+    pub(crate) fn move_to_start(&mut self) {
+        self.caret = 0;
+    }
+
+    pub(crate) fn move_to_end(&mut self) {
+        self.caret = self.len();
+    }
+
+    pub(crate) fn browse_history(&mut self, older: bool) {
+        if self.history.is_empty() {
+            return;
+        }
+
+        let index = match (self.history_index, older) {
+            (None, true) => {
+                self.draft = self.buffer.clone();
+                Some(self.history.len() - 1)
+            }
+            (None, false) => return,
+            (Some(i), true) => Some(i.saturating_sub(1)),
+            (Some(i), false) if i + 1 < self.history.len() => Some(i + 1),
+            (Some(_), false) => None,
+        };
+
+        self.history_index = index;
+        self.buffer = match index {
+            Some(i) => self.history[i].clone(),
+            None => std::mem::take(&mut self.draft),
+        };
+        self.caret = self.len();
+    }
+
+    /// Takes the typed line out and remembers it for Up/Down
+    pub(crate) fn submit(&mut self) -> String {
+        let line = std::mem::take(&mut self.buffer);
+        if !line.trim().is_empty() && self.history.last() != Some(&line) {
+            if self.history.len() >= INPUT_HISTORY_LEN {
+                self.history.remove(0);
+            }
+            self.history.push(line.clone());
+        }
+        self.close();
+        line
+    }
+
+    pub(crate) fn prompt(&self) -> String {
+        let index = self.byte_index(self.caret);
+        let (before, after) = self.buffer.split_at(index);
+        match self.target {
+            ChatTarget::All => format!("> {}|{}", before, after),
+            ChatTarget::Team => format!("[Team] > {}|{}", before, after),
+        }
+    }
+}
+
+fn paste_from_clipboard() -> Option<String> {
+    arboard::Clipboard::new().and_then(|mut c| c.get_text()).ok()
+}
+
+fn format_chat_line(m: &ChatMessage, player_id: Id, profiles: &PlayerProfiles) -> (String, Color) {
+    let time = LocalTime
+        .timestamp_millis_opt(m.timestamp)
+        .single()
+        .map(|t| t.format("%H:%M").to_string())
+        .unwrap_or_default();
+    let (line, color) = match m.channel {
+        ChatChannel::Global => (format!("{}: {}", m.sender_name, m.message), GLOBAL_CHAT_COLOR),
+        ChatChannel::Team(_) => (format!("[Team] {}: {}", m.sender_name, m.message), TEAM_CHAT_COLOR),
+        ChatChannel::Direct { to } if m.sender == player_id => (
+            format!("[to {}] {}", profiles.display_name(to), m.message),
+            DIRECT_CHAT_COLOR,
+        ),
+        ChatChannel::Direct { .. } => (format!("[from {}] {}", m.sender_name, m.message), DIRECT_CHAT_COLOR),
+    };
+    (format!("{} {}", time, line), color)
+}
+
 pub fn chat_window(
-    player_info: Res<PlayerInfo>,
+    mut commands: Commands,
     mut connection: Single<&mut TcpConnection<CTcpType>>,
+    profiles: Res<PlayerProfiles>,
+    player_info: Res<PlayerInfo>,
+    time: Res<Time>,
+    keys: Res<ButtonInput<KeyCode>>,
     mut keyboard_input: MessageReader<KeyboardInput>,
-    mut message_buffer: Local<String>,
-    mut is_active: Local<bool>,
-    mut chat: Query<(&mut Text, &mut Chat), With<Chat>>,
+    mut mouse_wheel: MessageReader<MouseWheel>,
+    mut input: ResMut<ChatInput>,
+    settings: Res<ClientSettings>,
+    mut rendered: Local<Vec<(String, Color)>>,
+    mut last_log_len: Local<usize>,
+    chat: Query<(Entity, &Chat, &TextFont, Option<&Children>)>,
 ) {
-    let message_full = message_buffer.len() >= MAX_CHAT_MESSAGE_LENGTH;
-    
+    let ctrl = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    let now = time.elapsed_secs();
+
     for k in keyboard_input.read() {
         if k.state == ButtonState::Released {
             continue;
         }
 
-        if *is_active {
-            match &k.logical_key {
-                Key::Backspace => {
-                    message_buffer.pop();
+        if input.active {
+            match (&k.logical_key, k.key_code) {
+                (_, KeyCode::KeyV) if ctrl => {
+                    if let Some(text) = paste_from_clipboard() {
+                        input.insert(&text);
+                    }
                 }
-                Key::Enter => {
-                    if connection.stream.is_some() {
+                (Key::Backspace, _) => input.backspace(ctrl),
+                (Key::Delete, _) => input.delete(ctrl),
+                (Key::ArrowLeft, _) => input.move_left(ctrl),
+                (Key::ArrowRight, _) => input.move_right(ctrl),
+                (Key::Home, _) => input.move_to_start(),
+                (Key::End, _) => input.move_to_end(),
+                (Key::ArrowUp, _) => input.browse_history(true),
+                (Key::ArrowDown, _) => input.browse_history(false),
+                (Key::PageUp, _) => input.scroll += CHAT_HISTORY_LEN / 2,
+                (Key::PageDown, _) => input.scroll = input.scroll.saturating_sub(CHAT_HISTORY_LEN / 2),
+                (Key::Enter, _) => {
+                    let message = input.submit();
+                    if connection.stream.is_some() && !message.trim().is_empty() {
                         connection.add_message(
                             NetworkMessage(CTcpType::ChatMessage {
-                                player_id: player_info.current_player_id,
-                                message: ChatMessage {
-                                    message: message_buffer.clone(),
-                                },
+                                target: input.target,
+                                message,
                         }))
                     }
-                    message_buffer.clear();
-                    *is_active = false;
-                }
-                Key::Character(c) => {
-                    if !message_full { message_buffer.push_str(c.as_str()) }
-                }
-                Key::Space => {
-                    if !message_full { message_buffer.push_str(" ") }
                 }
+                (Key::Escape, _) => input.close(),
+                (Key::Character(c), _) if !ctrl => input.insert(c.as_str()),
+                (Key::Space, _) => input.insert(" "),
                 _ => {}
             }
+            continue;
         }
 
-        match k.key_code {
-            KeyCode::KeyT => {
-                *is_active = true;
-            }
-            KeyCode::Escape => {
-                *is_active = false;
-            }
+        let bindings = &settings.key_bindings;
+        match InputBinding::Key(k.key_code) {
+            key if key == bindings.chat => input.open(ChatTarget::All, ""),
+            key if key == bindings.team_chat => input.open(ChatTarget::Team, ""),
+            // Opens the chat with the command prefix already typed
+            InputBinding::Key(KeyCode::Slash) => input.open(ChatTarget::All, "/"),
             _ => {}
         }
+    }
 
-        // println!("{:?}", message_buffer);
+    if input.active {
+        input.last_activity = now;
+        for wheel in mouse_wheel.read() {
+            let lines = wheel.y.round() as i64;
+            input.scroll = (input.scroll as i64 + lines).max(0) as usize;
+        }
+    } else {
+        mouse_wheel.clear();
     }
 
     // Updates chat window
-    if let Some(mut chat) = chat.single_mut().ok() {
-        chat.0.0.clear();
-        for c in chat.1.chat_history.iter_mut() {
-            chat.0.0.push_str(&format!(
-                "{:?}: {:?}\n",
-                c.0.0.to_string(),
-                c.1.message.to_string()
-            ));
+    let Some((entity, chat, font, children)) = chat.single().ok() else {
+        return;
+    };
+
+    let mut log: Vec<(String, Color)> = chat
+        .chat_history
+        .iter()
+        .map(|m| format_chat_line(m, player_info.current_player_id, &profiles))
+        .collect();
+    log.extend(chat.notices.iter().map(|n| (n.clone(), NOTICE_COLOR)));
+
+    if log.len() != *last_log_len {
+        // Keeps the same lines in view when scrolled up and new ones come in
+        if input.scroll > 0 {
+            input.scroll += log.len().saturating_sub(*last_log_len);
         }
-        if *is_active {
-            chat.0
-                .0
-                .push_str(&format!("{:?}\n", message_buffer.to_string()));
+        *last_log_len = log.len();
+        input.last_activity = now;
+    }
+    input.scroll = input.scroll.min(log.len().saturating_sub(CHAT_HISTORY_LEN));
+
+    let end = log.len() - input.scroll;
+    let start = end.saturating_sub(CHAT_HISTORY_LEN);
+    let mut lines: Vec<(String, Color)> = log[start..end].to_vec();
+    if input.scroll > 0 {
+        lines.push((format!("-- {} more below --", input.scroll), NOTICE_COLOR));
+    }
+
+    // Fades out a while after the last activity, comes back as soon as something happens
+    let alpha = if input.active {
+        1.0
+    } else {
+        let idle = now - input.last_activity;
+        1.0 - ((idle - CHAT_FADE_DELAY) / CHAT_FADE_SECONDS).clamp(0.0, 1.0)
+    };
+    for (_, color) in lines.iter_mut() {
+        *color = color.with_alpha(alpha);
+    }
+
+    if input.active {
+        lines.push((input.prompt(), GLOBAL_CHAT_COLOR));
+    }
+
+    // Lines are rebuilt as coloured spans only when something changed
+    if *rendered == lines && children.is_some() {
+        return;
+    }
+
+    if let Some(children) = children {
+        for child in children.iter() {
+            commands.entity(child).despawn();
         }
     }
+    for (line, color) in lines.iter() {
+        let span = commands
+            .spawn((TextSpan::new(format!("{}\n", line)), font.clone(), TextColor(*color)))
+            .id();
+        commands.entity(entity).add_child(span);
+    }
+    *rendered = lines;
 }
 
+/// Merges new messages into the history. Backlog replies can arrive after newer messages, so it's kept sorted by id.
 pub fn client_add_chat_message(
-    messages: &mut Vec<(Id, ChatMessage)>,
+    messages: &Vec<ChatMessage>,
     chat: &mut Query<&mut Chat>
 ) {
     if let Some(mut chat) = chat.single_mut().ok() {
-        chat.chat_history.clear();
-        while !messages.is_empty() {
-            if chat.chat_history.len() >= CHAT_HISTORY_LEN {
-                chat.chat_history.pop_back();
+        for message in messages.iter() {
+            if chat.chat_history.iter().any(|m| m.id == message.id) {
+                continue;
             }
-            let message = messages.pop().unwrap();
-            chat.chat_history.push_front(message);
+            let index = chat.chat_history.partition_point(|m| m.id < message.id);
+            chat.chat_history.insert(index, message.clone());
+        }
+
+        while chat.chat_history.len() > CHAT_BACKLOG_LEN {
+            chat.chat_history.pop_front();
         }
     }
 }
 
-pub fn server_add_chat_message(message: (Id, ChatMessage), chat: &mut Query<&mut Chat>) {
+pub fn client_clear_chat(chat: &mut Query<&mut Chat>) {
     if let Some(mut chat) = chat.single_mut().ok() {
-        while chat.chat_history.len() >= CHAT_HISTORY_LEN {
-            chat.chat_history.pop_front();
+        chat.chat_history.clear();
+    }
+}
+
+pub fn client_add_notice(
+    message: &str,
+    chat: &mut Query<&mut Chat>
+) {
+    if let Some(mut chat) = chat.single_mut().ok() {
+        for line in message.lines() {
+            if chat.notices.len() >= CHAT_HISTORY_LEN {
+                chat.notices.pop_front();
+            }
+            chat.notices.push_back(line.to_string());
         }
-        if !(message.1.message.len() > MAX_CHAT_MESSAGE_LENGTH) {
-            chat.chat_history.push_back(message);
+    }
+}
+
+/// Works out the channel for a message a player typed and adds it to their lobby's chat
+pub fn handle_chat_message(
+    sender: Id,
+    sender_name: String,
+    sender_team: Team,
+    target: ChatTarget,
+    message: &str,
+    connection: &mut TcpConnection<STcpType>,
+    chat: &mut Chat,
+    sequence: &mut ChatSequence,
+) {
+    let channel = match target {
+        ChatTarget::All => ChatChannel::Global,
+        ChatTarget::Team if sender_team == Team::None => {
+            connection.add_message(NetworkMessage(STcpType::Notice {
+                message: "You aren't on a team".to_string(),
+            }));
+            return;
         }
+        ChatTarget::Team => ChatChannel::Team(sender_team),
+    };
+
+    server_add_chat_message(
+        sequence.next_message(sender, sender_name, channel, message.to_string()),
+        chat,
+    );
+}
+
+pub fn server_add_chat_message(message: ChatMessage, chat: &mut Chat) {
+    while chat.chat_history.len() >= CHAT_BACKLOG_LEN {
+        chat.chat_history.pop_front();
     }
+    if message.message.chars().count() <= MAX_CHAT_MESSAGE_LENGTH {
+        chat.chat_history.push_back(message);
+    }
+}
+
+/// Sends a player joining late the last few messages of their lobby they are allowed to see
+pub fn handle_chat_backlog(
+    count: usize,
+    player_team: Team,
+    connection: &mut TcpConnection<STcpType>,
+    chat: &Chat,
+) {
+    let Some(player_id) = connection.player_id else {
+        return;
+    };
+
+    let visible: Vec<ChatMessage> = chat.chat_history.iter().filter(|m| m.visible_to(player_id, player_team)).cloned().collect();
+    let start = visible.len().saturating_sub(count.min(CHAT_BACKLOG_LEN));
+
+    connection.add_message(NetworkMessage(STcpType::Chat {
+        messages: visible[start..].to_vec(),
+    }));
 }
 
+/// Sends the members of each lobby the messages added since the last send that they are allowed to see
 pub fn send_chat_to_all_connections(
-    chat: Query<&mut Chat, Changed<Chat>>,
+    mut lobbies: Query<(&Lobby, &mut Chat), Changed<Chat>>,
+    players: Query<(&Id, Option<&Team>), With<PlayerMarker>>,
     mut connections: Query<&mut TcpConnection<STcpType>>,
 ) {
-    if let Some(chat) = chat.single().ok() {
+    if lobbies.is_empty() {
+        return;
+    }
+
+    let teams: HashMap<Id, Team> = players.iter().map(|(id, team)| (*id, team.copied().unwrap_or_default())).collect();
+
+    for (lobby, mut chat) in lobbies.iter_mut() {
+        let new_messages: Vec<&ChatMessage> = chat.chat_history.iter().filter(|m| m.id > chat.last_sent_id).collect();
+        let Some(newest) = new_messages.last().map(|m| m.id) else {
+            continue;
+        };
+
         for mut c in connections.iter_mut() {
-            c.add_message(NetworkMessage(STcpType::Chat {
-                messages: Vec::from(chat.chat_history.clone()),
-            }));
+            let Some(player_id) = c.player_id.filter(|id| lobby.members.contains(id)) else {
+                continue;
+            };
+            let team = teams.get(&player_id).copied().unwrap_or_default();
+
+            let messages: Vec<ChatMessage> = new_messages.iter().filter(|m| m.visible_to(player_id, team)).map(|m| (*m).clone()).collect();
+            if messages.is_empty() {
+                continue;
+            }
+            c.add_message(NetworkMessage(STcpType::Chat { messages }));
         }
+
+        // Not a change to the chat itself, so it shouldn't trigger another send
+        chat.bypass_change_detection().last_sent_id = newest;
     }
-}
\ No newline at end of file
+}
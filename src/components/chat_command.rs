@@ -0,0 +1,499 @@
+use bevy::prelude::{Message, MessageReader, MessageWriter, Mut, Query, Res, ResMut, Resource, With};
+use std::time::Duration;
+use crate::components::chat::{server_add_chat_message, Chat, ChatChannel, ChatSequence};
+use crate::components::chat_moderation::ChatModeration;
+use crate::components::common::Id;
+use crate::components::game_mode::{GameModeKind, Match, MatchPhase, Team};
+use crate::components::lobby::{InLobby, Lobby};
+use crate::components::player::PlayerMarker;
+use crate::components::profile::{handle_set_profile, PlayerProfile, PlayerProfiles};
+use crate::config::AdminSettings;
+use crate::network::net_bans::{BanList, BanTarget};
+use crate::network::net_manage::TcpConnection;
+use crate::network::net_message::{NetworkMessage, STcpType};
+
+pub const COMMAND_PREFIX: char = '/';
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PermissionLevel {
+    #[default]
+    Player,
+    Admin,
+}
+
+/// Who gets more than the default level. Connections from the server's own machine are always admins.
+#[derive(Resource, Default)]
+pub struct Permissions {
+    /// Addresses and ranges from the server config
+    pub admins: Vec<BanTarget>,
+}
+
+impl Permissions {
+    pub fn from_settings(settings: &AdminSettings) -> Self {
+        Self {
+            admins: settings.chat_admins.clone(),
+        }
+    }
+
+    pub fn level(&self, connection: &TcpConnection<STcpType>) -> PermissionLevel {
+        match connection.peer_ip() {
+            Some(ip) if ip.is_loopback() || self.admins.iter().any(|a| a.matches_ip(ip)) => PermissionLevel::Admin,
+            _ => PermissionLevel::Player,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ChatCommand {
+    Help { command: Option<String> },
+    Name { name: String },
+    Whisper { target: String, message: String },
+    Kick { target: String, reason: Option<String> },
+    Team { team: Team },
+    Mute { target: String, minutes: Option<u64> },
+    Unmute { target: String },
+}
+
+struct CommandInfo {
+    name: &'static str,
+    usage: &'static str,
+    description: &'static str,
+    permission: PermissionLevel,
+}
+
+const COMMANDS: [CommandInfo; 7] = [
+    CommandInfo {
+        name: "help",
+        usage: "/help [command]",
+        description: "Lists the commands you can use",
+        permission: PermissionLevel::Player,
+    },
+    CommandInfo {
+        name: "name",
+        usage: "/name <name>",
+        description: "Changes your display name",
+        permission: PermissionLevel::Player,
+    },
+    CommandInfo {
+        name: "w",
+        usage: "/w <player> <message>",
+        description: "Sends a message only the given player can see",
+        permission: PermissionLevel::Player,
+    },
+    CommandInfo {
+        name: "kick",
+        usage: "/kick <player> [reason]",
+        description: "Disconnects a player from the server",
+        permission: PermissionLevel::Admin,
+    },
+    CommandInfo {
+        name: "team",
+        usage: "/team <red|blue>",
+        description: "Switches team during warmup",
+        permission: PermissionLevel::Player,
+    },
+    CommandInfo {
+        name: "mute",
+        usage: "/mute <player> [minutes]",
+        description: "Stops a player from chatting, for good if no time is given",
+        permission: PermissionLevel::Admin,
+    },
+    CommandInfo {
+        name: "unmute",
+        usage: "/unmute <player>",
+        description: "Lets a muted player chat again",
+        permission: PermissionLevel::Admin,
+    },
+];
+
+/// A command that passed parsing and permission checks, run by `run_chat_commands`
+#[derive(Message)]
+pub struct CommandIssued {
+    pub sender: Id,
+    pub command: ChatCommand,
+}
+
+pub fn is_command(message: &str) -> bool {
+    message.starts_with(COMMAND_PREFIX)
+}
+
+/// Splits off the first argument, names with spaces can be wrapped in quotes
+fn split_argument(input: &str) -> Option<(String, &str)> {
+    let input = input.trim_start();
+    if input.is_empty() {
+        return None;
+    }
+
+    if let Some(quoted) = input.strip_prefix('"') {
+        let end = quoted.find('"')?;
+        return Some((quoted[..end].to_string(), quoted[end + 1..].trim_start()));
+    }
+
+    match input.split_once(char::is_whitespace) {
+        Some((argument, rest)) => Some((argument.to_string(), rest.trim_start())),
+        None => Some((input.to_string(), "")),
+    }
+}
+
+fn usage_error(name: &str) -> String {
+    match COMMANDS.iter().find(|c| c.name == name) {
+        Some(info) => format!("Usage: {}", info.usage),
+        None => format!("Unknown command: {}", name),
+    }
+}
+
+pub fn parse_command(line: &str) -> Result<ChatCommand, String> {
+    let line = line.trim().trim_start_matches(COMMAND_PREFIX);
+    let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let name = name.to_lowercase();
+    let rest = rest.trim();
+
+    match name.as_str() {
+        "help" | "?" => Ok(ChatCommand::Help {
+            command: split_argument(rest).map(|(c, _)| c.trim_start_matches(COMMAND_PREFIX).to_lowercase()),
+        }),
+        "name" | "nick" => {
+            if rest.is_empty() {
+                return Err(usage_error("name"));
+            }
+            Ok(ChatCommand::Name { name: rest.to_string() })
+        }
+        "w" | "whisper" | "msg" => {
+            let Some((target, message)) = split_argument(rest) else {
+                return Err(usage_error("w"));
+            };
+            if message.is_empty() {
+                return Err(usage_error("w"));
+            }
+            Ok(ChatCommand::Whisper {
+                target,
+                message: message.to_string(),
+            })
+        }
+        "kick" => {
+            let Some((target, reason)) = split_argument(rest) else {
+                return Err(usage_error("kick"));
+            };
+            Ok(ChatCommand::Kick {
+                target,
+                reason: (!reason.is_empty()).then(|| reason.to_string()),
+            })
+        }
+        "team" => {
+            let team = match rest.to_lowercase().as_str() {
+                "red" => Team::Red,
+                "blue" => Team::Blue,
+                _ => return Err(usage_error("team")),
+            };
+            Ok(ChatCommand::Team { team })
+        }
+        "mute" => {
+            let Some((target, minutes)) = split_argument(rest) else {
+                return Err(usage_error("mute"));
+            };
+            let minutes = match minutes {
+                "" => None,
+                m => Some(m.parse::<u64>().map_err(|_| usage_error("mute"))?),
+            };
+            Ok(ChatCommand::Mute { target, minutes })
+        }
+        "unmute" => {
+            let Some((target, _)) = split_argument(rest) else {
+                return Err(usage_error("unmute"));
+            };
+            Ok(ChatCommand::Unmute { target })
+        }
+        _ => Err(format!("Unknown command: /{}. Type /help for a list of commands", name)),
+    }
+}
+
+impl ChatCommand {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChatCommand::Help { .. } => "help",
+            ChatCommand::Name { .. } => "name",
+            ChatCommand::Whisper { .. } => "w",
+            ChatCommand::Kick { .. } => "kick",
+            ChatCommand::Team { .. } => "team",
+            ChatCommand::Mute { .. } => "mute",
+            ChatCommand::Unmute { .. } => "unmute",
+        }
+    }
+}
+
+pub fn required_permission(command: &ChatCommand) -> PermissionLevel {
+    COMMANDS
+        .iter()
+        .find(|c| c.name == command.name())
+        .map(|c| c.permission)
+        .unwrap_or(PermissionLevel::Admin)
+}
+
+pub fn reply(connection: &mut TcpConnection<STcpType>, message: impl Into<String>) {
+    connection.add_message(NetworkMessage(STcpType::Notice {
+        message: message.into(),
+    }));
+}
+
+/// Tells the client why and hangs up once that message went out
+pub fn kick(connection: &mut TcpConnection<STcpType>, reason: String) {
+    connection.add_message(NetworkMessage(STcpType::Kicked { reason }));
+    connection.disconnecting = true;
+}
+
+/// Parses a chat line starting with '/' and queues it if the sender is allowed to run it
+pub fn handle_chat_command(
+    line: &str,
+    connection: &mut TcpConnection<STcpType>,
+    permissions: &Permissions,
+    moderation: &mut ChatModeration,
+    issued: &mut MessageWriter<CommandIssued>,
+) {
+    let Some(sender) = connection.player_id else {
+        return;
+    };
+
+    // Commands share the chat rate limit, admins can still moderate while muted
+    let level = permissions.level(connection);
+    let ip = if level == PermissionLevel::Admin { None } else { connection.peer_ip() };
+    if let Err(e) = moderation.check_command(sender, ip) {
+        reply(connection, e);
+        return;
+    }
+
+    let command = match parse_command(line) {
+        Ok(c) => c,
+        Err(e) => {
+            reply(connection, e);
+            return;
+        }
+    };
+
+    if level < required_permission(&command) {
+        reply(connection, "You don't have permission to use that command");
+        return;
+    }
+
+    issued.write(CommandIssued { sender, command });
+}
+
+/// Looks a player up by display name, falling back to their numeric id
+fn find_player(target: &str, profiles: &PlayerProfiles) -> Option<Id> {
+    profiles
+        .profiles
+        .iter()
+        .find(|(_, p)| p.name.eq_ignore_ascii_case(target))
+        .map(|(id, _)| *id)
+        .or_else(|| {
+            let id = Id(target.parse().ok()?);
+            profiles.profiles.contains_key(&id).then_some(id)
+        })
+}
+
+fn connection_of<'a>(
+    connections: &'a mut Query<&mut TcpConnection<STcpType>>,
+    id: Id,
+) -> Option<Mut<'a, TcpConnection<STcpType>>> {
+    connections.iter_mut().find(|c| c.player_id == Some(id))
+}
+
+pub fn run_chat_commands(
+    mut issued: MessageReader<CommandIssued>,
+    mut connections: Query<&mut TcpConnection<STcpType>>,
+    mut profiles: ResMut<PlayerProfiles>,
+    permissions: Res<Permissions>,
+    mut chat_sequence: ResMut<ChatSequence>,
+    mut moderation: ResMut<ChatModeration>,
+    bans: Res<BanList>,
+    mut lobbies: Query<(&Lobby, &Match, &mut Chat)>,
+    mut players: Query<(&Id, &InLobby, &mut Team), With<PlayerMarker>>,
+) {
+    for CommandIssued { sender, command } in issued.read() {
+        let sender = *sender;
+
+        match command {
+            ChatCommand::Help { command } => {
+                let Some(mut c) = connection_of(&mut connections, sender) else {
+                    continue;
+                };
+                let level = permissions.level(&c);
+
+                let lines: Vec<String> = COMMANDS
+                    .iter()
+                    .filter(|info| info.permission <= level)
+                    .filter(|info| command.as_ref().is_none_or(|name| info.name == name))
+                    .map(|info| format!("{} - {}", info.usage, info.description))
+                    .collect();
+
+                if lines.is_empty() {
+                    reply(&mut c, "No such command");
+                } else {
+                    reply(&mut c, lines.join("\n"));
+                }
+            }
+            ChatCommand::Name { name } => {
+                let Some(mut c) = connection_of(&mut connections, sender) else {
+                    continue;
+                };
+                let color = profiles.profiles.get(&sender).map(|p| p.color).unwrap_or(PlayerProfile::default().color);
+
+                handle_set_profile(&PlayerProfile { name: name.clone(), color }, &mut c, &mut profiles, &bans, &moderation);
+                if c.disconnecting {
+                    continue;
+                }
+                let name = profiles.display_name(sender);
+                reply(&mut c, format!("You are now known as {}", name));
+            }
+            ChatCommand::Whisper { target, message } => {
+                let sender_name = profiles.display_name(sender);
+                let target_id = find_player(target, &profiles);
+
+                match target_id {
+                    Some(target_id) if target_id == sender => {
+                        if let Some(mut c) = connection_of(&mut connections, sender) {
+                            reply(&mut c, "You can't whisper to yourself");
+                        }
+                    }
+                    Some(target_id) => {
+                        let in_lobby = |id: Id| lobbies.iter().any(|(l, _, _)| l.members.contains(&id));
+                        let error = if !in_lobby(sender) {
+                            Some("Join a lobby to send whispers".to_string())
+                        } else if !in_lobby(target_id) {
+                            Some(format!("{} isn't in a lobby", profiles.display_name(target_id)))
+                        } else {
+                            None
+                        };
+                        if let Some(error) = error {
+                            if let Some(mut c) = connection_of(&mut connections, sender) {
+                                reply(&mut c, error);
+                            }
+                            continue;
+                        }
+
+                        let message = match moderation.check_command_text(sender, message) {
+                            Ok(m) => m,
+                            Err(e) => {
+                                if let Some(mut c) = connection_of(&mut connections, sender) {
+                                    reply(&mut c, e);
+                                }
+                                continue;
+                            }
+                        };
+
+                        // Goes into the chat of both players' lobbies, only the two of them can see it
+                        let whisper = chat_sequence.next_message(
+                            sender,
+                            sender_name,
+                            ChatChannel::Direct { to: target_id },
+                            message,
+                        );
+                        for (lobby, _, mut chat) in lobbies.iter_mut() {
+                            if lobby.members.contains(&sender) || lobby.members.contains(&target_id) {
+                                server_add_chat_message(whisper.clone(), &mut chat);
+                            }
+                        }
+                    }
+                    None => {
+                        if let Some(mut c) = connection_of(&mut connections, sender) {
+                            reply(&mut c, format!("No player called {}", target));
+                        }
+                    }
+                }
+            }
+            ChatCommand::Kick { target, reason } => {
+                let target_id = find_player(target, &profiles);
+                let Some(target_id) = target_id.filter(|id| *id != sender) else {
+                    if let Some(mut c) = connection_of(&mut connections, sender) {
+                        reply(&mut c, format!("Can't kick {}", target));
+                    }
+                    continue;
+                };
+
+                let target_name = profiles.display_name(target_id);
+                let reason = reason.clone().unwrap_or_else(|| "Kicked by an admin".to_string());
+
+                if let Some(mut c) = connection_of(&mut connections, target_id) {
+                    kick(&mut c, reason.clone());
+                }
+
+                println!("{:?} kicked {:?}: {}", sender, target_id, reason);
+
+                if let Some(mut c) = connection_of(&mut connections, sender) {
+                    reply(&mut c, format!("Kicked {}", target_name));
+                }
+            }
+            ChatCommand::Mute { target, minutes } => {
+                let target_id = find_player(target, &profiles);
+                let target_connection = target_id.and_then(|id| connection_of(&mut connections, id));
+                let response = match (target_id, target_connection) {
+                    (Some(target_id), Some(mut c)) => match c.peer_ip() {
+                        Some(ip) => {
+                            moderation.mute(ip, minutes.map(|m| Duration::from_secs(m * 60)));
+                            match minutes {
+                                Some(m) => reply(&mut c, format!("You have been muted for {} minute(s)", m)),
+                                None => reply(&mut c, "You have been muted"),
+                            }
+                            println!("{:?} muted {:?} ({}) for {:?} minutes", sender, target_id, ip, minutes);
+                            format!("Muted {}", profiles.display_name(target_id))
+                        }
+                        None => format!("Can't mute {}", profiles.display_name(target_id)),
+                    },
+                    _ => format!("No player called {}", target),
+                };
+                if let Some(mut c) = connection_of(&mut connections, sender) {
+                    reply(&mut c, response);
+                }
+            }
+            ChatCommand::Unmute { target } => {
+                let target_id = find_player(target, &profiles);
+                let target_connection = target_id.and_then(|id| connection_of(&mut connections, id));
+                let response = match (target_id, target_connection) {
+                    (Some(target_id), Some(mut c)) if c.peer_ip().is_some_and(|ip| moderation.unmute(ip)) => {
+                        reply(&mut c, "You can chat again");
+                        format!("Unmuted {}", profiles.display_name(target_id))
+                    }
+                    (Some(target_id), Some(_)) => format!("{} isn't muted", profiles.display_name(target_id)),
+                    _ => format!("No player called {}", target),
+                };
+                if let Some(mut c) = connection_of(&mut connections, sender) {
+                    reply(&mut c, response);
+                }
+            }
+            ChatCommand::Team { team } => {
+                let result = switch_team(sender, *team, &lobbies, &mut players);
+                if let Some(mut c) = connection_of(&mut connections, sender) {
+                    match result {
+                        Ok(()) => reply(&mut c, format!("You joined the {:?} team", team)),
+                        Err(e) => reply(&mut c, e),
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn switch_team(
+    player_id: Id,
+    team: Team,
+    lobbies: &Query<(&Lobby, &Match, &mut Chat)>,
+    players: &mut Query<(&Id, &InLobby, &mut Team), With<PlayerMarker>>,
+) -> Result<(), &'static str> {
+    let Some((_, in_lobby, mut current_team)) = players.iter_mut().find(|p| *p.0 == player_id) else {
+        return Err("You need to be in a lobby to pick a team");
+    };
+
+    let Some((_, game_match, _)) = lobbies.iter().find(|(l, _, _)| l.id == in_lobby.0) else {
+        return Err("You need to be in a lobby to pick a team");
+    };
+
+    if game_match.mode.kind() != GameModeKind::TeamDeathmatch {
+        return Err("This game mode has no teams");
+    }
+
+    if game_match.phase != MatchPhase::Warmup {
+        return Err("You can only switch teams during warmup");
+    }
+
+    *current_team = team;
+    Ok(())
+}
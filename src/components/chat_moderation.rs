@@ -0,0 +1,201 @@
+use bevy::prelude::{info, Res, ResMut, Resource};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::fs;
+use std::time::{Duration, Instant};
+use crate::components::common::Id;
+use crate::config::ServerConfig;
+
+pub const MAX_CHAT_MESSAGE_LENGTH: usize = 50;
+pub const DEFAULT_WORD_FILTER_PATH: &str = "chat_filter.txt";
+
+// Token bucket, a player can send a burst of this many messages...
+const CHAT_BURST: f32 = 5.0;
+// ...and then gets one more every this many seconds
+const CHAT_REFILL_SECONDS: f32 = 1.5;
+// The same message again within this window is dropped
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(10);
+
+struct TokenBucket {
+    tokens: f32,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(now: Instant) -> Self {
+        Self {
+            tokens: CHAT_BURST,
+            last_refill: now,
+        }
+    }
+
+    fn try_take(&mut self, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f32();
+        self.tokens = (self.tokens + elapsed / CHAT_REFILL_SECONDS).min(CHAT_BURST);
+        self.last_refill = now;
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// Server side checks every chat message and whisper goes through before it's accepted
+#[derive(Resource, Default)]
+pub struct ChatModeration {
+    buckets: HashMap<Id, TokenBucket>,
+    last_messages: HashMap<Id, (String, Instant)>,
+    /// Muted addresses and when the mute runs out, None means until unmuted.
+    /// Kept by address like bans, player ids are handed out again on reconnect.
+    muted: HashMap<IpAddr, Option<Instant>>,
+    filtered_words: Vec<String>,
+}
+
+impl ChatModeration {
+    /// Reads one word per line, blank lines and lines starting with '#' are skipped
+    pub fn load_word_filter(&mut self, path: &str) {
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                self.filtered_words = contents
+                    .lines()
+                    .map(|l| l.trim())
+                    .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                    .map(|l| l.to_lowercase())
+                    .collect();
+                info!("Loaded {} filtered words from {}", self.filtered_words.len(), path);
+            }
+            Err(e) => {
+                info!("No chat word filter loaded from {}: {}", path, e);
+                self.filtered_words.clear();
+            }
+        }
+    }
+
+    pub fn mute(&mut self, ip: IpAddr, duration: Option<Duration>) {
+        self.muted.insert(ip, duration.map(|d| Instant::now() + d));
+    }
+
+    pub fn unmute(&mut self, ip: IpAddr) -> bool {
+        self.muted.remove(&ip).is_some()
+    }
+
+    /// Drops the rate limits of a player that disconnected, mutes stay with the address
+    pub fn forget(&mut self, player_id: Id) {
+        self.buckets.remove(&player_id);
+        self.last_messages.remove(&player_id);
+    }
+
+    fn mute_message(&mut self, ip: IpAddr, now: Instant) -> Option<String> {
+        match self.muted.get(&ip) {
+            Some(Some(until)) if *until <= now => {
+                self.muted.remove(&ip);
+                None
+            }
+            Some(Some(until)) => {
+                let minutes = (until.duration_since(now).as_secs() + 59) / 60;
+                Some(format!("You are muted for {} more minute(s)", minutes))
+            }
+            Some(None) => Some("You are muted".to_string()),
+            None => None,
+        }
+    }
+
+    /// Returns the message with filtered words masked, or why it was rejected
+    pub fn check(&mut self, player_id: Id, ip: Option<IpAddr>, message: &str) -> Result<String, String> {
+        let now = Instant::now();
+        let message = message.trim();
+
+        if let Some(reason) = ip.and_then(|ip| self.mute_message(ip, now)) {
+            return Err(reason);
+        }
+        check_length(message)?;
+        self.take_token(player_id, now)?;
+        self.check_repeat(player_id, message, now)?;
+
+        Ok(self.filter_words(message))
+    }
+
+    /// Rate limits chat commands like messages. Pass no address to skip the mute, admins can still moderate while muted.
+    pub fn check_command(&mut self, player_id: Id, ip: Option<IpAddr>) -> Result<(), String> {
+        let now = Instant::now();
+        if let Some(reason) = ip.and_then(|ip| self.mute_message(ip, now)) {
+            return Err(reason);
+        }
+        self.take_token(player_id, now)
+    }
+
+    /// Like check for text that came with a command, which already went through check_command
+    pub fn check_command_text(&mut self, player_id: Id, message: &str) -> Result<String, String> {
+        let message = message.trim();
+        check_length(message)?;
+        self.check_repeat(player_id, message, Instant::now())?;
+
+        Ok(self.filter_words(message))
+    }
+
+    fn take_token(&mut self, player_id: Id, now: Instant) -> Result<(), String> {
+        let bucket = self.buckets.entry(player_id).or_insert_with(|| TokenBucket::new(now));
+        if !bucket.try_take(now) {
+            return Err("You are sending messages too quickly".to_string());
+        }
+        Ok(())
+    }
+
+    fn check_repeat(&mut self, player_id: Id, message: &str, now: Instant) -> Result<(), String> {
+        let normalized = message.to_lowercase();
+        if let Some((last, sent)) = self.last_messages.get(&player_id) {
+            if *last == normalized && now.duration_since(*sent) < DUPLICATE_WINDOW {
+                return Err("You already sent that message".to_string());
+            }
+        }
+        self.last_messages.insert(player_id, (normalized, now));
+        Ok(())
+    }
+
+    /// Masks whole words that appear in the filter list, ignoring case
+    pub fn filter_words(&self, message: &str) -> String {
+        if self.filtered_words.is_empty() {
+            return message.to_string();
+        }
+
+        let mut filtered = String::with_capacity(message.len());
+        let mut word = String::new();
+
+        let flush = |word: &mut String, out: &mut String| {
+            if self.filtered_words.contains(&word.to_lowercase()) {
+                out.extend(std::iter::repeat_n('*', word.chars().count()));
+            } else {
+                out.push_str(word);
+            }
+            word.clear();
+        };
+
+        for c in message.chars() {
+            if c.is_alphanumeric() || c == '\'' {
+                word.push(c);
+            } else {
+                flush(&mut word, &mut filtered);
+                filtered.push(c);
+            }
+        }
+        flush(&mut word, &mut filtered);
+
+        filtered
+    }
+}
+
+fn check_length(message: &str) -> Result<(), String> {
+    if message.is_empty() {
+        return Err("Message is empty".to_string());
+    }
+    if message.chars().count() > MAX_CHAT_MESSAGE_LENGTH {
+        return Err(format!("Messages can be at most {} characters long", MAX_CHAT_MESSAGE_LENGTH));
+    }
+    Ok(())
+}
+
+pub fn load_chat_filter(mut moderation: ResMut<ChatModeration>, config: Res<ServerConfig>) {
+    moderation.load_word_filter(&config.moderation.word_filter_file);
+}
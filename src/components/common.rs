@@ -1,7 +1,8 @@
 use std::any::Any;
 use std::ops::{Add, AddAssign, Sub, SubAssign};
 use approx::ulps_eq;
-use bevy::prelude::{Component, Reflect};
+use bevy::prelude::{Component, Reflect, Resource};
+use std::collections::HashSet;
 use bincode::{Decode, Encode};
 use serde::{Deserialize, Serialize};
 
@@ -9,6 +10,45 @@ use serde::{Deserialize, Serialize};
 #[derive(Eq)]
 pub struct Id(pub u32);
 
+/// Hands out unique player ids. Released ids are only reused once the counter wraps around,
+/// so late packets for a player that left can't be attributed to someone new.
+#[derive(Resource, Debug)]
+pub struct IdPool {
+    next: u32,
+    in_use: HashSet<u32>,
+}
+
+impl Default for IdPool {
+    fn default() -> Self {
+        Self {
+            // Id(0) means "no id yet" on the client
+            next: 1,
+            in_use: HashSet::new(),
+        }
+    }
+}
+
+impl IdPool {
+    pub fn allocate(&mut self) -> Option<Id> {
+        if self.in_use.len() >= (u32::MAX - 1) as usize {
+            return None;
+        }
+
+        while self.next == 0 || self.in_use.contains(&self.next) {
+            self.next = self.next.wrapping_add(1);
+        }
+
+        let id = self.next;
+        self.next = self.next.wrapping_add(1);
+        self.in_use.insert(id);
+        Some(Id(id))
+    }
+
+    pub fn release(&mut self, id: Id) {
+        self.in_use.remove(&id.0);
+    }
+}
+
 #[derive(Component, Serialize, Deserialize, Default, Clone, Copy, Debug)]
 pub struct Vec3 {
     pub x: f32,
@@ -0,0 +1,37 @@
+use crate::components::common::Id;
+use crate::components::game_mode::{GameMode, GameModeKind, MatchSettings, MatchWinner, Team};
+use crate::components::scoreboard::Scoreboard;
+use std::collections::HashMap;
+
+/// Free for all, the first player to reach the score limit wins
+pub struct Deathmatch;
+
+impl GameMode for Deathmatch {
+    fn kind(&self) -> GameModeKind {
+        GameModeKind::Deathmatch
+    }
+
+    fn name(&self) -> &'static str {
+        "Deathmatch"
+    }
+
+    fn assign_team(&self, _teams: &HashMap<Id, Team>) -> Team {
+        Team::None
+    }
+
+    fn winner(&self, scoreboard: &Scoreboard, _teams: &HashMap<Id, Team>, settings: &MatchSettings, time_up: bool) -> Option<MatchWinner> {
+        let entries = scoreboard.sorted_entries();
+        let (leader, leader_entry) = entries.first()?;
+
+        if leader_entry.score >= settings.score_limit {
+            return Some(MatchWinner::Player(*leader));
+        }
+
+        if time_up {
+            let tied = entries.get(1).is_some_and(|(_, second)| second.score == leader_entry.score);
+            return Some(if tied { MatchWinner::Draw } else { MatchWinner::Player(*leader) });
+        }
+
+        None
+    }
+}
@@ -0,0 +1,466 @@
+pub mod deathmatch;
+pub mod team_deathmatch;
+pub mod plugin;
+
+use crate::components::common::Id;
+use crate::components::game_mode::deathmatch::Deathmatch;
+use crate::components::game_mode::team_deathmatch::TeamDeathmatch;
+use crate::components::lobby::{InLobby, Lobby};
+use crate::components::player::controller::MotorState;
+use crate::components::player::{Dead, PendingInputs, PlayerMarker};
+use crate::components::profile::PlayerProfiles;
+use crate::components::scoreboard::Scoreboard;
+use crate::components::weapon::{Health, PlayerKilled};
+use crate::network::net_manage::TcpConnection;
+use crate::network::net_message::{NetworkMessage, STcpType};
+use avian3d::prelude::{ColliderDisabled, LinearVelocity, Position};
+use bevy::prelude::{info, Commands, Component, DetectChanges, Entity, Local, MessageReader, Query, Ref, Res, Text, Time, Vec3, With, Without};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// How often the match clock is resent to clients while nothing else changes
+const MATCH_STATE_SEND_INTERVAL: f32 = 1.0;
+const SPAWN_AREA_HALF_EXTENT: f32 = 15.0;
+const SPAWN_HEIGHT: f32 = 3.0;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MatchPhase {
+    #[default]
+    Warmup,
+    InProgress,
+    PostMatch,
+}
+
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, Default, Hash, PartialEq, Eq)]
+pub enum Team {
+    #[default]
+    None,
+    Red,
+    Blue,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchWinner {
+    Player(Id),
+    Team(Team),
+    Draw,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GameModeKind {
+    #[default]
+    Deathmatch,
+    TeamDeathmatch,
+}
+
+impl GameModeKind {
+    pub fn create(&self) -> Box<dyn GameMode> {
+        match self {
+            GameModeKind::Deathmatch => Box::new(Deathmatch),
+            GameModeKind::TeamDeathmatch => Box::new(TeamDeathmatch),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MatchSettings {
+    pub score_limit: i32,
+    /// Length of a match in seconds
+    pub time_limit: f32,
+    pub warmup_time: f32,
+    pub post_match_time: f32,
+    /// Seconds a killed player waits before spawning again
+    pub respawn_time: f32,
+    pub min_players: usize,
+    pub friendly_fire: bool,
+}
+
+impl Default for MatchSettings {
+    fn default() -> Self {
+        Self {
+            score_limit: 20,
+            time_limit: 600.0,
+            warmup_time: 30.0,
+            post_match_time: 10.0,
+            respawn_time: 5.0,
+            min_players: 2,
+            friendly_fire: false,
+        }
+    }
+}
+
+/// Rules of a game mode. The match state machine is shared, a mode only decides how teams are
+/// formed, who may damage whom and when the match has been won.
+pub trait GameMode: Send + Sync {
+    fn kind(&self) -> GameModeKind;
+
+    fn name(&self) -> &'static str;
+
+    /// Picks the team for a player joining with the current assignments
+    fn assign_team(&self, teams: &HashMap<Id, Team>) -> Team;
+
+    /// Returns the players that have to switch teams to even out the teams
+    fn rebalance(&self, _teams: &HashMap<Id, Team>) -> Vec<(Id, Team)> {
+        Vec::new()
+    }
+
+    fn can_damage(&self, attacker: Team, victim: Team, settings: &MatchSettings) -> bool {
+        attacker == Team::None || attacker != victim || settings.friendly_fire
+    }
+
+    fn team_scores(&self, _scoreboard: &Scoreboard, _teams: &HashMap<Id, Team>) -> Vec<(Team, i32)> {
+        Vec::new()
+    }
+
+    /// Returns a winner once the score limit is reached, or the leader when the time runs out
+    fn winner(&self, scoreboard: &Scoreboard, teams: &HashMap<Id, Team>, settings: &MatchSettings, time_up: bool) -> Option<MatchWinner>;
+}
+
+#[derive(Component)]
+pub struct Match {
+    pub mode: Box<dyn GameMode>,
+    pub settings: MatchSettings,
+    pub phase: MatchPhase,
+    pub time_remaining: f32,
+    pub winner: Option<MatchWinner>,
+}
+
+/// The part of a match the clients need for their HUD
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct MatchState {
+    pub mode: GameModeKind,
+    pub phase: MatchPhase,
+    pub time_remaining: f32,
+    pub score_limit: i32,
+    pub team_scores: Vec<(Team, i32)>,
+    pub teams: HashMap<Id, Team>,
+    pub winner: Option<MatchWinner>,
+}
+
+/// Client copy of the replicated match state, counted down locally between updates
+#[derive(Component, Default)]
+pub struct MatchStatus {
+    pub state: MatchState,
+}
+
+impl Match {
+    pub fn new(mode: Box<dyn GameMode>, settings: MatchSettings) -> Self {
+        Self {
+            mode,
+            settings,
+            phase: MatchPhase::Warmup,
+            time_remaining: settings.warmup_time,
+            winner: None,
+        }
+    }
+
+    fn set_phase(&mut self, phase: MatchPhase) {
+        info!("Match phase {:?} -> {:?}", self.phase, phase);
+        self.phase = phase;
+        self.time_remaining = match phase {
+            MatchPhase::Warmup => self.settings.warmup_time,
+            MatchPhase::InProgress => self.settings.time_limit,
+            MatchPhase::PostMatch => self.settings.post_match_time,
+        };
+        if phase != MatchPhase::PostMatch {
+            self.winner = None;
+        }
+    }
+
+    /// Starts over from warmup, used when the lobby's map changes
+    pub fn restart(&mut self) {
+        self.set_phase(MatchPhase::Warmup);
+    }
+
+    pub fn state(&self, scoreboard: &Scoreboard, teams: &HashMap<Id, Team>) -> MatchState {
+        MatchState {
+            mode: self.mode.kind(),
+            phase: self.phase,
+            time_remaining: self.time_remaining,
+            score_limit: self.settings.score_limit,
+            team_scores: self.mode.team_scores(scoreboard, teams),
+            teams: teams.clone(),
+            winner: self.winner,
+        }
+    }
+}
+
+pub fn spawn_position() -> Vec3 {
+    let mut rng = rand::rng();
+    Vec3::new(
+        rng.random_range(-SPAWN_AREA_HALF_EXTENT..SPAWN_AREA_HALF_EXTENT),
+        SPAWN_HEIGHT,
+        rng.random_range(-SPAWN_AREA_HALF_EXTENT..SPAWN_AREA_HALF_EXTENT),
+    )
+}
+
+pub fn assign_teams(
+    mut commands: Commands,
+    lobbies: Query<(&Lobby, &Match)>,
+    new_players: Query<(Entity, &Id, &InLobby), (With<PlayerMarker>, Without<Team>)>,
+    players: Query<(&Id, &Team, &InLobby), With<PlayerMarker>>,
+) {
+    for (lobby, game_match) in lobbies.iter() {
+        let mut teams: HashMap<Id, Team> = players
+            .iter()
+            .filter(|p| p.2.0 == lobby.id)
+            .map(|(id, team, _)| (*id, *team))
+            .collect();
+
+        for (entity, id, in_lobby) in new_players.iter() {
+            if in_lobby.0 != lobby.id {
+                continue;
+            }
+            let team = game_match.mode.assign_team(&teams);
+            teams.insert(*id, team);
+            commands.entity(entity).insert(team);
+        }
+    }
+}
+
+pub fn update_match(
+    mut commands: Commands,
+    mut lobbies: Query<(&mut Lobby, &mut Match, &mut Scoreboard)>,
+    mut players: Query<(Entity, &Id, &InLobby, &mut Team, &mut Health, &mut Position, &mut LinearVelocity, &mut MotorState), With<PlayerMarker>>,
+    mut connections: Query<&mut TcpConnection<STcpType>>,
+    time: Res<Time>,
+) {
+    for (mut lobby, mut game_match, mut scoreboard) in lobbies.iter_mut() {
+        game_match.time_remaining = (game_match.time_remaining - time.delta_secs()).max(0.0);
+        let time_up = game_match.time_remaining <= 0.0;
+
+        let teams: HashMap<Id, Team> = players
+            .iter()
+            .filter(|p| p.2.0 == lobby.id)
+            .map(|p| (*p.1, *p.3))
+            .collect();
+
+        match game_match.phase {
+            MatchPhase::Warmup => {
+                let enough_players = teams.len() >= game_match.settings.min_players;
+                // Everyone toggling ready in the lobby screen skips the rest of the warmup
+                if !time_up && !(enough_players && lobby.all_ready()) {
+                    continue;
+                }
+                if !enough_players {
+                    // Keep warming up until enough players are around
+                    game_match.time_remaining = game_match.settings.warmup_time;
+                    continue;
+                }
+
+                let moves = game_match.mode.rebalance(&teams);
+
+                for (entity, id, in_lobby, mut team, mut health, mut position, mut linear_velocity, mut motor) in players.iter_mut() {
+                    if in_lobby.0 != lobby.id {
+                        continue;
+                    }
+                    if let Some((_, new_team)) = moves.iter().find(|(moved, _)| moved == id) {
+                        *team = *new_team;
+                    }
+                    respawn_player(entity, *id, &mut health, &mut position, &mut linear_velocity, &mut motor, &mut connections, &mut commands);
+                }
+
+                for entry in scoreboard.entries.values_mut() {
+                    let ping = entry.ping;
+                    *entry = Default::default();
+                    entry.ping = ping;
+                }
+
+                game_match.set_phase(MatchPhase::InProgress);
+            }
+            MatchPhase::InProgress => {
+                let settings = game_match.settings;
+                if let Some(winner) = game_match.mode.winner(&scoreboard, &teams, &settings, time_up) {
+                    info!("Match in lobby {:?} won by {:?}", lobby.id, winner);
+                    game_match.set_phase(MatchPhase::PostMatch);
+                    game_match.winner = Some(winner);
+                }
+            }
+            MatchPhase::PostMatch => {
+                if time_up {
+                    lobby.ready.clear();
+                    game_match.set_phase(MatchPhase::Warmup);
+                }
+            }
+        }
+    }
+}
+
+/// Scores kills and takes the victims out until their respawn time is up, they spectate meanwhile
+pub fn apply_kills(
+    mut commands: Commands,
+    mut kills: MessageReader<PlayerKilled>,
+    mut lobbies: Query<(&Lobby, &Match, &mut Scoreboard)>,
+    mut players: Query<(Entity, &Id, &mut Health, &mut LinearVelocity), (With<PlayerMarker>, Without<Dead>)>,
+    mut connections: Query<&mut TcpConnection<STcpType>>,
+) {
+    for kill in kills.read() {
+        let Some((lobby, game_match, mut scoreboard)) = lobbies
+            .iter_mut()
+            .find(|(l, _, _)| l.members.contains(&kill.victim))
+        else {
+            continue;
+        };
+
+        // Kills during warmup respawn the victim but are not scored
+        if game_match.phase == MatchPhase::InProgress {
+            scoreboard.record_kill(kill.killer, kill.victim);
+        }
+
+        for mut c in connections.iter_mut() {
+            if !c.player_id.is_some_and(|id| lobby.members.contains(&id)) {
+                continue;
+            }
+            c.add_message(NetworkMessage(STcpType::PlayerKilled {
+                killer: kill.killer,
+                victim: kill.victim,
+                respawn_time: game_match.settings.respawn_time,
+            }));
+        }
+
+        for (entity, id, mut health, mut linear_velocity) in players.iter_mut() {
+            if *id == kill.victim {
+                health.current = 0;
+                linear_velocity.0 = Vec3::ZERO;
+                commands.entity(entity).insert((Dead { respawn_in: game_match.settings.respawn_time }, ColliderDisabled));
+            }
+        }
+    }
+}
+
+/// Brings killed players back once their respawn time is up
+pub fn respawn_dead_players(
+    mut commands: Commands,
+    mut players: Query<(Entity, &Id, &mut Dead, &mut Health, &mut Position, &mut LinearVelocity, &mut MotorState, &mut PendingInputs), With<PlayerMarker>>,
+    mut connections: Query<&mut TcpConnection<STcpType>>,
+    time: Res<Time>,
+) {
+    for (entity, id, mut dead, mut health, mut position, mut linear_velocity, mut motor, mut pending) in players.iter_mut() {
+        dead.respawn_in -= time.delta_secs();
+        if dead.respawn_in > 0.0 {
+            continue;
+        }
+
+        // Whatever was held while spectating shouldn't carry over into the new life
+        *pending = PendingInputs::default();
+        respawn_player(entity, *id, &mut health, &mut position, &mut linear_velocity, &mut motor, &mut connections, &mut commands);
+    }
+}
+
+fn respawn_player(
+    entity: Entity,
+    id: Id,
+    health: &mut Health,
+    position: &mut Position,
+    linear_velocity: &mut LinearVelocity,
+    motor: &mut MotorState,
+    connections: &mut Query<&mut TcpConnection<STcpType>>,
+    commands: &mut Commands,
+) {
+    let spawn = spawn_position();
+
+    commands.entity(entity).remove::<(Dead, ColliderDisabled)>();
+
+    health.current = health.max;
+    position.0 = spawn;
+    linear_velocity.0 = Vec3::ZERO;
+    // Crouching is kept so it still matches the collider, letting go of the key stands up as usual
+    *motor = MotorState { crouching: motor.crouching, ..Default::default() };
+
+    for mut c in connections.iter_mut() {
+        if c.player_id == Some(id) {
+            c.add_message(NetworkMessage(STcpType::Respawn {
+                position: spawn.into(),
+            }));
+        }
+    }
+}
+
+pub fn send_match_state(
+    lobbies: Query<(&Lobby, &Match, Ref<Scoreboard>)>,
+    players: Query<(&Id, &InLobby, Ref<Team>), With<PlayerMarker>>,
+    mut connections: Query<&mut TcpConnection<STcpType>>,
+    time: Res<Time>,
+    mut last_phases: Local<HashMap<Id, MatchPhase>>,
+    mut since_last_send: Local<f32>,
+) {
+    *since_last_send += time.delta_secs();
+    let resync = *since_last_send >= MATCH_STATE_SEND_INTERVAL;
+    if resync {
+        *since_last_send = 0.0;
+    }
+
+    last_phases.retain(|id, _| lobbies.iter().any(|(l, _, _)| l.id == *id));
+
+    for (lobby, game_match, scoreboard) in lobbies.iter() {
+        // The clock alone only needs an occasional resync since clients count it down themselves
+        let phase_changed = last_phases.get(&lobby.id) != Some(&game_match.phase);
+        let teams_changed = players.iter().any(|p| p.1.0 == lobby.id && p.2.is_changed());
+        let score_changed = scoreboard.is_changed();
+        if !phase_changed && !teams_changed && !score_changed && !resync {
+            continue;
+        }
+
+        last_phases.insert(lobby.id, game_match.phase);
+
+        let teams: HashMap<Id, Team> = players
+            .iter()
+            .filter(|p| p.1.0 == lobby.id)
+            .map(|(id, _, team)| (*id, *team))
+            .collect();
+        let state = game_match.state(&scoreboard, &teams);
+
+        for mut c in connections.iter_mut() {
+            if !c.player_id.is_some_and(|id| lobby.members.contains(&id)) {
+                continue;
+            }
+            c.add_message(NetworkMessage(STcpType::MatchState {
+                state: state.clone(),
+            }));
+        }
+    }
+}
+
+pub fn client_set_match_state(
+    state: &MatchState,
+    match_status: &mut Query<&mut MatchStatus>,
+) {
+    if let Some(mut match_status) = match_status.single_mut().ok() {
+        match_status.state = state.clone();
+    }
+}
+
+pub fn match_hud(
+    mut match_status: Query<(&mut Text, &mut MatchStatus)>,
+    profiles: Res<PlayerProfiles>,
+    time: Res<Time>,
+) {
+    let Some((mut text, mut match_status)) = match_status.single_mut().ok() else {
+        return;
+    };
+
+    let state = &mut match_status.state;
+    state.time_remaining = (state.time_remaining - time.delta_secs()).max(0.0);
+
+    let seconds = state.time_remaining.ceil() as u32;
+    let clock = format!("{}:{:02}", seconds / 60, seconds % 60);
+
+    text.0.clear();
+    match state.phase {
+        MatchPhase::Warmup => text.0.push_str(&format!("Warmup {}", clock)),
+        MatchPhase::InProgress => text.0.push_str(&format!("{:?} {}", state.mode, clock)),
+        MatchPhase::PostMatch => {
+            let winner = match state.winner {
+                Some(MatchWinner::Player(id)) => format!("{} wins", profiles.display_name(id)),
+                Some(MatchWinner::Team(team)) => format!("{:?} team wins", team),
+                Some(MatchWinner::Draw) | None => "Draw".to_string(),
+            };
+            text.0.push_str(&format!("{} - next match in {}", winner, clock));
+        }
+    }
+
+    for (team, score) in state.team_scores.iter() {
+        text.0.push_str(&format!("\n{:?}: {} / {}", team, score, state.score_limit));
+    }
+}
@@ -0,0 +1,23 @@
+use bevy::app::{App, FixedUpdate, Plugin};
+use bevy::prelude::IntoScheduleConfigs;
+use crate::components::game_mode::{apply_kills, assign_teams, respawn_dead_players, send_match_state, update_match};
+use crate::components::weapon::PlayerKilled;
+
+/// Server side match flow. Clients only receive the resulting `MatchState`.
+pub struct GameModePlugin;
+
+impl Plugin for GameModePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_message::<PlayerKilled>();
+        app.add_systems(
+            FixedUpdate,
+            (
+                assign_teams,
+                apply_kills,
+                respawn_dead_players,
+                update_match,
+                send_match_state,
+            ).chain()
+        );
+    }
+}
@@ -0,0 +1,74 @@
+use crate::components::common::Id;
+use crate::components::game_mode::{GameMode, GameModeKind, MatchSettings, MatchWinner, Team};
+use crate::components::scoreboard::Scoreboard;
+use std::collections::HashMap;
+
+/// Red against blue, the kills of every team member count towards the team score
+pub struct TeamDeathmatch;
+
+fn team_sizes(teams: &HashMap<Id, Team>) -> (usize, usize) {
+    let red = teams.values().filter(|t| **t == Team::Red).count();
+    let blue = teams.values().filter(|t| **t == Team::Blue).count();
+    (red, blue)
+}
+
+impl GameMode for TeamDeathmatch {
+    fn kind(&self) -> GameModeKind {
+        GameModeKind::TeamDeathmatch
+    }
+
+    fn name(&self) -> &'static str {
+        "Team Deathmatch"
+    }
+
+    fn assign_team(&self, teams: &HashMap<Id, Team>) -> Team {
+        let (red, blue) = team_sizes(teams);
+        if blue < red { Team::Blue } else { Team::Red }
+    }
+
+    fn rebalance(&self, teams: &HashMap<Id, Team>) -> Vec<(Id, Team)> {
+        let (red, blue) = team_sizes(teams);
+        let (from, to, surplus) = if red > blue + 1 {
+            (Team::Red, Team::Blue, (red - blue) / 2)
+        } else if blue > red + 1 {
+            (Team::Blue, Team::Red, (blue - red) / 2)
+        } else {
+            return Vec::new();
+        };
+
+        // Sorted so the same players get picked when rebalancing the same teams
+        let mut candidates: Vec<Id> = teams.iter().filter(|(_, t)| **t == from).map(|(id, _)| *id).collect();
+        candidates.sort_by(|a, b| b.0.cmp(&a.0));
+        candidates.into_iter().take(surplus).map(|id| (id, to)).collect()
+    }
+
+    fn team_scores(&self, scoreboard: &Scoreboard, teams: &HashMap<Id, Team>) -> Vec<(Team, i32)> {
+        let mut red = 0;
+        let mut blue = 0;
+        for (id, entry) in scoreboard.entries.iter() {
+            match teams.get(id) {
+                Some(Team::Red) => red += entry.score,
+                Some(Team::Blue) => blue += entry.score,
+                _ => {}
+            }
+        }
+        vec![(Team::Red, red), (Team::Blue, blue)]
+    }
+
+    fn winner(&self, scoreboard: &Scoreboard, teams: &HashMap<Id, Team>, settings: &MatchSettings, time_up: bool) -> Option<MatchWinner> {
+        let scores = self.team_scores(scoreboard, teams);
+        let (red, blue) = (scores[0].1, scores[1].1);
+
+        if red >= settings.score_limit || blue >= settings.score_limit || time_up {
+            return Some(if red > blue {
+                MatchWinner::Team(Team::Red)
+            } else if blue > red {
+                MatchWinner::Team(Team::Blue)
+            } else {
+                MatchWinner::Draw
+            });
+        }
+
+        None
+    }
+}
@@ -1,4 +1,55 @@
-use bevy::prelude::Component;
+use bevy::prelude::{Component, Query, Res, Text, Time};
+use std::collections::VecDeque;
+use crate::components::common::Id;
+use crate::components::profile::PlayerProfiles;
+
+const KILL_FEED_LEN: usize = 5;
+const KILL_FEED_DURATION: f32 = 6.0;
 
 #[derive(Component)]
 pub struct Hud;
+
+#[derive(Component, Default)]
+pub struct KillFeed {
+    /// Killer, victim and the seconds the entry has been shown
+    pub entries: VecDeque<(Id, Id, f32)>,
+}
+
+pub fn client_add_kill(
+    killer: Id,
+    victim: Id,
+    kill_feed: &mut Query<&mut KillFeed>,
+) {
+    if let Some(mut kill_feed) = kill_feed.single_mut().ok() {
+        if kill_feed.entries.len() >= KILL_FEED_LEN {
+            kill_feed.entries.pop_front();
+        }
+        kill_feed.entries.push_back((killer, victim, 0.0));
+    }
+}
+
+pub fn kill_feed_window(
+    mut kill_feed: Query<(&mut Text, &mut KillFeed)>,
+    profiles: Res<PlayerProfiles>,
+    time: Res<Time>,
+) {
+    if let Some((mut text, mut kill_feed)) = kill_feed.single_mut().ok() {
+        for entry in kill_feed.entries.iter_mut() {
+            entry.2 += time.delta_secs();
+        }
+        kill_feed.entries.retain(|e| e.2 < KILL_FEED_DURATION);
+
+        text.0.clear();
+        for (killer, victim, _) in kill_feed.entries.iter() {
+            if killer == victim {
+                text.0.push_str(&format!("{} died\n", profiles.display_name(*victim)));
+            } else {
+                text.0.push_str(&format!(
+                    "{} killed {}\n",
+                    profiles.display_name(*killer),
+                    profiles.display_name(*victim)
+                ));
+            }
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use bevy::prelude::{Camera3d, Children, Quat, Query, Res, ResMut, Resource, SceneRoot, Single, Time, Transform, Vec3, Visibility, With, Without};
+use bevy::prelude::EulerRot::YXZ;
+use std::collections::{HashMap, VecDeque};
+use crate::components::camera::{CAMERA_FORWARD, CAMERA_HEIGHT};
+use crate::components::common::Id;
+use crate::components::player::{PlayerInfo, PlayerMarker, PlayerState};
+
+/// Seconds of snapshots kept around
+const HISTORY_LENGTH: f32 = 5.0;
+/// Seconds before the death that get replayed
+const KILLCAM_LENGTH: f32 = 3.0;
+
+#[derive(Clone)]
+struct Snapshot {
+    /// When it arrived, in seconds since startup
+    time: f32,
+    players: HashMap<Id, PlayerState>,
+}
+
+/// Client side. Keeps the last few seconds of snapshots and plays them back from the killer's eyes after we die.
+#[derive(Resource, Default)]
+pub struct Killcam {
+    history: VecDeque<Snapshot>,
+    replay: Vec<Snapshot>,
+    killer: Option<Id>,
+    /// Seconds into the replay
+    elapsed: f32,
+}
+
+impl Killcam {
+    pub fn playing(&self) -> bool {
+        self.killer.is_some()
+    }
+
+    pub fn killer(&self) -> Option<Id> {
+        self.killer
+    }
+}
+
+pub fn client_record_snapshot(players: &HashMap<Id, PlayerState>, time: f32, killcam: &mut Killcam) {
+    killcam.history.push_back(Snapshot {
+        time,
+        players: players.clone(),
+    });
+    while killcam.history.front().is_some_and(|s| time - s.time > HISTORY_LENGTH) {
+        killcam.history.pop_front();
+    }
+}
+
+/// Starts the replay when we were the victim, there's nothing to show for a suicide
+pub fn client_start_killcam(killer: Id, victim: Id, player_info: &PlayerInfo, killcam: &mut Killcam) {
+    if victim != player_info.current_player_id || killer == victim {
+        return;
+    }
+    let Some(now) = killcam.history.back().map(|s| s.time) else {
+        return;
+    };
+
+    let replay: Vec<Snapshot> = killcam
+        .history
+        .iter()
+        .filter(|s| now - s.time <= KILLCAM_LENGTH && s.players.contains_key(&killer))
+        .cloned()
+        .collect();
+    if replay.len() < 2 {
+        return;
+    }
+
+    killcam.replay = replay;
+    killcam.killer = Some(killer);
+    killcam.elapsed = 0.0;
+}
+
+/// Ends the replay early, the respawn doesn't wait for it
+pub fn client_stop_killcam(killcam: &mut Killcam) {
+    killcam.killer = None;
+    killcam.replay.clear();
+}
+
+/// Snapshots from another lobby or an earlier life aren't worth replaying
+pub fn client_clear_killcam(killcam: &mut Killcam) {
+    client_stop_killcam(killcam);
+    killcam.history.clear();
+}
+
+/// Where a player was a fraction of the way between two snapshots
+fn interpolate(from: &Snapshot, to: &Snapshot, id: Id, t: f32) -> Option<PlayerState> {
+    let a = from.players.get(&id)?;
+    let b = to.players.get(&id).unwrap_or(a);
+
+    Some(PlayerState {
+        position: a.position.lerp(b.position, t),
+        linear_velocity: a.linear_velocity.lerp(b.linear_velocity, t),
+        // Yaw keeps counting past a full turn, so there's no wrap around to take care of
+        yaw: a.yaw + (b.yaw - a.yaw) * t,
+        pitch: a.pitch + (b.pitch - a.pitch) * t,
+        ..*b
+    })
+}
+
+/// Moves everyone back to where they were and puts the camera in the killer's eyes.
+/// Runs after the snapshots have been applied so the replay wins until it's over.
+pub fn play_killcam(
+    mut killcam: ResMut<Killcam>,
+    mut camera: Single<&mut Transform, (With<Camera3d>, Without<PlayerMarker>)>,
+    mut players: Query<(&Id, &mut Transform, &Children), (With<PlayerMarker>, Without<Camera3d>)>,
+    mut player_models: Query<&mut Visibility, With<SceneRoot>>,
+    player_info: Res<PlayerInfo>,
+    time: Res<Time>,
+) {
+    if killcam.playing() {
+        killcam.elapsed += time.delta_secs();
+        let start = killcam.replay.first().map_or(0.0, |s| s.time);
+        let end = killcam.replay.last().map_or(0.0, |s| s.time);
+        if start + killcam.elapsed > end {
+            client_stop_killcam(&mut killcam);
+        }
+    }
+
+    let frames = killcam.playing().then(|| replay_frames(&killcam));
+
+    for (id, mut transform, children) in players.iter_mut() {
+        // Our own model is the camera controller's business, except in the replay that's about us
+        if *id == player_info.current_player_id && !killcam.playing() {
+            continue;
+        }
+
+        // The killer's model would be in the way of their view. Ours shows even if we died in first person.
+        let visibility = if killcam.killer == Some(*id) { Visibility::Hidden } else { Visibility::Inherited };
+        for child in children.iter() {
+            if let Ok(mut model) = player_models.get_mut(child) {
+                model.set_if_neq(visibility);
+            }
+        }
+
+        if let Some(state) = frames.and_then(|(from, to, t)| interpolate(from, to, *id, t)) {
+            transform.translation = state.position;
+            transform.rotation = Quat::from_euler(YXZ, state.yaw, 0.0, 0.0);
+        }
+    }
+
+    let Some(killer) = killcam.killer else {
+        return;
+    };
+    if let Some(state) = frames.and_then(|(from, to, t)| interpolate(from, to, killer, t)) {
+        let rotation = Quat::from_euler(YXZ, state.yaw, -state.pitch, 0.0);
+        camera.rotation = rotation;
+        camera.translation = state.position + Vec3::new(0.0, CAMERA_HEIGHT, 0.0) + rotation * Vec3::new(0.0, 0.0, -CAMERA_FORWARD);
+    }
+}
+
+/// The two snapshots around the current replay time and how far between them it is
+fn replay_frames(killcam: &Killcam) -> (&Snapshot, &Snapshot, f32) {
+    let replay = &killcam.replay;
+    let time = replay[0].time + killcam.elapsed;
+    let index = replay.iter().rposition(|s| s.time <= time).unwrap_or(0);
+    let from = &replay[index];
+    let to = &replay[(index + 1).min(replay.len() - 1)];
+
+    let t = if to.time > from.time { ((time - from.time) / (to.time - from.time)).clamp(0.0, 1.0) } else { 0.0 };
+    (from, to, t)
+}
@@ -1,70 +1,491 @@
-use rand::Rng;
 use avian3d::prelude::{Collider, Friction, LinearVelocity, LockedAxes, RigidBody};
-use bevy::input::ButtonState;
-use bevy::input::keyboard::KeyboardInput;
-use bevy::prelude::{Commands, KeyCode, MessageReader, Single, Transform};
+use bevy::prelude::{info, Changed, Commands, Component, Entity, Or, Query, ResMut, Resource, Transform, With, Without};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use crate::components::camera::CameraInfo;
-use crate::components::common::Id;
-use crate::components::player::animation::{AnimationState, PlayerAnimationState};
+use crate::components::chat::Chat;
+use crate::components::chat_moderation::ChatModeration;
+use crate::components::common::{Id, IdPool};
+use crate::components::game_mode::{spawn_position, GameModeKind, Match, MatchPhase};
+use crate::config::GameplaySettings;
+use crate::components::player::animation::PlayerAnimationState;
+use crate::components::player::controller::{player_collider, MotorState};
 use crate::components::player::{PendingInputs, PlayerMarker};
-use crate::network::net_manage::TcpConnection;
-use crate::network::net_message::{CTcpType, NetworkMessage, STcpType};
+use crate::components::profile::PlayerProfiles;
+use crate::components::prop::{spawn_lobby_props, Prop};
+use crate::components::scoreboard::Scoreboard;
+use crate::components::weapon::{Health, WeaponCooldown};
+use crate::components::{lobby_collision_layers, CollisionLayer, MAX_LOBBY_PARTITIONS};
+use crate::network::net_manage::{TcpConnection, UdpConnection};
+use crate::network::net_message::{NetworkMessage, STcpType, SUdpType};
 
-const LOBBY_ID: u32 = 1;
-pub fn join_lobby(
-    mut keyboard_input: MessageReader<KeyboardInput>,
-    mut connection: Single<&mut TcpConnection<CTcpType>>,
+pub const DEFAULT_LOBBY_ID: Id = Id(1);
+pub const DEFAULT_MAX_PLAYERS: usize = 16;
+const MAX_LOBBY_NAME_LENGTH: usize = 24;
+const DEFAULT_MAP: &str = "Flatland";
+pub const AVAILABLE_MAPS: [&str; 1] = [DEFAULT_MAP];
+
+#[derive(Component, Debug)]
+pub struct Lobby {
+    pub id: Id,
+    pub name: String,
+    pub map: String,
+    pub max_players: usize,
+    pub members: HashSet<Id>,
+    /// Members that toggled ready during warmup
+    pub ready: HashSet<Id>,
+    /// Members watching without a player of their own
+    pub spectators: HashSet<Id>,
+    /// Collision layer bit that keeps this lobby's bodies from touching other lobbies
+    pub partition: u8,
+    /// Persistent lobbies stay open when their last member leaves
+    pub persistent: bool,
+}
+
+/// Tags player and level entities with the lobby they belong to
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InLobby(pub Id);
+
+/// Hands out ids for lobbies created at runtime
+#[derive(Resource, Debug)]
+pub struct LobbyIds {
+    pub next: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LobbyInfo {
+    pub id: Id,
+    pub name: String,
+    pub player_count: usize,
+    pub max_players: usize,
+    pub map: String,
+    pub mode: GameModeKind,
+}
+
+impl Lobby {
+    pub fn info(&self, mode: GameModeKind) -> LobbyInfo {
+        LobbyInfo {
+            id: self.id,
+            name: self.name.clone(),
+            player_count: self.player_count(),
+            max_players: self.max_players,
+            map: self.map.clone(),
+            mode,
+        }
+    }
+
+    /// Spectators don't take up a slot
+    pub fn player_count(&self) -> usize {
+        self.members.len() - self.spectators.len()
+    }
+
+    pub fn is_player(&self, id: &Id) -> bool {
+        self.members.contains(id) && !self.spectators.contains(id)
+    }
+
+    pub fn players(&self) -> impl Iterator<Item = &Id> {
+        self.members.iter().filter(|id| !self.spectators.contains(id))
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.player_count() >= self.max_players
+    }
+
+    pub fn all_ready(&self) -> bool {
+        self.player_count() > 0 && self.players().all(|id| self.ready.contains(id))
+    }
+}
+
+/// Spawns a lobby with its own chat, scoreboard, match and level geometry
+pub fn spawn_lobby(
+    commands: &mut Commands,
+    lobby: Lobby,
+    mode: GameModeKind,
+    settings: &GameplaySettings,
+) {
+    let lobby_id = lobby.id;
+    let partition = lobby.partition;
+
+    commands.spawn((
+        lobby,
+        Chat::default(),
+        Scoreboard::default(),
+        Match::new(mode.create(), settings.match_settings()),
+    ));
+
+    commands.spawn((
+        RigidBody::Static,
+        Collider::cuboid(settings.ground_size[0], 0.5, settings.ground_size[1]),
+        Transform::from_xyz(0.0, 0.0, 0.0),
+        lobby_collision_layers(CollisionLayer::Ground, partition),
+        InLobby(lobby_id),
+    ));
+
+    spawn_lobby_props(commands, lobby_id, partition);
+}
+
+pub fn spawn_default_lobby(commands: &mut Commands, settings: &GameplaySettings) {
+    commands.insert_resource(LobbyIds { next: DEFAULT_LOBBY_ID.0 + 1 });
+
+    spawn_lobby(
+        commands,
+        Lobby {
+            id: DEFAULT_LOBBY_ID,
+            name: "Default".to_string(),
+            map: DEFAULT_MAP.to_string(),
+            max_players: settings.max_players_per_lobby,
+            members: HashSet::new(),
+            ready: HashSet::new(),
+            spectators: HashSet::new(),
+            partition: 0,
+            persistent: true,
+        },
+        GameModeKind::Deathmatch,
+        settings,
+    );
+}
+
+pub fn handle_list_lobbies(
+    connection: &mut TcpConnection<STcpType>,
+    lobbies: &Query<(Entity, &mut Lobby, &Match)>,
 ) {
-    for k in keyboard_input.read() {
-        if k.state == ButtonState::Released {
+    let mut list: Vec<LobbyInfo> = lobbies.iter().map(|(_, lobby, game_match)| lobby.info(game_match.mode.kind())).collect();
+    list.sort_by(|a, b| a.id.0.cmp(&b.id.0));
+
+    connection.add_message(NetworkMessage(STcpType::LobbyList { lobbies: list }));
+}
+
+pub fn handle_create_lobby(
+    name: &str,
+    mode: GameModeKind,
+    settings: &GameplaySettings,
+    connection: &mut TcpConnection<STcpType>,
+    lobbies: &mut Query<(Entity, &mut Lobby, &Match)>,
+    lobby_ids: &mut ResMut<LobbyIds>,
+    players: &Query<(Entity, &Id), With<PlayerMarker>>,
+    commands: &mut Commands,
+) {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_LOBBY_NAME_LENGTH {
+        reject(connection, "Lobby names must be 1 to 24 characters");
+        return;
+    }
+
+    if lobbies.iter().any(|(_, l, _)| l.name.eq_ignore_ascii_case(name)) {
+        reject(connection, "A lobby with that name already exists");
+        return;
+    }
+
+    let used_partitions: HashSet<u8> = lobbies.iter().map(|(_, l, _)| l.partition).collect();
+    let Some(partition) = (0..MAX_LOBBY_PARTITIONS).find(|p| !used_partitions.contains(p)) else {
+        reject(connection, "The server can't host any more lobbies");
+        return;
+    };
+
+    let lobby_id = Id(lobby_ids.next);
+    lobby_ids.next += 1;
+
+    leave_current_lobby(connection, lobbies, players, commands);
+
+    let mut lobby = Lobby {
+        id: lobby_id,
+        name: name.to_string(),
+        map: DEFAULT_MAP.to_string(),
+        max_players: settings.max_players_per_lobby,
+        members: HashSet::new(),
+        ready: HashSet::new(),
+        spectators: HashSet::new(),
+        partition,
+        persistent: false,
+    };
+
+    println!("Lobby created: {:?} {:?}", lobby_id, lobby.name);
+
+    add_player_to_lobby(&mut lobby, mode, false, connection, commands);
+    spawn_lobby(commands, lobby, mode, settings);
+}
+
+/// Joins to play, or to watch when asked to or when every player slot is taken
+pub fn handle_join(
+    lobby_id: Id,
+    spectate: bool,
+    connection: &mut TcpConnection<STcpType>,
+    lobbies: &mut Query<(Entity, &mut Lobby, &Match)>,
+    players: &Query<(Entity, &Id), With<PlayerMarker>>,
+    commands: &mut Commands,
+) {
+    println!("Trying to join lobby: {:?}", lobby_id);
+
+    let Some((_, lobby, _)) = lobbies.iter().find(|(_, l, _)| l.id == lobby_id) else {
+        reject(connection, "That lobby doesn't exist");
+        return;
+    };
+
+    let spectate = spectate || lobby.is_full();
+
+    if connection.player_id.is_some_and(|id| lobby.members.contains(&id)) {
+        return;
+    }
+
+    leave_current_lobby(connection, lobbies, players, commands);
+
+    if let Some((_, mut lobby, game_match)) = lobbies.iter_mut().find(|(_, l, _)| l.id == lobby_id) {
+        let mode = game_match.mode.kind();
+        add_player_to_lobby(&mut lobby, mode, spectate, connection, commands);
+    }
+}
+
+pub fn handle_set_ready(
+    ready: bool,
+    connection: &mut TcpConnection<STcpType>,
+    lobbies: &mut Query<(Entity, &mut Lobby, &Match)>,
+) {
+    let Some(player_id) = connection.player_id else {
+        return;
+    };
+
+    for (_, mut lobby, game_match) in lobbies.iter_mut() {
+        if !lobby.is_player(&player_id) || game_match.phase != MatchPhase::Warmup {
             continue;
-        };
+        }
 
-        match k.key_code {
-            KeyCode::KeyJ => {
-                if connection.stream.is_some() {
-                    connection.add_message(NetworkMessage(CTcpType::Join { lobby_id: Id(LOBBY_ID) }));
-                }
-            }
-            _ => {}
+        if ready {
+            lobby.ready.insert(player_id);
+        } else {
+            lobby.ready.remove(&player_id);
         }
     }
 }
 
-pub fn handle_join(lobby_id: Id, connection: &mut TcpConnection<STcpType>, commands: &mut Commands) {
-    println!("Trying to join lobby: {:?}", lobby_id);
-    // Generate an ID
+pub fn handle_leave(
+    connection: &mut TcpConnection<STcpType>,
+    lobbies: &mut Query<(Entity, &mut Lobby, &Match)>,
+    players: &Query<(Entity, &Id), With<PlayerMarker>>,
+    commands: &mut Commands,
+) {
+    if leave_current_lobby(connection, lobbies, players, commands) {
+        connection.add_message(NetworkMessage(STcpType::LobbyLeft));
+    }
+}
 
-    let player_id = generate_random_u32();
+/// Switches between playing and watching without leaving the lobby
+pub fn handle_set_spectating(
+    spectating: bool,
+    connection: &mut TcpConnection<STcpType>,
+    lobbies: &mut Query<(Entity, &mut Lobby, &Match)>,
+    players: &Query<(Entity, &Id), With<PlayerMarker>>,
+    commands: &mut Commands,
+) {
+    let Some(player_id) = connection.player_id else {
+        return;
+    };
+    let Some((_, mut lobby, _)) = lobbies.iter_mut().find(|(_, l, _)| l.members.contains(&player_id)) else {
+        return;
+    };
+    if lobby.spectators.contains(&player_id) == spectating {
+        return;
+    }
+
+    if spectating {
+        for (entity, id) in players.iter() {
+            if *id == player_id {
+                commands.entity(entity).despawn();
+            }
+        }
+        lobby.spectators.insert(player_id);
+        lobby.ready.remove(&player_id);
+    } else {
+        if lobby.is_full() {
+            connection.add_message(NetworkMessage(STcpType::Notice {
+                message: "There's no free slot to play in".to_string(),
+            }));
+            return;
+        }
+        lobby.spectators.remove(&player_id);
+        spawn_lobby_player(commands, &lobby, player_id);
+    }
+
+    println!("Player {:?} spectating in lobby {:?}: {}", player_id, lobby.id, spectating);
+
+    connection.add_message(NetworkMessage(STcpType::Spectating { spectating }));
+}
+
+fn add_player_to_lobby(
+    lobby: &mut Lobby,
+    mode: GameModeKind,
+    spectate: bool,
+    connection: &mut TcpConnection<STcpType>,
+    commands: &mut Commands,
+) {
+    // The id is handed out when the connection is accepted and stays with it across lobbies
+    let Some(player_id) = connection.player_id else {
+        return;
+    };
 
     println!("Player joined: {:?}", player_id);
 
+    lobby.members.insert(player_id);
+    // Spectators get snapshots like everyone else but no body in the level
+    if spectate {
+        lobby.spectators.insert(player_id);
+    } else {
+        spawn_lobby_player(commands, lobby, player_id);
+    }
+
+    connection.add_message(NetworkMessage(STcpType::LobbyJoined {
+        lobby: lobby.info(mode),
+        spectating: spectate,
+    }));
+}
+
+fn spawn_lobby_player(commands: &mut Commands, lobby: &Lobby, player_id: Id) {
     commands.spawn((
         RigidBody::Kinematic,
-        Collider::capsule(0.5, 1.0),
+        player_collider(false),
         Friction::new(1.0),
         LinearVelocity::default(),
         LockedAxes::new()
             .lock_rotation_x()
             .lock_rotation_y()
             .lock_rotation_z(),
-        Transform::from_xyz(0.0, 3.0, 0.0),
+        Transform::from_translation(spawn_position()),
+        lobby_collision_layers(CollisionLayer::Player, lobby.partition),
         CameraInfo {
             yaw: 0.0,
             pitch: 0.0,
         },
-        PlayerAnimationState(AnimationState::Idle),
+        PlayerAnimationState::default(),
         PendingInputs::default(),
-        Id(player_id),
+        MotorState::default(),
+        Health::default(),
+        WeaponCooldown::default(),
+        player_id,
+        InLobby(lobby.id),
         PlayerMarker,
     ));
+}
+
+/// Removes the connection's player from whatever lobby it is in. Returns false if it wasn't in one.
+fn leave_current_lobby(
+    connection: &mut TcpConnection<STcpType>,
+    lobbies: &mut Query<(Entity, &mut Lobby, &Match)>,
+    players: &Query<(Entity, &Id), With<PlayerMarker>>,
+    commands: &mut Commands,
+) -> bool {
+    let Some(player_id) = connection.player_id else {
+        return false;
+    };
+
+    for (entity, id) in players.iter() {
+        if *id == player_id {
+            commands.entity(entity).despawn();
+        }
+    }
+
+    for (entity, mut lobby, _) in lobbies.iter_mut() {
+        if !lobby.members.remove(&player_id) {
+            continue;
+        }
+        lobby.ready.remove(&player_id);
+        lobby.spectators.remove(&player_id);
+
+        println!("Player {:?} left lobby {:?}", player_id, lobby.id);
 
-    connection.add_message(NetworkMessage(STcpType::PlayerId {
-        player_uid: Id(player_id),
+        if lobby.members.is_empty() && !lobby.persistent {
+            println!("Closing empty lobby {:?}", lobby.id);
+            commands.entity(entity).despawn();
+        }
+        return true;
+    }
+
+    false
+}
+
+/// Sends the member list with ready states whenever a lobby's membership changes
+pub fn send_lobby_members(
+    lobbies: Query<&Lobby, Changed<Lobby>>,
+    mut connections: Query<&mut TcpConnection<STcpType>>,
+) {
+    for lobby in lobbies.iter() {
+        let mut members: Vec<(Id, bool)> = lobby.members.iter().map(|id| (*id, lobby.ready.contains(id))).collect();
+        members.sort_by(|a, b| a.0.0.cmp(&b.0.0));
+        let mut spectators: Vec<Id> = lobby.spectators.iter().copied().collect();
+        spectators.sort_by(|a, b| a.0.cmp(&b.0));
+
+        for mut c in connections.iter_mut() {
+            if !c.player_id.is_some_and(|id| lobby.members.contains(&id)) {
+                continue;
+            }
+            c.add_message(NetworkMessage(STcpType::LobbyMembers {
+                members: members.clone(),
+                spectators: spectators.clone(),
+            }));
+        }
+    }
+}
+
+/// Despawns the level geometry of lobbies that have been closed
+pub fn cleanup_closed_lobbies(
+    mut commands: Commands,
+    lobbies: Query<&Lobby>,
+    lobby_entities: Query<(Entity, &InLobby), Without<PlayerMarker>>,
+) {
+    for (entity, in_lobby) in lobby_entities.iter() {
+        if !lobbies.iter().any(|l| l.id == in_lobby.0) {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+/// Removes every player and prop of the lobby the client was in, they get respawned from the next snapshot
+pub fn client_clear_lobby(
+    lobby_entities: &Query<Entity, Or<(With<PlayerMarker>, With<Prop>)>>,
+    commands: &mut Commands,
+) {
+    for entity in lobby_entities.iter() {
+        commands.entity(entity).despawn();
+    }
+}
+
+fn reject(connection: &mut TcpConnection<STcpType>, reason: &str) {
+    info!("Lobby request rejected: {}", reason);
+    connection.add_message(NetworkMessage(STcpType::JoinRejected {
+        reason: reason.to_string(),
     }));
 }
 
-fn generate_random_u32() -> u32 {
-    let mut rng = rand::rng();
-    rng.random::<u32>()
-}
\ No newline at end of file
+/// Removes players whose TCP connection closed and gives their id back to the pool
+pub fn handle_disconnects(
+    mut commands: Commands,
+    mut connections: Query<(Entity, &mut TcpConnection<STcpType>)>,
+    udp_connections: Query<(Entity, &UdpConnection<SUdpType>)>,
+    mut lobbies: Query<(Entity, &mut Lobby, &Match)>,
+    players: Query<(Entity, &Id), With<PlayerMarker>>,
+    mut id_pool: ResMut<IdPool>,
+    mut profiles: ResMut<PlayerProfiles>,
+    mut moderation: ResMut<ChatModeration>,
+) {
+    for (entity, mut connection) in connections.iter_mut() {
+        if !connection.closed {
+            continue;
+        }
+
+        leave_current_lobby(&mut connection, &mut lobbies, &players, &mut commands);
+
+        if let Some(player_id) = connection.player_id {
+            println!("Player {:?} disconnected", player_id);
+
+            for (udp_entity, udp_connection) in udp_connections.iter() {
+                if udp_connection.player_id == Some(player_id) {
+                    commands.entity(udp_entity).despawn();
+                }
+            }
+
+            profiles.profiles.remove(&player_id);
+            moderation.forget(player_id);
+            id_pool.release(player_id);
+        }
+
+        commands.entity(entity).despawn();
+    }
+}
@@ -0,0 +1,647 @@
+pub mod options;
+pub mod plugin;
+
+use bevy::input::ButtonState;
+use bevy::input::keyboard::{Key, KeyboardInput};
+use bevy::prelude::{default, AlignItems, BackgroundColor, Button, Changed, Children, Color, Commands, Component, DetectChanges, Entity, FlexDirection, Interaction, JustifyContent, Local, MessageReader, NextState, Node, PositionType, Query, Res, ResMut, Resource, Single, State, States, Text, TextColor, TextFont, UiRect, Val, With, Without};
+use bevy::text::FontSmoothing;
+use bevy::window::{CursorGrabMode, CursorOptions};
+use crate::client_plugin::DefaultFont;
+use crate::components::common::Id;
+use crate::components::game_mode::{GameModeKind, MatchPhase, MatchStatus};
+use crate::components::lobby::LobbyInfo;
+use crate::components::player::PlayerInfo;
+use crate::components::menu::options::OptionsForm;
+use crate::components::profile::{sanitize_name, LocalProfile, PlayerProfile, PlayerProfiles, MAX_PLAYER_NAME_LENGTH, PROFILE_COLORS};
+use crate::components::settings::ClientSettings;
+use crate::network::net_manage::TcpConnection;
+use crate::network::net_message::{CTcpType, NetworkMessage};
+
+const MAX_LOBBY_NAME_LENGTH: usize = 24;
+const PANEL_COLOR: Color = Color::srgba(0.05, 0.05, 0.08, 0.85);
+const BUTTON_COLOR: Color = Color::srgb(0.2, 0.2, 0.25);
+const BUTTON_HOVER_COLOR: Color = Color::srgb(0.3, 0.3, 0.4);
+const BUTTON_PRESSED_COLOR: Color = Color::srgb(0.4, 0.5, 0.4);
+
+#[derive(States, Default, Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum MenuState {
+    #[default]
+    LobbyBrowser,
+    CreateLobby,
+    EditProfile,
+    InLobby,
+    InGame,
+    Options,
+}
+
+/// Client side copy of what the server told us about lobbies
+#[derive(Resource, Default)]
+pub struct LobbyBrowser {
+    pub lobbies: Vec<LobbyInfo>,
+    pub current: Option<LobbyInfo>,
+    pub members: Vec<(Id, bool)>,
+    pub spectators: Vec<Id>,
+    pub status: Option<String>,
+}
+
+#[derive(Resource, Default)]
+pub struct CreateLobbyForm {
+    pub name: String,
+    pub mode: GameModeKind,
+}
+
+/// Name and colour being edited before they're sent to the server
+#[derive(Resource, Default)]
+pub struct ProfileForm {
+    pub name: String,
+    pub color: usize,
+}
+
+#[derive(Component)]
+pub enum MenuButton {
+    Refresh,
+    OpenCreate,
+    OpenProfile,
+    OpenOptions,
+    CycleColor,
+    SaveProfile,
+    CancelProfile,
+    Join(Id),
+    Spectate(Id),
+    ToggleMode,
+    ConfirmCreate,
+    CancelCreate,
+    ToggleReady,
+    ToggleSpectate,
+    Leave,
+}
+
+#[derive(Component)]
+pub struct MenuScreen;
+
+#[derive(Component)]
+pub struct LobbyListContainer;
+
+#[derive(Component)]
+pub struct MemberListContainer;
+
+#[derive(Component)]
+pub struct LobbyStatusText;
+
+#[derive(Component)]
+pub struct CreateLobbyNameText;
+
+#[derive(Component)]
+pub struct CreateLobbyModeText;
+
+#[derive(Component)]
+pub struct ProfileNameText;
+
+#[derive(Component)]
+pub struct ProfileColorText;
+
+#[derive(Component)]
+pub struct LocalNameText;
+
+#[derive(Component)]
+pub struct ReadyButtonText;
+
+#[derive(Component)]
+pub struct SpectateButtonText;
+
+fn text_font(default_font: &DefaultFont, font_size: f32) -> TextFont {
+    TextFont {
+        font: default_font.0.clone(),
+        font_size,
+        font_smoothing: FontSmoothing::None,
+        ..default()
+    }
+}
+
+fn screen_node() -> (Node, BackgroundColor, MenuScreen) {
+    (
+        Node {
+            position_type: PositionType::Absolute,
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        BackgroundColor(Color::NONE),
+        MenuScreen,
+    )
+}
+
+fn panel_node() -> (Node, BackgroundColor) {
+    (
+        Node {
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(16.0)),
+            row_gap: Val::Px(8.0),
+            min_width: Val::Px(420.0),
+            ..default()
+        },
+        BackgroundColor(PANEL_COLOR),
+    )
+}
+
+fn spawn_button(commands: &mut Commands, parent: Entity, default_font: &DefaultFont, label: &str, button: impl Component) -> Entity {
+    let button = commands
+        .spawn((
+            Button,
+            Node {
+                padding: UiRect::axes(Val::Px(12.0), Val::Px(6.0)),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            BackgroundColor(BUTTON_COLOR),
+            button,
+        ))
+        .with_children(|b| {
+            b.spawn((Text::new(label), text_font(default_font, 20.0)));
+        })
+        .id();
+    commands.entity(parent).add_child(button);
+    button
+}
+
+fn spawn_row(commands: &mut Commands, parent: Entity) -> Entity {
+    let row = commands
+        .spawn(Node {
+            flex_direction: FlexDirection::Row,
+            column_gap: Val::Px(8.0),
+            ..default()
+        })
+        .id();
+    commands.entity(parent).add_child(row);
+    row
+}
+
+pub fn despawn_menu_screen(mut commands: Commands, screens: Query<Entity, With<MenuScreen>>) {
+    for entity in screens.iter() {
+        commands.entity(entity).despawn();
+    }
+}
+
+pub fn show_cursor(mut cursor_options: Single<&mut CursorOptions>) {
+    cursor_options.grab_mode = CursorGrabMode::None;
+    cursor_options.visible = true;
+}
+
+pub fn grab_cursor(mut cursor_options: Single<&mut CursorOptions>) {
+    cursor_options.grab_mode = CursorGrabMode::Locked;
+    cursor_options.visible = false;
+}
+
+pub fn request_lobby_list(mut connection: Single<&mut TcpConnection<CTcpType>>) {
+    if connection.stream.is_some() {
+        connection.add_message(NetworkMessage(CTcpType::ListLobbies));
+    }
+}
+
+/// Fetches the list as soon as the TCP connection comes up, the browser is shown before that
+pub fn request_lobby_list_on_connect(
+    mut connection: Single<&mut TcpConnection<CTcpType>>,
+    mut was_connected: Local<bool>,
+) {
+    let connected = connection.stream.is_some();
+    if connected && !*was_connected {
+        connection.add_message(NetworkMessage(CTcpType::ListLobbies));
+    }
+    *was_connected = connected;
+}
+
+pub fn setup_lobby_browser(mut commands: Commands, default_font: Res<DefaultFont>) {
+    let screen = commands.spawn(screen_node()).id();
+    let panel = commands.spawn(panel_node()).id();
+    commands.entity(screen).add_child(panel);
+
+    let title = commands.spawn((Text::new("Lobbies"), text_font(&default_font, 32.0))).id();
+    let local_name = commands.spawn((Text::new(""), text_font(&default_font, 18.0), LocalNameText)).id();
+    let header = commands
+        .spawn((
+            Text::new(format!("{:<20}{:>9}  {:<12}{}", "Name", "Players", "Map", "Mode")),
+            text_font(&default_font, 18.0),
+        ))
+        .id();
+    let list = commands
+        .spawn((
+            Node {
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(4.0),
+                ..default()
+            },
+            LobbyListContainer,
+        ))
+        .id();
+    let status = commands.spawn((Text::new(""), text_font(&default_font, 18.0), LobbyStatusText)).id();
+    commands.entity(panel).add_children(&[title, local_name, header, list, status]);
+
+    let buttons = spawn_row(&mut commands, panel);
+    spawn_button(&mut commands, buttons, &default_font, "Refresh", MenuButton::Refresh);
+    spawn_button(&mut commands, buttons, &default_font, "Create", MenuButton::OpenCreate);
+    spawn_button(&mut commands, buttons, &default_font, "Profile", MenuButton::OpenProfile);
+    spawn_button(&mut commands, buttons, &default_font, "Options", MenuButton::OpenOptions);
+}
+
+pub fn setup_edit_profile(
+    mut commands: Commands,
+    default_font: Res<DefaultFont>,
+    local_profile: Res<LocalProfile>,
+    mut form: ResMut<ProfileForm>,
+) {
+    form.name = local_profile.0.name.clone();
+    form.color = PROFILE_COLORS.iter().position(|c| *c == local_profile.0.color).unwrap_or(0);
+
+    let screen = commands.spawn(screen_node()).id();
+    let panel = commands.spawn(panel_node()).id();
+    commands.entity(screen).add_child(panel);
+
+    let title = commands.spawn((Text::new("Profile"), text_font(&default_font, 32.0))).id();
+    let name = commands.spawn((Text::new("Name: _"), text_font(&default_font, 20.0), ProfileNameText)).id();
+    commands.entity(panel).add_children(&[title, name]);
+
+    let color_row = spawn_row(&mut commands, panel);
+    let color = spawn_button(&mut commands, color_row, &default_font, "Colour", MenuButton::CycleColor);
+    commands.entity(color).insert(ProfileColorText);
+
+    let buttons = spawn_row(&mut commands, panel);
+    spawn_button(&mut commands, buttons, &default_font, "Save", MenuButton::SaveProfile);
+    spawn_button(&mut commands, buttons, &default_font, "Cancel", MenuButton::CancelProfile);
+}
+
+pub fn setup_create_lobby(mut commands: Commands, default_font: Res<DefaultFont>, mut form: ResMut<CreateLobbyForm>) {
+    form.name.clear();
+
+    let screen = commands.spawn(screen_node()).id();
+    let panel = commands.spawn(panel_node()).id();
+    commands.entity(screen).add_child(panel);
+
+    let title = commands.spawn((Text::new("Create Lobby"), text_font(&default_font, 32.0))).id();
+    let name = commands.spawn((Text::new("Name: _"), text_font(&default_font, 20.0), CreateLobbyNameText)).id();
+    commands.entity(panel).add_children(&[title, name]);
+
+    let mode_row = spawn_row(&mut commands, panel);
+    let mode = spawn_button(&mut commands, mode_row, &default_font, "", MenuButton::ToggleMode);
+    commands.entity(mode).insert(CreateLobbyModeText);
+
+    let buttons = spawn_row(&mut commands, panel);
+    spawn_button(&mut commands, buttons, &default_font, "Create", MenuButton::ConfirmCreate);
+    spawn_button(&mut commands, buttons, &default_font, "Cancel", MenuButton::CancelCreate);
+}
+
+pub fn setup_in_lobby(mut commands: Commands, default_font: Res<DefaultFont>, browser: Res<LobbyBrowser>) {
+    let screen = commands.spawn(screen_node()).id();
+    let panel = commands.spawn(panel_node()).id();
+    commands.entity(screen).add_child(panel);
+
+    let title_text = match &browser.current {
+        Some(lobby) => format!("{} - {:?} on {}", lobby.name, lobby.mode, lobby.map),
+        None => "Lobby".to_string(),
+    };
+    let title = commands.spawn((Text::new(title_text), text_font(&default_font, 28.0))).id();
+    let members = commands
+        .spawn((
+            Text::new(""),
+            text_font(&default_font, 20.0),
+            MemberListContainer,
+        ))
+        .id();
+    commands.entity(panel).add_children(&[title, members]);
+
+    let buttons = spawn_row(&mut commands, panel);
+    let ready = spawn_button(&mut commands, buttons, &default_font, "Ready", MenuButton::ToggleReady);
+    commands.entity(ready).insert(ReadyButtonText);
+    let spectate = spawn_button(&mut commands, buttons, &default_font, "Spectate", MenuButton::ToggleSpectate);
+    commands.entity(spectate).insert(SpectateButtonText);
+    spawn_button(&mut commands, buttons, &default_font, "Leave", MenuButton::Leave);
+    spawn_button(&mut commands, buttons, &default_font, "Options", MenuButton::OpenOptions);
+}
+
+/// Rebuilds the lobby rows whenever a new list arrives from the server
+pub fn update_lobby_list(
+    mut commands: Commands,
+    default_font: Res<DefaultFont>,
+    browser: Res<LobbyBrowser>,
+    container: Query<(Entity, Option<&Children>), With<LobbyListContainer>>,
+    mut status: Query<&mut Text, With<LobbyStatusText>>,
+    mut local_name: Query<&mut Text, (With<LocalNameText>, Without<LobbyStatusText>)>,
+    player_info: Res<PlayerInfo>,
+    profiles: Res<PlayerProfiles>,
+) {
+    if let Some(mut local_name) = local_name.single_mut().ok() {
+        let name = format!("Playing as {}", profiles.display_name(player_info.current_player_id));
+        if local_name.0 != name {
+            local_name.0 = name;
+        }
+    }
+
+    let Some((container, children)) = container.single().ok() else {
+        return;
+    };
+    if !browser.is_changed() && children.is_some() {
+        return;
+    }
+
+    if let Some(children) = children {
+        for child in children.iter() {
+            commands.entity(child).despawn();
+        }
+    }
+
+    for lobby in browser.lobbies.iter() {
+        let label = format!(
+            "{:<20}{:>4}/{:<4}  {:<12}{:?}",
+            lobby.name, lobby.player_count, lobby.max_players, lobby.map, lobby.mode
+        );
+        let row = spawn_row(&mut commands, container);
+        spawn_button(&mut commands, row, &default_font, &label, MenuButton::Join(lobby.id));
+        spawn_button(&mut commands, row, &default_font, "Watch", MenuButton::Spectate(lobby.id));
+    }
+
+    if let Some(mut status) = status.single_mut().ok() {
+        status.0 = match (&browser.status, browser.lobbies.is_empty()) {
+            (Some(s), _) => s.clone(),
+            (None, true) => "No lobbies found".to_string(),
+            (None, false) => String::new(),
+        };
+    }
+}
+
+pub fn update_member_list(
+    browser: Res<LobbyBrowser>,
+    mut members: Query<&mut Text, With<MemberListContainer>>,
+    ready_button: Query<&Children, With<ReadyButtonText>>,
+    spectate_button: Query<&Children, With<SpectateButtonText>>,
+    mut texts: Query<&mut Text, Without<MemberListContainer>>,
+    player_info: Res<PlayerInfo>,
+    profiles: Res<PlayerProfiles>,
+) {
+    if let Some(mut members) = members.single_mut().ok() {
+        members.0.clear();
+        for (id, ready) in browser.members.iter() {
+            let state = match (browser.spectators.contains(id), *ready) {
+                (true, _) => "Spectating",
+                (false, true) => "Ready",
+                (false, false) => "Not ready",
+            };
+            members.0.push_str(&format!("{:<18}{}\n", profiles.display_name(*id), state));
+        }
+    }
+
+    let is_ready = browser
+        .members
+        .iter()
+        .any(|(id, ready)| *id == player_info.current_player_id && *ready);
+
+    if let Some(children) = ready_button.single().ok() {
+        for child in children.iter() {
+            if let Some(mut text) = texts.get_mut(child).ok() {
+                text.0 = if is_ready { "Unready".to_string() } else { "Ready".to_string() };
+            }
+        }
+    }
+
+    let is_spectating = browser.spectators.contains(&player_info.current_player_id);
+    if let Some(children) = spectate_button.single().ok() {
+        for child in children.iter() {
+            if let Some(mut text) = texts.get_mut(child).ok() {
+                text.0 = if is_spectating { "Play".to_string() } else { "Spectate".to_string() };
+            }
+        }
+    }
+}
+
+pub fn update_create_lobby_form(
+    mut keyboard_input: MessageReader<KeyboardInput>,
+    mut form: ResMut<CreateLobbyForm>,
+    mut name_text: Query<&mut Text, With<CreateLobbyNameText>>,
+    mode_button: Query<&Children, With<CreateLobbyModeText>>,
+    mut texts: Query<&mut Text, Without<CreateLobbyNameText>>,
+) {
+    for k in keyboard_input.read() {
+        if k.state == ButtonState::Released {
+            continue;
+        }
+
+        match &k.logical_key {
+            Key::Backspace => {
+                form.name.pop();
+            }
+            Key::Character(c) => {
+                if form.name.chars().count() < MAX_LOBBY_NAME_LENGTH {
+                    form.name.push_str(c.as_str());
+                }
+            }
+            Key::Space => {
+                if form.name.chars().count() < MAX_LOBBY_NAME_LENGTH {
+                    form.name.push(' ');
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(mut name_text) = name_text.single_mut().ok() {
+        name_text.0 = format!("Name: {}_", form.name);
+    }
+
+    if let Some(children) = mode_button.single().ok() {
+        for child in children.iter() {
+            if let Some(mut text) = texts.get_mut(child).ok() {
+                text.0 = format!("Mode: {:?}", form.mode);
+            }
+        }
+    }
+}
+
+pub fn update_profile_form(
+    mut keyboard_input: MessageReader<KeyboardInput>,
+    mut form: ResMut<ProfileForm>,
+    mut name_text: Query<&mut Text, With<ProfileNameText>>,
+    color_button: Query<&Children, With<ProfileColorText>>,
+    mut texts: Query<(&mut Text, &mut TextColor), Without<ProfileNameText>>,
+) {
+    for k in keyboard_input.read() {
+        if k.state == ButtonState::Released {
+            continue;
+        }
+
+        match &k.logical_key {
+            Key::Backspace => {
+                form.name.pop();
+            }
+            Key::Character(c) => {
+                if form.name.chars().count() < MAX_PLAYER_NAME_LENGTH {
+                    form.name.push_str(c.as_str());
+                }
+            }
+            Key::Space => {
+                if form.name.chars().count() < MAX_PLAYER_NAME_LENGTH {
+                    form.name.push(' ');
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(mut name_text) = name_text.single_mut().ok() {
+        name_text.0 = format!("Name: {}_", form.name);
+    }
+
+    let [r, g, b] = PROFILE_COLORS[form.color % PROFILE_COLORS.len()];
+    if let Some(children) = color_button.single().ok() {
+        for child in children.iter() {
+            if let Some((_, mut color)) = texts.get_mut(child).ok() {
+                color.0 = Color::srgb_u8(r, g, b);
+            }
+        }
+    }
+}
+
+pub fn menu_buttons(
+    mut buttons: Query<(&Interaction, &MenuButton, &mut BackgroundColor), Changed<Interaction>>,
+    mut connection: Single<&mut TcpConnection<CTcpType>>,
+    mut form: ResMut<CreateLobbyForm>,
+    mut profile_form: ResMut<ProfileForm>,
+    mut local_profile: ResMut<LocalProfile>,
+    mut browser: ResMut<LobbyBrowser>,
+    mut settings: ResMut<ClientSettings>,
+    mut options_form: ResMut<OptionsForm>,
+    player_info: Res<PlayerInfo>,
+    state: Res<State<MenuState>>,
+    mut next_state: ResMut<NextState<MenuState>>,
+) {
+    for (interaction, button, mut color) in buttons.iter_mut() {
+        match interaction {
+            Interaction::Hovered => color.0 = BUTTON_HOVER_COLOR,
+            Interaction::None => color.0 = BUTTON_COLOR,
+            Interaction::Pressed => {
+                color.0 = BUTTON_PRESSED_COLOR;
+
+                // The options don't need the server
+                if connection.stream.is_none() && !matches!(button, MenuButton::OpenOptions) {
+                    browser.status = Some("Not connected to the server".to_string());
+                    continue;
+                }
+
+                match button {
+                    MenuButton::Refresh => {
+                        connection.add_message(NetworkMessage(CTcpType::ListLobbies));
+                    }
+                    MenuButton::OpenCreate => {
+                        next_state.set(MenuState::CreateLobby);
+                    }
+                    MenuButton::OpenProfile => {
+                        next_state.set(MenuState::EditProfile);
+                    }
+                    MenuButton::OpenOptions => {
+                        options_form.return_to = *state.get();
+                        next_state.set(MenuState::Options);
+                    }
+                    MenuButton::CycleColor => {
+                        profile_form.color = (profile_form.color + 1) % PROFILE_COLORS.len();
+                    }
+                    MenuButton::SaveProfile => {
+                        // The server has the final say on the name, it comes back with the profile list
+                        local_profile.0 = PlayerProfile {
+                            name: sanitize_name(&profile_form.name),
+                            color: PROFILE_COLORS[profile_form.color % PROFILE_COLORS.len()],
+                        };
+                        settings.display_name = local_profile.0.name.clone();
+                        settings.color = local_profile.0.color;
+                        settings.save();
+                        next_state.set(MenuState::LobbyBrowser);
+                    }
+                    MenuButton::CancelProfile => {
+                        next_state.set(MenuState::LobbyBrowser);
+                    }
+                    MenuButton::Join(lobby_id) => {
+                        connection.add_message(NetworkMessage(CTcpType::Join { lobby_id: *lobby_id, spectate: false }));
+                    }
+                    MenuButton::Spectate(lobby_id) => {
+                        connection.add_message(NetworkMessage(CTcpType::Join { lobby_id: *lobby_id, spectate: true }));
+                    }
+                    MenuButton::ToggleMode => {
+                        form.mode = match form.mode {
+                            GameModeKind::Deathmatch => GameModeKind::TeamDeathmatch,
+                            GameModeKind::TeamDeathmatch => GameModeKind::Deathmatch,
+                        };
+                    }
+                    MenuButton::ConfirmCreate => {
+                        connection.add_message(NetworkMessage(CTcpType::CreateLobby {
+                            name: form.name.trim().to_string(),
+                            mode: form.mode,
+                        }));
+                    }
+                    MenuButton::CancelCreate => {
+                        next_state.set(MenuState::LobbyBrowser);
+                    }
+                    MenuButton::ToggleReady => {
+                        let is_ready = browser
+                            .members
+                            .iter()
+                            .any(|(id, ready)| *id == player_info.current_player_id && *ready);
+                        connection.add_message(NetworkMessage(CTcpType::SetReady { ready: !is_ready }));
+                    }
+                    MenuButton::ToggleSpectate => {
+                        let is_spectating = browser.spectators.contains(&player_info.current_player_id);
+                        connection.add_message(NetworkMessage(CTcpType::SetSpectating { spectating: !is_spectating }));
+                    }
+                    MenuButton::Leave => {
+                        connection.add_message(NetworkMessage(CTcpType::LeaveLobby));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Hides the lobby screen while a match is running and brings it back for the next warmup
+pub fn follow_match_phase(
+    match_status: Query<&MatchStatus>,
+    browser: Res<LobbyBrowser>,
+    state: Res<State<MenuState>>,
+    mut next_state: ResMut<NextState<MenuState>>,
+) {
+    let Some(match_status) = match_status.single().ok() else {
+        return;
+    };
+
+    match (state.get(), match_status.state.phase) {
+        (MenuState::InLobby, MatchPhase::InProgress) => next_state.set(MenuState::InGame),
+        (MenuState::InGame, MatchPhase::Warmup) if browser.current.is_some() => next_state.set(MenuState::InLobby),
+        _ => {}
+    }
+}
+
+pub fn client_set_lobby_list(
+    lobbies: &Vec<LobbyInfo>,
+    browser: &mut LobbyBrowser,
+) {
+    browser.lobbies = lobbies.clone();
+    browser.status = None;
+}
+
+pub fn client_lobby_joined(
+    lobby: &LobbyInfo,
+    browser: &mut LobbyBrowser,
+    next_state: &mut NextState<MenuState>,
+) {
+    browser.current = Some(lobby.clone());
+    browser.members.clear();
+    browser.spectators.clear();
+    browser.status = None;
+    next_state.set(MenuState::InLobby);
+}
+
+pub fn client_lobby_left(
+    browser: &mut LobbyBrowser,
+    next_state: &mut NextState<MenuState>,
+) {
+    browser.current = None;
+    browser.members.clear();
+    browser.spectators.clear();
+    next_state.set(MenuState::LobbyBrowser);
+}
@@ -0,0 +1,250 @@
+use bevy::prelude::{BackgroundColor, ButtonInput, Changed, Commands, Component, Interaction, KeyCode, MouseButton, NextState, Query, Res, ResMut, Resource, State, Text};
+use crate::client_plugin::DefaultFont;
+use crate::components::chat::ChatInput;
+use crate::components::menu::{panel_node, screen_node, spawn_button, spawn_row, text_font, MenuState, BUTTON_COLOR, BUTTON_HOVER_COLOR, BUTTON_PRESSED_COLOR};
+use crate::components::actions::{InputAction, InputBinding};
+use crate::components::settings::{ClientSettings, MAX_DEAD_ZONE, MAX_FOV, MAX_SENSITIVITY, MAX_STICK_LOOK_SPEED, MAX_ZOOM, MIN_FOV, MIN_SENSITIVITY, MIN_STICK_LOOK_SPEED, MIN_ZOOM};
+
+const SENSITIVITY_STEP: f32 = 0.1;
+const FOV_STEP: f32 = 5.0;
+const ZOOM_STEP: f32 = 1.0;
+const STICK_LOOK_SPEED_STEP: f32 = 100.0;
+const DEAD_ZONE_STEP: f32 = 0.05;
+
+/// Where the options menu goes back to and which binding is waiting for a key
+#[derive(Resource, Default)]
+pub struct OptionsForm {
+    pub return_to: MenuState,
+    pub rebinding: Option<InputAction>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum OptionsLine {
+    Sensitivity,
+    InvertY,
+    Fov,
+    MaxZoom,
+    Msaa,
+    Vsync,
+    Shadows,
+    ShowFps,
+    Controller,
+    StickLookSpeed,
+    StickDeadZone,
+    Binding(InputAction),
+}
+
+impl OptionsLine {
+    const SETTINGS: [OptionsLine; 11] = [
+        OptionsLine::Sensitivity,
+        OptionsLine::InvertY,
+        OptionsLine::Fov,
+        OptionsLine::MaxZoom,
+        OptionsLine::Msaa,
+        OptionsLine::Vsync,
+        OptionsLine::Shadows,
+        OptionsLine::ShowFps,
+        OptionsLine::Controller,
+        OptionsLine::StickLookSpeed,
+        OptionsLine::StickDeadZone,
+    ];
+
+    /// Toggles and cycles get one button, numbers get a - and a +
+    fn is_numeric(&self) -> bool {
+        matches!(
+            self,
+            OptionsLine::Sensitivity | OptionsLine::Fov | OptionsLine::MaxZoom | OptionsLine::StickLookSpeed | OptionsLine::StickDeadZone
+        )
+    }
+}
+
+#[derive(Component)]
+pub enum OptionsButton {
+    Change(OptionsLine, f32),
+    ResetDefaults,
+    Back,
+}
+
+#[derive(Component)]
+pub struct OptionsText(OptionsLine);
+
+fn on_off(value: bool) -> &'static str {
+    if value { "On" } else { "Off" }
+}
+
+fn line_text(line: OptionsLine, settings: &ClientSettings, form: &OptionsForm) -> String {
+    let (label, value) = match line {
+        OptionsLine::Sensitivity => ("Sensitivity", format!("{:.1}", settings.mouse.sensitivity)),
+        OptionsLine::InvertY => ("Invert Y", on_off(settings.mouse.invert_y).to_string()),
+        OptionsLine::Fov => ("Field of view", format!("{:.0}", settings.camera.fov)),
+        OptionsLine::MaxZoom => ("Camera distance", format!("{:.0}", settings.camera.max_zoom)),
+        OptionsLine::Msaa => ("Anti-aliasing", match settings.graphics.msaa_samples {
+            1 => "Off".to_string(),
+            n => format!("{}x", n),
+        }),
+        OptionsLine::Vsync => ("VSync", on_off(settings.graphics.vsync).to_string()),
+        OptionsLine::Shadows => ("Shadows", on_off(settings.graphics.shadows).to_string()),
+        OptionsLine::ShowFps => ("FPS counter", on_off(settings.graphics.show_fps).to_string()),
+        OptionsLine::Controller => ("Gamepad", on_off(settings.controller.enabled).to_string()),
+        OptionsLine::StickLookSpeed => ("Stick look speed", format!("{:.0}", settings.controller.look_speed)),
+        OptionsLine::StickDeadZone => ("Stick dead zone", format!("{:.2}", settings.controller.move_dead_zone)),
+        OptionsLine::Binding(action) if form.rebinding == Some(action) => (action.label(), "Press a key or button".to_string()),
+        OptionsLine::Binding(action) => (action.label(), settings.key_bindings.get(action).to_string()),
+    };
+    format!("{:<18}{}", label, value)
+}
+
+fn change_setting(settings: &mut ClientSettings, form: &mut OptionsForm, line: OptionsLine, step: f32) {
+    match line {
+        OptionsLine::Sensitivity => {
+            settings.mouse.sensitivity = (settings.mouse.sensitivity + step * SENSITIVITY_STEP).clamp(MIN_SENSITIVITY, MAX_SENSITIVITY);
+        }
+        OptionsLine::InvertY => settings.mouse.invert_y = !settings.mouse.invert_y,
+        OptionsLine::Fov => settings.camera.fov = (settings.camera.fov + step * FOV_STEP).clamp(MIN_FOV, MAX_FOV),
+        OptionsLine::MaxZoom => {
+            settings.camera.max_zoom = (settings.camera.max_zoom + step * ZOOM_STEP).clamp(MIN_ZOOM, MAX_ZOOM);
+            settings.camera.min_zoom = settings.camera.min_zoom.min(settings.camera.max_zoom);
+        }
+        OptionsLine::Msaa => settings.graphics.cycle_msaa(),
+        OptionsLine::Vsync => settings.graphics.vsync = !settings.graphics.vsync,
+        OptionsLine::Shadows => settings.graphics.shadows = !settings.graphics.shadows,
+        OptionsLine::ShowFps => settings.graphics.show_fps = !settings.graphics.show_fps,
+        OptionsLine::Controller => settings.controller.enabled = !settings.controller.enabled,
+        OptionsLine::StickLookSpeed => {
+            settings.controller.look_speed = (settings.controller.look_speed + step * STICK_LOOK_SPEED_STEP).clamp(MIN_STICK_LOOK_SPEED, MAX_STICK_LOOK_SPEED);
+        }
+        OptionsLine::StickDeadZone => {
+            // Both sticks share the menu setting, the file can still set them apart
+            let dead_zone = (settings.controller.move_dead_zone + step * DEAD_ZONE_STEP).clamp(0.0, MAX_DEAD_ZONE);
+            settings.controller.move_dead_zone = dead_zone;
+            settings.controller.look_dead_zone = dead_zone;
+        }
+        OptionsLine::Binding(action) => form.rebinding = Some(action),
+    }
+}
+
+pub fn setup_options(mut commands: Commands, default_font: Res<DefaultFont>, settings: Res<ClientSettings>, mut form: ResMut<OptionsForm>) {
+    form.rebinding = None;
+
+    let screen = commands.spawn(screen_node()).id();
+    let panel = commands.spawn(panel_node()).id();
+    commands.entity(screen).add_child(panel);
+
+    let title = commands.spawn((Text::new("Options"), text_font(&default_font, 32.0))).id();
+    commands.entity(panel).add_child(title);
+
+    let lines = OptionsLine::SETTINGS
+        .into_iter()
+        .chain(InputAction::ALL.into_iter().map(OptionsLine::Binding));
+    for line in lines {
+        let row = spawn_row(&mut commands, panel);
+        let text = commands
+            .spawn((Text::new(line_text(line, &settings, &form)), text_font(&default_font, 20.0), OptionsText(line)))
+            .id();
+        commands.entity(row).add_child(text);
+
+        if line.is_numeric() {
+            spawn_button(&mut commands, row, &default_font, "-", OptionsButton::Change(line, -1.0));
+            spawn_button(&mut commands, row, &default_font, "+", OptionsButton::Change(line, 1.0));
+        } else {
+            spawn_button(&mut commands, row, &default_font, "Change", OptionsButton::Change(line, 1.0));
+        }
+    }
+
+    let buttons = spawn_row(&mut commands, panel);
+    spawn_button(&mut commands, buttons, &default_font, "Defaults", OptionsButton::ResetDefaults);
+    spawn_button(&mut commands, buttons, &default_font, "Back", OptionsButton::Back);
+}
+
+pub fn options_buttons(
+    mut buttons: Query<(&Interaction, &OptionsButton, &mut BackgroundColor), Changed<Interaction>>,
+    mut settings: ResMut<ClientSettings>,
+    mut form: ResMut<OptionsForm>,
+    mut next_state: ResMut<NextState<MenuState>>,
+) {
+    for (interaction, button, mut color) in buttons.iter_mut() {
+        match interaction {
+            Interaction::Hovered => color.0 = BUTTON_HOVER_COLOR,
+            Interaction::None => color.0 = BUTTON_COLOR,
+            Interaction::Pressed => {
+                color.0 = BUTTON_PRESSED_COLOR;
+
+                match button {
+                    OptionsButton::Change(line, step) => change_setting(&mut settings, &mut form, *line, *step),
+                    OptionsButton::ResetDefaults => {
+                        // The profile and server aren't options, only what this menu shows is reset
+                        let defaults = ClientSettings::default();
+                        settings.mouse = defaults.mouse;
+                        settings.camera = defaults.camera;
+                        settings.graphics = defaults.graphics;
+                        settings.key_bindings = defaults.key_bindings;
+                        settings.controller = defaults.controller;
+                        form.rebinding = None;
+                    }
+                    OptionsButton::Back => next_state.set(form.return_to),
+                }
+            }
+        }
+    }
+}
+
+/// Binds the next key or mouse button pressed while waiting for one, otherwise Escape leaves the menu.
+/// Reads just pressed input rather than keyboard messages so the Escape that opened the menu isn't seen again.
+/// Runs before the buttons so the click that starts a rebind isn't taken as the new binding.
+pub fn options_keys(
+    keys: Res<ButtonInput<KeyCode>>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    mut settings: ResMut<ClientSettings>,
+    mut form: ResMut<OptionsForm>,
+    mut next_state: ResMut<NextState<MenuState>>,
+) {
+    let pressed = keys
+        .get_just_pressed()
+        .next()
+        .map(|k| InputBinding::Key(*k))
+        .or_else(|| mouse.get_just_pressed().next().map(|b| InputBinding::Mouse(*b)));
+    let Some(pressed) = pressed else {
+        return;
+    };
+
+    match (form.rebinding, pressed) {
+        (Some(_), InputBinding::Key(KeyCode::Escape)) => form.rebinding = None,
+        (Some(action), binding) => {
+            settings.key_bindings.set(action, binding);
+            form.rebinding = None;
+        }
+        (None, InputBinding::Key(KeyCode::Escape)) => next_state.set(form.return_to),
+        _ => {}
+    }
+}
+
+pub fn update_options_text(
+    settings: Res<ClientSettings>,
+    form: Res<OptionsForm>,
+    mut texts: Query<(&mut Text, &OptionsText)>,
+) {
+    for (mut text, line) in texts.iter_mut() {
+        let value = line_text(line.0, &settings, &form);
+        if text.0 != value {
+            text.0 = value;
+        }
+    }
+}
+
+/// Escape during a match brings up the options, unless it's closing the chat
+pub fn open_options_in_game(
+    keys: Res<ButtonInput<KeyCode>>,
+    chat_input: Res<ChatInput>,
+    state: Res<State<MenuState>>,
+    mut form: ResMut<OptionsForm>,
+    mut next_state: ResMut<NextState<MenuState>>,
+) {
+    if keys.just_pressed(KeyCode::Escape) && !chat_input.active {
+        form.return_to = *state.get();
+        next_state.set(MenuState::Options);
+    }
+}
+
+pub fn save_client_settings(settings: Res<ClientSettings>) {
+    settings.save();
+}
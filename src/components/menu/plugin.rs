@@ -0,0 +1,47 @@
+use bevy::app::{App, Plugin, Update};
+use bevy::prelude::{in_state, AppExtStates, IntoScheduleConfigs, OnEnter, OnExit};
+use crate::components::chat::chat_window;
+use crate::components::menu::options::{open_options_in_game, options_buttons, options_keys, save_client_settings, setup_options, update_options_text, OptionsForm};
+use crate::components::menu::{despawn_menu_screen, follow_match_phase, grab_cursor, menu_buttons, request_lobby_list, request_lobby_list_on_connect, setup_create_lobby, setup_edit_profile, setup_in_lobby, setup_lobby_browser, show_cursor, update_create_lobby_form, update_lobby_list, update_member_list, update_profile_form, CreateLobbyForm, LobbyBrowser, MenuState, ProfileForm};
+
+pub struct MenuPlugin;
+
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_state::<MenuState>();
+        app.init_resource::<LobbyBrowser>();
+        app.init_resource::<CreateLobbyForm>();
+        app.init_resource::<ProfileForm>();
+        app.init_resource::<OptionsForm>();
+
+        app.add_systems(OnEnter(MenuState::LobbyBrowser), (setup_lobby_browser, request_lobby_list, show_cursor));
+        app.add_systems(OnExit(MenuState::LobbyBrowser), despawn_menu_screen);
+        app.add_systems(OnEnter(MenuState::CreateLobby), setup_create_lobby);
+        app.add_systems(OnExit(MenuState::CreateLobby), despawn_menu_screen);
+        app.add_systems(OnEnter(MenuState::EditProfile), setup_edit_profile);
+        app.add_systems(OnExit(MenuState::EditProfile), despawn_menu_screen);
+        app.add_systems(OnEnter(MenuState::InLobby), (setup_in_lobby, show_cursor));
+        app.add_systems(OnExit(MenuState::InLobby), despawn_menu_screen);
+        app.add_systems(OnEnter(MenuState::InGame), grab_cursor);
+        app.add_systems(OnEnter(MenuState::Options), (setup_options, show_cursor));
+        app.add_systems(OnExit(MenuState::Options), (despawn_menu_screen, save_client_settings));
+
+        app.add_systems(
+            Update,
+            (
+                menu_buttons,
+                follow_match_phase,
+                request_lobby_list_on_connect,
+                update_lobby_list.run_if(in_state(MenuState::LobbyBrowser)),
+                update_create_lobby_form.run_if(in_state(MenuState::CreateLobby)),
+                update_profile_form.run_if(in_state(MenuState::EditProfile)),
+                update_member_list.run_if(in_state(MenuState::InLobby)),
+                options_keys.run_if(in_state(MenuState::Options)).before(options_buttons),
+                options_buttons.run_if(in_state(MenuState::Options)),
+                update_options_text.run_if(in_state(MenuState::Options)),
+                // Runs first so the Escape that closes the chat doesn't also open the options
+                open_options_in_game.run_if(in_state(MenuState::InGame)).before(chat_window),
+            )
+        );
+    }
+}
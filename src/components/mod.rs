@@ -1,12 +1,23 @@
-use avian3d::prelude::PhysicsLayer;
+use avian3d::prelude::{CollisionLayers, LayerMask, PhysicsLayer};
 
+pub mod actions;
 pub mod chat;
+pub mod chat_command;
+pub mod chat_moderation;
 pub mod common;
 pub mod hud;
+pub mod killcam;
 pub mod lobby;
 pub mod player;
 pub mod camera;
 pub mod weapon;
+pub mod scoreboard;
+pub mod game_mode;
+pub mod menu;
+pub mod profile;
+pub mod prop;
+pub mod settings;
+pub mod spectator;
 
 #[derive(PhysicsLayer, Default, Debug, Copy, Clone)]
 pub enum CollisionLayer {
@@ -14,4 +25,14 @@ pub enum CollisionLayer {
     Ground,
     Player,
     Enemy,
-}
\ No newline at end of file
+    Prop,
+}
+// Layer bits above the named layers are handed out to lobbies, one per lobby
+const LOBBY_LAYER_OFFSET: u8 = 8;
+pub const MAX_LOBBY_PARTITIONS: u8 = 32 - LOBBY_LAYER_OFFSET;
+
+/// Collision layers for a body that should only interact with bodies of the same lobby partition
+pub fn lobby_collision_layers(layer: CollisionLayer, partition: u8) -> CollisionLayers {
+    let lobby_mask = LayerMask(1 << (LOBBY_LAYER_OFFSET + partition));
+    CollisionLayers::new(LayerMask::from(layer) | lobby_mask, lobby_mask)
+}
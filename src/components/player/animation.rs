@@ -1,24 +1,208 @@
-use bevy::animation::AnimationPlayer;
+use bevy::animation::{AnimationPlayer, AnimationTargetId};
 use bevy::asset::{AssetServer, Assets, Handle};
 use bevy::gltf::GltfAssetLabel;
-use bevy::prelude::{Added, AnimationGraph, AnimationGraphHandle, AnimationNodeIndex, ChildOf, Commands, Component, Entity, Local, Query, Res, ResMut, Resource, With};
+use bevy::math::{Vec2, Vec3};
+use bevy::prelude::{Added, AnimationGraph, AnimationGraphHandle, AnimationNodeIndex, AnimationTransitions, ChildOf, Children, Commands, Component, Entity, Local, Name, Query, Res, ResMut, Resource, Time, With};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::f32::consts::FRAC_PI_2;
+use std::fs;
+use std::time::Duration;
+use crate::components::player::controller::{MotorState, MovementSettings};
 use crate::components::player::PlayerMarker;
 
+const ANIMATION_FILE: &str = "assets/animations/player.toml";
+const PLAYER_MODEL: &str = "meshes\\player.glb";
+const DEFAULT_CROSSFADE: f32 = 0.2;
+const SPEED_EPSILON: f32 = 0.01;
+/// Clips scaled by ground speed never play faster than this
+const MAX_PLAYBACK_SPEED: f32 = 2.5;
+/// Mask group of every bone outside the upper body, the fire and aim layers leave it alone
+const LOWER_BODY_GROUP: u32 = 0;
+
+/// How one clip of the player model is played
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClipSettings {
+    /// Index of the animation in the model's glTF file
+    pub clip: usize,
+    /// Ground speed the clip was made for, playback speeds up or slows down to match. 0 always plays at normal speed
+    pub reference_speed: f32,
+    /// Plays through once and holds the last frame instead of looping
+    pub once: bool,
+    /// Weight of a layer against the locomotion underneath it
+    pub weight: f32,
+}
+
+impl Default for ClipSettings {
+    fn default() -> Self {
+        Self {
+            clip: 0,
+            reference_speed: 0.0,
+            once: false,
+            weight: 1.0,
+        }
+    }
+}
+
+/// The player's animation graph, read from `assets/animations/player.toml`
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnimationSettings {
+    /// Seconds one state takes to blend into the next
+    pub crossfade: f32,
+    /// Bone the upper body starts at. Without it the fire and aim layers move the whole body
+    pub upper_body_root: Option<String>,
+    /// States without a clip fall back to a similar one, see `AnimationState::fallback`
+    pub clips: HashMap<AnimationState, ClipSettings>,
+    /// Upper body layer played while fire is held
+    pub fire: Option<ClipSettings>,
+    /// Additive upper body poses blended in by how far up or down the player looks
+    pub aim_up: Option<ClipSettings>,
+    pub aim_down: Option<ClipSettings>,
+}
+
+impl Default for AnimationSettings {
+    fn default() -> Self {
+        Self {
+            crossfade: DEFAULT_CROSSFADE,
+            upper_body_root: None,
+            clips: HashMap::from([
+                (AnimationState::Idle, ClipSettings::default()),
+                (AnimationState::Walking, ClipSettings { clip: 1, reference_speed: 1.5, ..ClipSettings::default() }),
+            ]),
+            fire: None,
+            aim_up: None,
+            aim_down: None,
+        }
+    }
+}
+
+impl AnimationSettings {
+    /// Falls back to the idle and walk clips when the file is missing or broken
+    pub fn load() -> Self {
+        let contents = match fs::read_to_string(ANIMATION_FILE) {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("Couldn't read {}: {}", ANIMATION_FILE, e);
+                return Self::default();
+            }
+        };
+
+        match toml::from_str::<Self>(&contents) {
+            Ok(mut settings) => {
+                settings.crossfade = settings.crossfade.max(0.0);
+                settings
+            }
+            Err(e) => {
+                eprintln!("Invalid animation file {}: {}", ANIMATION_FILE, e);
+                Self::default()
+            }
+        }
+    }
+}
+
 #[derive(Resource)]
-pub struct PlayerAnimationGraph(Handle<AnimationGraph>);
+pub struct PlayerAnimationGraph {
+    handle: Handle<AnimationGraph>,
+    settings: AnimationSettings,
+    states: HashMap<AnimationState, AnimationNodeIndex>,
+    fire: Option<AnimationNodeIndex>,
+    aim_up: Option<AnimationNodeIndex>,
+    aim_down: Option<AnimationNodeIndex>,
+}
+
+impl PlayerAnimationGraph {
+    /// Node and clip a state plays, following the fallbacks when it has no clip of its own
+    fn resolve(&self, state: AnimationState) -> Option<(AnimationNodeIndex, ClipSettings)> {
+        let mut state = Some(state);
+        while let Some(s) = state {
+            if let (Some(node), Some(clip)) = (self.states.get(&s), self.settings.clips.get(&s)) {
+                return Some((*node, *clip));
+            }
+            state = s.fallback();
+        }
+        None
+    }
+}
 
 #[derive(Component)]
 pub struct AnimationPlayerLink(pub Entity);
 
-#[derive(Component)]
-pub struct PlayerAnimationState(pub AnimationState);
+/// What a player's model should be doing. Set by the server and replicated for other players, predicted for our own.
+#[derive(Component, Default)]
+pub struct PlayerAnimationState {
+    pub state: AnimationState,
+    /// Fire is held, plays the upper body fire layer
+    pub firing: bool,
+    /// Along the ground, scales the locomotion clips
+    pub speed: f32,
+    /// Look pitch the aim layer follows
+    pub pitch: f32,
+}
 
-#[derive(Serialize, Deserialize, Debug, Default, Copy, Clone, PartialEq)]
+/// Client side. What the model is actually playing, which lags behind the state while landing and blending.
+#[derive(Component, Default)]
+pub struct AnimationPlayback {
+    state: Option<AnimationState>,
+    node: Option<AnimationNodeIndex>,
+    fire_weight: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
 pub enum AnimationState {
     #[default]
     Idle,
     Walking,
+    Running,
+    Jumping,
+    Falling,
+    /// Only played by clients on touching down, the server never sends it
+    Landing,
+    Crouching,
+    Dead,
+}
+
+impl AnimationState {
+    /// Closest state to play instead when this one has no clip
+    fn fallback(self) -> Option<Self> {
+        match self {
+            AnimationState::Idle => None,
+            AnimationState::Running => Some(AnimationState::Walking),
+            AnimationState::Jumping => Some(AnimationState::Falling),
+            AnimationState::Walking
+            | AnimationState::Falling
+            | AnimationState::Landing
+            | AnimationState::Crouching
+            | AnimationState::Dead => Some(AnimationState::Idle),
+        }
+    }
+
+    fn airborne(self) -> bool {
+        matches!(self, AnimationState::Jumping | AnimationState::Falling)
+    }
+
+    fn on_ground(self) -> bool {
+        matches!(self, AnimationState::Idle | AnimationState::Walking | AnimationState::Running | AnimationState::Crouching)
+    }
+}
+
+/// Picks the state from the character's motor and velocity, the same way on the server and in prediction
+pub fn movement_animation(velocity: Vec3, motor: &MotorState, settings: &MovementSettings) -> AnimationState {
+    let speed = Vec2::new(velocity.x, velocity.z).length();
+
+    if !motor.grounded {
+        if motor.vertical_speed > 0.0 { AnimationState::Jumping } else { AnimationState::Falling }
+    } else if motor.crouching {
+        AnimationState::Crouching
+    } else if speed > (settings.walk_speed + settings.run_speed) / 2.0 {
+        AnimationState::Running
+    } else if speed > SPEED_EPSILON {
+        AnimationState::Walking
+    } else {
+        AnimationState::Idle
+    }
 }
 
 pub fn get_top_parent(
@@ -36,80 +220,163 @@ pub fn get_top_parent(
     curr_entity
 }
 
+/// Builds the graph once from the animation file. Locomotion states blend under an additive node
+/// so the aim poses go on top of them, fire blends in with the locomotion on the upper body only.
 pub fn setup_player_animations(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     mut animation_graphs: ResMut<Assets<AnimationGraph>>,
 ) {
-    let mut animation_graph = AnimationGraph::new();
-    animation_graph.add_clip(
-        asset_server.load(GltfAssetLabel::Animation(0).from_asset("meshes\\player.glb")),
-        1.0,
-        animation_graph.root
-    );
-    animation_graph.add_clip(
-        asset_server.load(GltfAssetLabel::Animation(1).from_asset("meshes\\player.glb")),
-        1.0,
-        animation_graph.root
-    );
-
-    let anim_graph_handle = animation_graphs.add(animation_graph);
-    commands.insert_resource(PlayerAnimationGraph(anim_graph_handle));
+    let settings = AnimationSettings::load();
+    let upper_body_mask = if settings.upper_body_root.is_some() { 1 << LOWER_BODY_GROUP } else { 0 };
+    let load = |clip: &ClipSettings| asset_server.load(GltfAssetLabel::Animation(clip.clip).from_asset(PLAYER_MODEL));
+
+    let mut graph = AnimationGraph::new();
+    let layers = graph.add_additive_blend(1.0, graph.root);
+    let base = graph.add_blend(1.0, layers);
+
+    let states = settings.clips.iter().map(|(state, clip)| (*state, graph.add_clip(load(clip), 1.0, base))).collect();
+    let fire = settings.fire.as_ref().map(|clip| graph.add_clip_with_mask(load(clip), upper_body_mask, 0.0, base));
+    let aim_up = settings.aim_up.as_ref().map(|clip| graph.add_clip_with_mask(load(clip), upper_body_mask, 0.0, layers));
+    let aim_down = settings.aim_down.as_ref().map(|clip| graph.add_clip_with_mask(load(clip), upper_body_mask, 0.0, layers));
+
+    commands.insert_resource(PlayerAnimationGraph {
+        handle: animation_graphs.add(graph),
+        settings,
+        states,
+        fire,
+        aim_up,
+        aim_down,
+    });
+}
+
+/// Puts every bone outside the upper body into the lower body mask group
+fn mask_lower_body(
+    graph: &mut AnimationGraph,
+    entity: Entity,
+    upper_body_root: &str,
+    in_upper_body: bool,
+    path: &mut Vec<Name>,
+    children: &Query<&Children>,
+    names: &Query<&Name>,
+) {
+    let Ok(name) = names.get(entity) else {
+        return;
+    };
+    let in_upper_body = in_upper_body || name.as_str() == upper_body_root;
+
+    path.push(name.clone());
+    if !in_upper_body {
+        graph.add_target_to_mask_group(AnimationTargetId::from_names(path.iter()), LOWER_BODY_GROUP);
+    }
+    for child in children.get(entity).into_iter().flat_map(|c| c.iter()) {
+        mask_lower_body(graph, child, upper_body_root, in_upper_body, path, children, names);
+    }
+    path.pop();
 }
 
 pub fn player_animations(
     mut commands: Commands,
-    mut query: Query<(Entity, &mut AnimationPlayer), Added<AnimationPlayer>>,
-    mut all_parents_query: Query<&ChildOf>,
+    query: Query<Entity, Added<AnimationPlayer>>,
+    all_parents_query: Query<&ChildOf>,
+    children: Query<&Children>,
+    names: Query<&Name>,
     animation_graph: Res<PlayerAnimationGraph>,
-    mut done: Local<bool>
+    mut animation_graphs: ResMut<Assets<AnimationGraph>>,
+    mut masked: Local<bool>,
 ) {
-    // if *done {
-    //     return;
-    // }
-    for (entity, mut player) in query.iter_mut() {
+    for entity in query.iter() {
         println!("Animation Player Found");
 
-        commands.entity(entity).insert((
-            AnimationGraphHandle(animation_graph.0.clone()),
-        ));
-        for i in 1..3 {
-            player.play(AnimationNodeIndex::new(i)).repeat();
+        // Every player shares the skeleton, so the first one to load is enough to find the bones
+        if !*masked {
+            if let (Some(root), Some(graph)) = (&animation_graph.settings.upper_body_root, animation_graphs.get_mut(&animation_graph.handle)) {
+                mask_lower_body(graph, entity, root, false, &mut Vec::new(), &children, &names);
+            }
+            *masked = true;
         }
 
-        let top_entity = get_top_parent(entity, &mut all_parents_query);
-        commands.entity(top_entity).log_components();
-        commands.entity(top_entity).insert(AnimationPlayerLink(entity));
+        commands.entity(entity).insert((
+            AnimationGraphHandle(animation_graph.handle.clone()),
+            AnimationTransitions::new(),
+        ));
 
-        // *done = true;
+        let top_entity = get_top_parent(entity, &all_parents_query);
+        commands.entity(top_entity).insert((AnimationPlayerLink(entity), AnimationPlayback::default()));
     }
 }
 
+/// Crossfades into the current state, scales it by ground speed and blends the fire and aim layers on top
 pub fn animation_control(
-    mut commands: Commands,
-    mut animation_players: Query<&mut AnimationPlayer>,
-    mut player_anim_state: Query<(&mut PlayerAnimationState, &AnimationPlayerLink), With<PlayerMarker>>,
+    mut animation_players: Query<(&mut AnimationPlayer, &mut AnimationTransitions)>,
+    mut players: Query<(&PlayerAnimationState, &AnimationPlayerLink, &mut AnimationPlayback), With<PlayerMarker>>,
+    animation_graph: Res<PlayerAnimationGraph>,
+    time: Res<Time>,
 ) {
-    for player_state in player_anim_state.iter_mut() {
-        if let Some(mut anim_play) = animation_players.get_mut(player_state.1.0).ok() {
-            match player_state.0.0 {
-                AnimationState::Idle => {
-                    if let Some(idle_anim) = anim_play.animation_mut(AnimationNodeIndex::new(1)) {
-                        idle_anim.set_weight(1.0);
-                    }
-                    if let Some(walking_anim) = anim_play.animation_mut(AnimationNodeIndex::new(2)) {
-                        walking_anim.set_weight(0.0);
-                    }
-                }
-                AnimationState::Walking => {
-                    if let Some(idle_anim) = anim_play.animation_mut(AnimationNodeIndex::new(1)) {
-                        idle_anim.set_weight(0.0);
-                    }
-                    if let Some(walking_anim) = anim_play.animation_mut(AnimationNodeIndex::new(2)) {
-                        walking_anim.set_weight(1.0);
-                    }
+    let crossfade = animation_graph.settings.crossfade;
+
+    for (anim_state, link, mut playback) in players.iter_mut() {
+        let Ok((mut player, mut transitions)) = animation_players.get_mut(link.0) else {
+            continue;
+        };
+
+        let landing_done = playback.node.and_then(|node| player.animation(node)).is_none_or(|a| a.is_finished());
+        let target = match (playback.state, anim_state.state) {
+            // Touching down plays the landing before whatever comes next
+            (Some(previous), next) if previous.airborne() && next.on_ground() && animation_graph.states.contains_key(&AnimationState::Landing) => AnimationState::Landing,
+            (Some(AnimationState::Landing), next) if next.on_ground() && !landing_done => AnimationState::Landing,
+            (_, next) => next,
+        };
+
+        let Some((node, clip)) = animation_graph.resolve(target) else {
+            continue;
+        };
+
+        // States sharing a clip through their fallbacks carry on without restarting it
+        if playback.node != Some(node) {
+            let active = transitions.play(&mut player, node, Duration::from_secs_f32(crossfade));
+            if !clip.once {
+                active.repeat();
+            }
+            playback.node = Some(node);
+        }
+        playback.state = Some(target);
+
+        if clip.reference_speed > 0.0 {
+            if let Some(active) = player.animation_mut(node) {
+                active.set_speed((anim_state.speed / clip.reference_speed).clamp(0.0, MAX_PLAYBACK_SPEED));
+            }
+        }
+
+        let alive = anim_state.state != AnimationState::Dead;
+
+        if let (Some(fire), Some(fire_clip)) = (animation_graph.fire, animation_graph.settings.fire) {
+            let target_weight = if anim_state.firing && alive { fire_clip.weight } else { 0.0 };
+            let step = if crossfade > 0.0 { fire_clip.weight * time.delta_secs() / crossfade } else { f32::MAX };
+            playback.fire_weight = if playback.fire_weight < target_weight {
+                (playback.fire_weight + step).min(target_weight)
+            } else {
+                (playback.fire_weight - step).max(target_weight)
+            };
+
+            if playback.fire_weight > 0.0 {
+                let active = player.play(fire);
+                active.set_weight(playback.fire_weight);
+                if !fire_clip.once {
+                    active.repeat();
                 }
+            } else {
+                player.stop(fire);
+            }
+        }
+
+        // Positive pitch looks down
+        let aim = if alive { (anim_state.pitch / FRAC_PI_2).clamp(-1.0, 1.0) } else { 0.0 };
+        let settings = &animation_graph.settings;
+        for (node, clip, amount) in [(animation_graph.aim_up, settings.aim_up, -aim), (animation_graph.aim_down, settings.aim_down, aim)] {
+            if let (Some(node), Some(clip)) = (node, clip) {
+                player.play(node).repeat().set_weight(amount.max(0.0) * clip.weight);
             }
         }
     }
-}
\ No newline at end of file
+}
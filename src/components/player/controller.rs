@@ -0,0 +1,367 @@
+use avian3d::math::Quaternion;
+use avian3d::prelude::{Collider, ShapeCastConfig, ShapeHitData, SpatialQueryFilter, SpatialQueryPipeline};
+use bevy::math::EulerRot::YXZ;
+use bevy::prelude::{Component, Dir3, Entity, Quat, Vec3};
+use serde::{Deserialize, Serialize};
+use crate::components::actions::{ActionFlags, AnalogMove};
+
+pub const DEFAULT_WALK_SPEED: f32 = 1.5;
+pub const DEFAULT_RUN_SPEED: f32 = 5.0;
+pub const DEFAULT_CROUCH_SPEED: f32 = 0.8;
+/// Upward speed a jump starts with, about a metre high with the default gravity
+pub const DEFAULT_JUMP_SPEED: f32 = 4.5;
+pub const DEFAULT_GRAVITY: f32 = 9.81;
+
+const CAPSULE_RADIUS: f32 = 0.5;
+const STAND_LENGTH: f32 = 1.0;
+const CROUCH_LENGTH: f32 = 0.2;
+/// How far the centre drops when crouching on the ground so the feet stay where they were
+const CROUCH_OFFSET: f32 = (STAND_LENGTH - CROUCH_LENGTH) / 2.0;
+
+// Seconds
+const COYOTE_TIME: f32 = 0.1;
+const JUMP_BUFFER_TIME: f32 = 0.15;
+
+/// Gap kept between the capsule and whatever it touches so the next cast doesn't start inside it
+const SKIN: f32 = 0.02;
+const GROUND_CHECK_DISTANCE: f32 = 0.05;
+/// How far down the player is pulled to stay on the ground when walking down slopes and steps
+const GROUND_SNAP: f32 = 0.15;
+/// Surfaces flatter than this are walkable, about 45 degrees
+const GROUND_NORMAL_Y: f32 = 0.7;
+/// Tallest ledge walked up without jumping
+const STEP_HEIGHT: f32 = 0.35;
+/// Each hit turns the remaining motion along the surface, corners need more than one
+const MAX_SLIDE_ITERATIONS: usize = 4;
+const MIN_MOVE: f32 = 0.0001;
+/// How quickly being shoved by another player wears off, per second
+const PUSH_DAMPING: f32 = 8.0;
+
+/// Movement constants the server simulates with, clients predict with the same values
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct MovementSettings {
+    pub walk_speed: f32,
+    pub run_speed: f32,
+    pub crouch_speed: f32,
+    pub jump_speed: f32,
+    pub gravity: f32,
+}
+
+impl Default for MovementSettings {
+    fn default() -> Self {
+        Self {
+            walk_speed: DEFAULT_WALK_SPEED,
+            run_speed: DEFAULT_RUN_SPEED,
+            crouch_speed: DEFAULT_CROUCH_SPEED,
+            jump_speed: DEFAULT_JUMP_SPEED,
+            gravity: DEFAULT_GRAVITY,
+        }
+    }
+}
+
+/// Controller state carried between ticks. Everything the step reads lives here or in the input so a
+/// resimulation from the same state gives the same result.
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct MotorState {
+    pub grounded: bool,
+    pub crouching: bool,
+    /// Time left to jump after walking off a ledge
+    pub coyote_time: f32,
+    /// Time left for a jump pressed just before landing to still happen
+    pub jump_buffer: f32,
+    /// Jump only triggers on the press, holding it doesn't bunny hop
+    pub jump_held: bool,
+    /// Kept apart from the returned velocity, which also has step ups and ground snapping in it
+    pub vertical_speed: f32,
+    /// Velocity of the platform stood on, kept after stepping or jumping off it
+    pub carried_velocity: Vec3,
+    /// Shoves from other players, wearing off over time
+    pub push_velocity: Vec3,
+}
+
+/// Something the character ran into during a step, so the server can push it
+#[derive(Clone, Copy, Debug)]
+pub struct CharacterHit {
+    pub entity: Entity,
+    /// Surface normal, pointing back at the character
+    pub normal: Vec3,
+    /// How fast the character was moving into it
+    pub speed: f32,
+}
+
+pub fn player_collider(crouching: bool) -> Collider {
+    Collider::capsule(CAPSULE_RADIUS, if crouching { CROUCH_LENGTH } else { STAND_LENGTH })
+}
+
+/// Moves the motor on by one fixed tick and returns the velocity that carries the player to where they end up.
+/// The caller integrates the position with it, the server lets physics do that so pushed bodies see the motion.
+/// `ground_velocity` gives the velocity of a body stood on, `hits` is filled with everything run into.
+pub fn step_character(
+    actions: ActionFlags,
+    movement: AnalogMove,
+    yaw: f32,
+    motor: &mut MotorState,
+    position: &mut Vec3,
+    settings: &MovementSettings,
+    spatial_query: &SpatialQueryPipeline,
+    filter: &SpatialQueryFilter,
+    ground_velocity: &dyn Fn(Entity) -> Vec3,
+    hits: &mut Vec<CharacterHit>,
+    dt: f32,
+) -> Vec3 {
+    hits.clear();
+    update_crouch(actions.contains(ActionFlags::CROUCH), motor, position, spatial_query, filter);
+    let collider = player_collider(motor.crouching);
+
+    let ground = if motor.vertical_speed <= 0.0 {
+        ground_below(&collider, *position, GROUND_CHECK_DISTANCE, spatial_query, filter)
+    } else {
+        None
+    };
+    motor.grounded = ground.is_some();
+    if let Some(ground) = &ground {
+        motor.carried_velocity = ground_velocity(ground.entity);
+    }
+
+    motor.coyote_time = if motor.grounded { COYOTE_TIME } else { (motor.coyote_time - dt).max(0.0) };
+
+    let jump = actions.contains(ActionFlags::JUMP);
+    motor.jump_buffer = if jump && !motor.jump_held { JUMP_BUFFER_TIME } else { (motor.jump_buffer - dt).max(0.0) };
+    motor.jump_held = jump;
+
+    let speed = if motor.crouching {
+        settings.crouch_speed
+    } else if actions.contains(ActionFlags::SPRINT) {
+        settings.run_speed
+    } else {
+        settings.walk_speed
+    };
+
+    // Half tilted sticks walk slower, keys always give a full length direction
+    let direction = movement.to_vec2();
+    let horizontal = Quat::from_euler(YXZ, yaw, 0.0, 0.0).mul_vec3(Vec3::new(direction.x, 0.0, -direction.y)) * speed;
+
+    motor.push_velocity *= (1.0 - PUSH_DAMPING * dt).max(0.0);
+    let horizontal = horizontal + motor.push_velocity;
+
+    if motor.grounded {
+        motor.vertical_speed = 0.0;
+    } else {
+        motor.vertical_speed -= settings.gravity * dt;
+    }
+
+    let mut jumped = false;
+    if motor.jump_buffer > 0.0 && motor.coyote_time > 0.0 && !motor.crouching {
+        motor.vertical_speed = settings.jump_speed;
+        motor.jump_buffer = 0.0;
+        motor.coyote_time = 0.0;
+        motor.grounded = false;
+        jumped = true;
+    }
+
+    let start = *position;
+    let mut end = if motor.grounded {
+        walk(&collider, start, horizontal * dt, spatial_query, filter, hits)
+    } else {
+        // Leaving a platform keeps its speed, a rising one throws the player up
+        motor.vertical_speed += motor.carried_velocity.y;
+        motor.carried_velocity.y = 0.0;
+
+        let motion = (horizontal + motor.carried_velocity + Vec3::Y * motor.vertical_speed) * dt;
+        let end = slide(&collider, start, motion, false, spatial_query, filter, hits);
+
+        // Whatever stopped the player going up or down takes that speed away, landing included
+        let moved_y = (end.y - start.y) / dt;
+        if motor.vertical_speed > 0.0 {
+            motor.vertical_speed = motor.vertical_speed.min(moved_y.max(0.0));
+        } else {
+            motor.vertical_speed = motor.vertical_speed.max(moved_y.min(0.0));
+        }
+        end
+    };
+
+    // Walking off a step or down a slope keeps the feet on the ground instead of launching off it
+    if motor.grounded && !jumped {
+        match ground_below(&collider, end, GROUND_SNAP, spatial_query, filter) {
+            Some(hit) => end.y -= (hit.distance - SKIN).max(0.0),
+            None => motor.grounded = false,
+        }
+    }
+
+    // The platform moves by the same amount in this tick's physics step, so it's left out of the sweep
+    if let Some(ground) = ground.filter(|_| motor.grounded) {
+        let mut riding = filter.clone();
+        riding.excluded_entities.insert(ground.entity);
+        end = slide(&collider, end, motor.carried_velocity * dt, false, spatial_query, &riding, hits);
+    }
+
+    for hit in hits.iter_mut() {
+        hit.speed /= dt;
+    }
+
+    (end - start) / dt
+}
+
+/// Ground movement. Tries the move as it is and lifted by a step, and keeps whichever gets further.
+fn walk(
+    collider: &Collider,
+    start: Vec3,
+    motion: Vec3,
+    spatial_query: &SpatialQueryPipeline,
+    filter: &SpatialQueryFilter,
+    hits: &mut Vec<CharacterHit>,
+) -> Vec3 {
+    let mut flat_hits = Vec::new();
+    let flat = slide(collider, start, motion, true, spatial_query, filter, &mut flat_hits);
+
+    if let Some((stepped, step_hits)) = step_up(collider, start, motion, spatial_query, filter) {
+        if horizontal_distance(start, stepped) > horizontal_distance(start, flat) + MIN_MOVE {
+            hits.extend(step_hits);
+            return stepped;
+        }
+    }
+
+    hits.extend(flat_hits);
+    flat
+}
+
+/// The move lifted by up to a step and put back down. Only counts if there is walkable ground to land on.
+fn step_up(
+    collider: &Collider,
+    start: Vec3,
+    motion: Vec3,
+    spatial_query: &SpatialQueryPipeline,
+    filter: &SpatialQueryFilter,
+) -> Option<(Vec3, Vec<CharacterHit>)> {
+    if motion.length() < MIN_MOVE {
+        return None;
+    }
+
+    let lift = sweep(collider, start, Dir3::Y, STEP_HEIGHT, spatial_query, filter)
+        .map_or(STEP_HEIGHT, |hit| (hit.distance - SKIN).max(0.0));
+    if lift < MIN_MOVE {
+        return None;
+    }
+
+    let mut hits = Vec::new();
+    let raised = slide(collider, start + Vec3::Y * lift, motion, true, spatial_query, filter, &mut hits);
+    let landing = ground_below(collider, raised, lift, spatial_query, filter)?;
+    Some((raised - Vec3::Y * (landing.distance - SKIN).max(0.0), hits))
+}
+
+fn horizontal_distance(a: Vec3, b: Vec3) -> f32 {
+    Vec3::new(b.x - a.x, 0.0, b.z - a.z).length()
+}
+
+/// Collide and slide. Moves until something is hit, then carries on with what's left of the motion along the surface.
+fn slide(
+    collider: &Collider,
+    start: Vec3,
+    motion: Vec3,
+    on_ground: bool,
+    spatial_query: &SpatialQueryPipeline,
+    filter: &SpatialQueryFilter,
+    hits: &mut Vec<CharacterHit>,
+) -> Vec3 {
+    let mut position = start;
+    let mut remaining = motion;
+    let mut last_normal: Option<Vec3> = None;
+
+    for _ in 0..MAX_SLIDE_ITERATIONS {
+        let Ok((direction, distance)) = Dir3::new_and_length(remaining) else {
+            break;
+        };
+        if distance < MIN_MOVE {
+            break;
+        }
+
+        let Some(hit) = sweep(collider, position, direction, distance + SKIN, spatial_query, filter) else {
+            position += remaining;
+            break;
+        };
+
+        let travel = (hit.distance - SKIN).clamp(0.0, distance);
+        position += direction * travel;
+        remaining = direction * (distance - travel);
+
+        let mut normal = hit.normal1;
+        if on_ground && normal.y <= GROUND_NORMAL_Y {
+            // Too steep to walk up, treat it as a wall so sliding along it can't climb it
+            normal = Vec3::new(normal.x, 0.0, normal.z).normalize_or_zero();
+        }
+
+        let into_surface = -direction.dot(normal) * distance;
+        if into_surface > 0.0 {
+            hits.push(CharacterHit { entity: hit.entity, normal, speed: into_surface });
+        }
+        remaining -= normal * remaining.dot(normal).min(0.0);
+
+        // Wedged between two surfaces, only the crease between them is left to move along
+        if let Some(last) = last_normal {
+            if remaining.dot(last) < 0.0 {
+                let crease = last.cross(normal).normalize_or_zero();
+                remaining = crease * remaining.dot(crease);
+            }
+        }
+        last_normal = Some(normal);
+    }
+
+    position
+}
+
+fn sweep(
+    collider: &Collider,
+    origin: Vec3,
+    direction: Dir3,
+    distance: f32,
+    spatial_query: &SpatialQueryPipeline,
+    filter: &SpatialQueryFilter,
+) -> Option<ShapeHitData> {
+    spatial_query.cast_shape(
+        collider,
+        origin,
+        Quaternion::default(),
+        direction,
+        &ShapeCastConfig {
+            max_distance: distance,
+            target_distance: 0.0,
+            compute_contact_on_penetration: false,
+            ignore_origin_penetration: true,
+        },
+        filter,
+    )
+}
+
+/// Walkable ground within the distance under the capsule
+fn ground_below(
+    collider: &Collider,
+    position: Vec3,
+    distance: f32,
+    spatial_query: &SpatialQueryPipeline,
+    filter: &SpatialQueryFilter,
+) -> Option<ShapeHitData> {
+    sweep(collider, position, Dir3::NEG_Y, distance + SKIN, spatial_query, filter).filter(|hit| hit.normal1.y > GROUND_NORMAL_Y)
+}
+
+/// Crouching always works, standing back up only if the taller capsule fits
+fn update_crouch(
+    wants_crouch: bool,
+    motor: &mut MotorState,
+    position: &mut Vec3,
+    spatial_query: &SpatialQueryPipeline,
+    filter: &SpatialQueryFilter,
+) {
+    // In the air the capsule shrinks towards its centre instead, so tucking the legs in clears ledges
+    let offset = if motor.grounded { CROUCH_OFFSET } else { 0.0 };
+
+    if wants_crouch && !motor.crouching {
+        motor.crouching = true;
+        position.y -= offset;
+    } else if !wants_crouch && motor.crouching {
+        let standing = *position + Vec3::Y * offset;
+        if spatial_query.shape_intersections(&player_collider(false), standing, Quaternion::default(), filter).is_empty() {
+            motor.crouching = false;
+            *position = standing;
+        }
+    }
+}
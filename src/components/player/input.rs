@@ -1,14 +1,28 @@
 use bevy::input::keyboard::KeyboardInput;
 use bevy::input::mouse::{AccumulatedMouseMotion, MouseMotion};
-use bevy::prelude::{ButtonInput, KeyCode, Res, ResMut, Vec2};
+use bevy::prelude::{Local, Res, ResMut, State, Time, Vec2};
+use crate::components::actions::{ActionFlags, Actions, AnalogMove};
+use crate::components::chat::ChatInput;
+use crate::components::menu::MenuState;
 use crate::components::player::{MovementState, PlayerInfo};
+use crate::components::settings::ClientSettings;
+
+// How far over the look stick has to be before it starts speeding up, and how long until it's at full speed
+const LOOK_ACCELERATION_THRESHOLD: f32 = 0.9;
+const LOOK_ACCELERATION_TIME: f32 = 1.0;
 
 pub fn input_system(
     mouse_input: Res<AccumulatedMouseMotion>,
-    keyboard_input: Res<ButtonInput<KeyCode>>,
+    actions: Actions,
+    chat_input: Res<ChatInput>,
+    settings: Res<ClientSettings>,
+    menu_state: Res<State<MenuState>>,
+    time: Res<Time>,
     mut player_info: ResMut<PlayerInfo>,
+    mut look_held: Local<f32>,
 ) {
-    player_info.player_inputs = 0;
+    player_info.player_inputs = ActionFlags::empty();
+    player_info.move_input = AnalogMove::default();
     player_info.player_movement_state.clear();
     
     // Accumulated mouse delta was one frame off
@@ -17,37 +31,62 @@ pub fn input_system(
         player_info.accumulated_mouse_delta = player_info.mouse_delta;
     }
     
-    player_info.mouse_delta = mouse_input.delta.into();
-    
-    player_info.accumulated_mouse_delta += mouse_input.delta;
+    // The camera stays put while the options menu is open
+    let options_open = *menu_state.get() == MenuState::Options;
+    let invert_y = if settings.mouse.invert_y { -1.0 } else { 1.0 };
+    let mouse_delta = if options_open {
+        Vec2::ZERO
+    } else {
+        mouse_input.delta * Vec2::new(1.0, invert_y) * settings.mouse.sensitivity + stick_look(&actions, &settings, &time, &mut look_held)
+    };
+
+    player_info.mouse_delta = mouse_delta.into();
     
-    if keyboard_input.pressed(KeyCode::KeyW) {
-        player_info.player_inputs |= 1;
-        player_info.player_movement_state.insert(MovementState::Walking);
-    }
-    if keyboard_input.pressed(KeyCode::KeyS) {
-        player_info.player_inputs |= 2;
-        player_info.player_movement_state.insert(MovementState::Walking);
-    }
-    if keyboard_input.pressed(KeyCode::KeyD) {
-        player_info.player_inputs |= 4;
-        player_info.player_movement_state.insert(MovementState::Walking);
+    player_info.accumulated_mouse_delta += mouse_delta;
+
+    // Keys typed into the chat shouldn't move the player
+    if chat_input.active || options_open {
+        player_info.player_movement_state.insert(MovementState::Idle);
+        return;
     }
-    if keyboard_input.pressed(KeyCode::KeyA) {
-        player_info.player_inputs |= 8;
+    
+    let inputs = actions.flags();
+    player_info.player_inputs = inputs;
+
+    // Whichever of the keys and the stick is pushed further wins
+    let keys = inputs.move_direction();
+    let stick = actions.move_stick();
+    player_info.move_input = AnalogMove::from_vec2(if stick.length() > keys.length() { stick } else { keys });
+
+    if !player_info.move_input.is_zero() {
         player_info.player_movement_state.insert(MovementState::Walking);
     }
-    if keyboard_input.pressed(KeyCode::Space) {
-        player_info.player_inputs |= 16;
+    if inputs.contains(ActionFlags::JUMP) {
         player_info.player_movement_state.insert(MovementState::Jumping);
     }
-    if keyboard_input.pressed(KeyCode::ShiftLeft) {
-        player_info.player_inputs |= 32;
+    if inputs.contains(ActionFlags::SPRINT) {
         player_info.player_movement_state.insert(MovementState::Running);
         player_info.player_movement_state.remove(&MovementState::Walking);
     }
     
-    if player_info.player_inputs == 0 {
+    if inputs.is_empty() && player_info.move_input.is_zero() {
         player_info.player_movement_state.insert(MovementState::Idle);
     }
-}
\ No newline at end of file
+}
+
+/// Right stick turned into the same units as mouse movement, speeding up while it's held all the way over
+fn stick_look(actions: &Actions, settings: &ClientSettings, time: &Time, look_held: &mut f32) -> Vec2 {
+    let controller = &settings.controller;
+    let stick = actions.look_stick();
+
+    if stick.length() >= LOOK_ACCELERATION_THRESHOLD {
+        *look_held += time.delta_secs();
+    } else {
+        *look_held = 0.0;
+    }
+    let acceleration = 1.0 + controller.look_acceleration * look_held.min(LOOK_ACCELERATION_TIME) / LOOK_ACCELERATION_TIME;
+
+    // Pushing the stick up looks up, which is the opposite of moving the mouse up
+    let invert_y = if controller.invert_y { 1.0 } else { -1.0 };
+    Vec2::new(stick.x, stick.y * invert_y) * controller.look_speed * acceleration * time.delta_secs()
+}
@@ -1,15 +1,17 @@
 pub mod animation;
+pub mod controller;
 pub mod plugin;
 mod input;
 
+use crate::components::actions::{ActionFlags, AnalogMove};
 use crate::components::common::Id;
 use crate::components::hud::Hud;
 use crate::network::net_manage::UdpConnection;
-use crate::network::net_message::{BitMask, NetworkMessage, SequenceNumber, CUdpType, SUdpType};
+use crate::network::net_message::{NetworkMessage, SequenceNumber, CUdpType, SUdpType};
 use crate::network::net_reconciliation::{StateTimeline, ObjectState, MISS_PREDICT_LIMIT, BUFFER_SIZE, get_next_sequence_num};
 use bevy::asset::{AssetServer, Assets};
 use bevy::input::ButtonInput;
-use bevy::prelude::{error, info, warn, AnimationGraph, AnimationGraphHandle, AnimationNodeIndex, AnimationPlayer, Camera, Capsule3d, ChildOf, Command, Component, Dir3, Entity, Gizmos, GlobalTransform, Handle, Local, Node, Reflect, Resource, Scene, SceneRoot, Single, Time, Val, Vec2, Vec3, World};
+use bevy::prelude::{error, info, warn, Has, Without, AnimationGraph, AnimationGraphHandle, AnimationNodeIndex, AnimationPlayer, Camera, Capsule3d, ChildOf, Command, Component, Dir3, Entity, Fixed, Gizmos, GlobalTransform, Handle, Local, Node, Reflect, Resource, Scene, SceneRoot, Single, Time, Val, Vec2, Vec3, World};
 use bevy::prelude::{
     Camera3d, Commands, KeyCode, Mesh3d, MeshMaterial3d, Query, ReflectResource, Res, ResMut, Text, TextLayout, Transform, With,
 };
@@ -18,7 +20,7 @@ use std::collections::{HashMap, HashSet, VecDeque};
 use std::f32::consts::PI;
 use std::time::Duration;
 use avian3d::math::Quaternion;
-use avian3d::prelude::{Collider, CollisionLayers, Friction, LayerMask, LinearVelocity, LockedAxes, Physics, PhysicsSchedule, Position, RigidBody, Rotation, ShapeCastConfig, Sleeping, SpatialQueryFilter, SpatialQueryPipeline};
+use avian3d::prelude::{Collider, ColliderDisabled, CollisionLayers, ComputedMass, Friction, LayerMask, LinearVelocity, LockedAxes, Physics, PhysicsSchedule, Position, RigidBody, Rotation, ShapeCastConfig, Sleeping, SpatialQueryFilter, SpatialQueryPipeline};
 use bevy::color::palettes::basic::{BLACK, PURPLE, WHITE};
 use bevy::color::palettes::css::{RED, YELLOW};
 use bevy::gltf::GltfAssetLabel;
@@ -30,9 +32,13 @@ use bevy::text::{FontSmoothing, TextFont};
 use bevy::ui::PositionType;
 use bevy::utils::default;
 use crate::client_plugin::DefaultFont;
-use crate::components::camera::{apply_player_camera_input, CameraInfo};
+use crate::components::camera::{apply_look_input, apply_player_camera_input, CameraInfo};
+use crate::components::player::controller::{player_collider, step_character, MotorState, MovementSettings};
+use crate::components::prop::{prop_velocity, push_prop, Prop, Pushable};
+use crate::config::ServerConfig;
 use crate::components::CollisionLayer;
-use crate::components::player::animation::{AnimationState, PlayerAnimationState};
+use crate::components::player::animation::{movement_animation, AnimationState, PlayerAnimationState};
+use crate::components::spectator::Spectator;
 use crate::network::net_reconciliation::StateType::{Input, Player};
 
 #[derive(Reflect, Hash, PartialEq, Eq, Clone, Copy, Debug)]
@@ -47,10 +53,18 @@ pub enum MovementState {
 #[derive(Resource, Default)]
 pub struct PlayerInfo {
     pub current_player_id: Id,
-    pub player_inputs: BitMask,
+    /// Token sent with every UDP message so the server can tell which player it came from
+    pub session_token: u64,
+    pub player_inputs: ActionFlags,
+    /// Keyboard or stick direction as sent to the server
+    pub move_input: AnalogMove,
     pub mouse_delta: Vec2,
     pub accumulated_mouse_delta: Vec2,
     pub player_movement_state: HashSet<MovementState>,
+    /// Movement constants the server simulates with, predicted with the same values
+    pub movement: MovementSettings,
+    /// Width and depth of the server's ground, the client's floor is built to match
+    pub ground_size: [f32; 2],
 }
 
 #[derive(Component, Default, Debug, Copy, Clone)]
@@ -59,18 +73,29 @@ pub struct PredictedPlayerState {
     pub predicted_linear_velocity: Vec3,
     pub predicted_yaw: f32,
     pub predicted_pitch: f32,
+    pub motor: MotorState,
 }
 
 #[derive(Component)]
 pub struct PlayerMarker;
 
+/// Killed and waiting to spawn again, the collider is disabled until then.
+/// Only the server counts down, clients use it to tell who is alive.
+#[derive(Component, Default, Debug)]
+pub struct Dead {
+    pub respawn_in: f32,
+}
+
 #[derive(Serialize, Deserialize, Debug, Default, Copy, Clone, PartialEq)]
 pub struct PlayerState {
     pub position: Vec3,
     pub linear_velocity: Vec3,
     pub yaw: f32,
     pub pitch: f32,
-    pub animation_state: AnimationState
+    pub animation_state: AnimationState,
+    pub crouching: bool,
+    pub alive: bool,
+    pub firing: bool,
 }
 
 // pub struct ResimulatePlayer {
@@ -78,24 +103,31 @@ pub struct PlayerState {
 //     pub object_states: Vec<ObjectState>,
 // }
 
+#[derive(Clone, Copy, Default)]
 pub struct PlayerInput {
-    pub keymask: BitMask,
+    pub actions: ActionFlags,
+    pub movement: AnalogMove,
     pub mouse_delta: Vec2,
 }
 
 #[derive(Component, Default)]
 pub struct PendingInputs {
     pub buffer: VecDeque<PlayerInput>,
+    /// Repeated on ticks where no input arrived so a late packet doesn't stop the player
+    pub last: PlayerInput,
 }
 
 impl PlayerState {
-    pub fn new(position: Vec3, linear_velocity: Vec3, yaw: f32, pitch: f32, animation_state: AnimationState) -> Self {
+    pub fn new(position: Vec3, linear_velocity: Vec3, yaw: f32, pitch: f32, animation_state: AnimationState, crouching: bool, alive: bool, firing: bool) -> Self {
         Self {
             position,
             linear_velocity,
             yaw,
             pitch,
-            animation_state
+            animation_state,
+            crouching,
+            alive,
+            firing,
         }
     }
 }
@@ -289,125 +321,114 @@ impl PlayerState {
 pub fn set_player_id(
     player_info: &mut ResMut<PlayerInfo>,
     player_id: Id,
+    session_token: u64,
+    movement: MovementSettings,
+    tick_rate: f64,
+    fixed_time: &mut Time<Fixed>,
     reconcile_buffer: &mut StateTimeline
 ) {
     player_info.current_player_id = player_id;
-    reconcile_buffer.history.clear()
+    player_info.session_token = session_token;
+    player_info.movement = movement;
+    client_set_tick_rate(tick_rate, fixed_time, reconcile_buffer);
 }
 
-const WALK_SPEED: f32 = 1.5;
-const RUN_SPEED: f32 = 5.0;
-
-const GRAVITY: f32 = 9.81;
-const SKIN: f32 = 0.02;
-const GROUND_SNAP: f32 = 0.15;
-const GROUND_NORMAL_Y: f32 = 0.7;
-
-fn apply_gravity(
-    linear_velocity: &mut Vec3,
-    time: &Time,
-){
-    linear_velocity.y -= GRAVITY * time.delta_secs();
+/// Prediction steps once per input, so it has to tick as often as the server consumes them
+pub fn client_set_tick_rate(tick_rate: f64, fixed_time: &mut Time<Fixed>, reconcile_buffer: &mut StateTimeline) {
+    fixed_time.set_timestep_hz(tick_rate);
+    // Steps predicted at the old rate can't be replayed at the new one
+    reconcile_buffer.history.clear()
 }
 
-fn apply_constraint_solver(
-    spatial_query: &Res<SpatialQueryPipeline>,
-    player_predicted_state: &mut PredictedPlayerState,
-    collider: &Collider,
-    // gizmos: &mut Gizmos,
-    time: &Time,
+pub fn respawn_player(
+    position: crate::components::common::Vec3,
+    player_info: &PlayerInfo,
+    players: &mut Query<(&Id, &mut PredictedPlayerState), With<PlayerMarker>>,
+    reconcile_buffer: &mut StateTimeline,
 ) {
-    // gizmos.ray(*position, *velocity, YELLOW);
-    
-    if let Some(hit) = spatial_query.cast_shape(
-        collider,
-        player_predicted_state.predicted_position,
-        Quaternion::default(),
-        Dir3::try_from(player_predicted_state.predicted_linear_velocity).unwrap(),
-        &ShapeCastConfig{
-            max_distance: player_predicted_state.predicted_linear_velocity.length() * time.delta_secs(),
-            target_distance: 0.0,
-            compute_contact_on_penetration: false,
-            ignore_origin_penetration: true,
-        },
-        &SpatialQueryFilter::from_mask(!LayerMask::from(CollisionLayer::Player))
-    ) {
-        // gizmos.sphere(Isometry3d::new(*position + (Dir3::try_from(*velocity).unwrap() * hit.distance * time.delta_secs()), Quaternion::default()), 0.1, BLACK);
-        // gizmos.sphere(Isometry3d::new(hit.point1, Quaternion::default()), 0.1, RED);
-    
-        let normal = hit.normal1;
-        let into_surface = player_predicted_state.predicted_linear_velocity.dot(normal);
-    
-        if hit.normal1.y > 0.7 {
-            // SNAP TO GROUND
-            player_predicted_state.predicted_linear_velocity.y = 0.0;
-            return;
-        }
-    
-        if into_surface < 0.0 {
-            player_predicted_state.predicted_linear_velocity -= normal * into_surface;
-        }
-    
-        let max_move = (hit.distance - 0.02).max(0.0);
-        if player_predicted_state.predicted_linear_velocity.length() > max_move {
-            player_predicted_state.predicted_linear_velocity = player_predicted_state.predicted_linear_velocity.normalize() * max_move;
+    for (id, mut predicted_state) in players.iter_mut() {
+        if *id == player_info.current_player_id {
+            predicted_state.predicted_position = position.into();
+            predicted_state.predicted_linear_velocity = Vec3::ZERO;
+            predicted_state.motor = MotorState { crouching: predicted_state.motor.crouching, ..default() };
         }
     }
+
+    // Predictions from before the respawn can't be reconciled against anymore
+    reconcile_buffer.history.clear();
 }
 
-fn apply_player_movement_input(
-    encoded_input: BitMask,
-    linear_velocity: &mut Vec3,
-    yaw: &f32,
+/// Inputs queued past this are dropped so a burst of packets doesn't leave the player running behind
+const MAX_PENDING_INPUTS: usize = 4;
+/// How much of a player's speed into someone else carries over into shoving them
+const PLAYER_PUSH_SHARE: f32 = 0.5;
+
+/// Runs the shared character step on the client's prediction. Platforms are predicted with their last replicated velocity,
+/// pushing things around is left to the server.
+fn predict_step(
+    state: &mut PredictedPlayerState,
+    actions: ActionFlags,
+    movement: AnalogMove,
+    settings: &MovementSettings,
+    spatial_query: &SpatialQueryPipeline,
+    filter: &SpatialQueryFilter,
+    props: &Query<&LinearVelocity, (With<Prop>, Without<PlayerMarker>)>,
+    dt: f32,
 ) {
-    let mut vector = Vec3::ZERO;
-
-    if encoded_input & 1 > 0 {
-        vector.z -= 1.0;
-    }
-    if encoded_input & 2 > 0 {
-        vector.z += 1.0;
-    }
-    if encoded_input & 4 > 0 {
-        vector.x += 1.0;
-    }
-    if encoded_input & 8 > 0 {
-        vector.x -= 1.0;
-    }
-    if encoded_input & 16 > 0 {
-        linear_velocity.y += 1.0;
-    }
-
-    let normalized_rotated_velocity = Quat::from_euler(YXZ, *yaw, 0.0, 0.0).mul_vec3(vector.normalize_or_zero());
-    
-    linear_velocity.x = normalized_rotated_velocity.x * WALK_SPEED;
-    linear_velocity.z = normalized_rotated_velocity.z * WALK_SPEED;
+    state.predicted_linear_velocity = step_character(
+        actions,
+        movement,
+        state.predicted_yaw,
+        &mut state.motor,
+        &mut state.predicted_position,
+        settings,
+        spatial_query,
+        filter,
+        &|ground| prop_velocity(props, ground),
+        &mut Vec::new(),
+        dt,
+    );
+    state.predicted_position += state.predicted_linear_velocity * dt;
 }
 
 pub fn player_controller(
     mut player_info: ResMut<PlayerInfo>,
-    mut players: Query<(&Id, &mut PredictedPlayerState, &mut CameraInfo, &mut PlayerAnimationState, &Collider), With<PlayerMarker>>,
+    mut players: Query<(&Id, &mut PredictedPlayerState, &mut PlayerAnimationState, &mut Collider), With<PlayerMarker>>,
+    props: Query<&LinearVelocity, (With<Prop>, Without<PlayerMarker>)>,
     spatial_query: Res<SpatialQueryPipeline>,
     mut hud: Query<&mut Text, With<Hud>>,
     mut connection: Single<&mut UdpConnection<CUdpType>>,
     state_timeline: Res<StateTimeline>,
+    spectator: Res<Spectator>,
     time: Res<Time>,
     mut gizmos: Gizmos,
     mut commands: Commands,
 ) {
     if connection.socket.is_some() {
-        for (id, mut player_predicted_state, mut camera_info, mut player_anim_state, collider) in players.iter_mut() {
-            
-            apply_gravity(&mut player_predicted_state.predicted_linear_velocity, &time);
-            
-            if player_info.current_player_id == *id {
-                if player_info.player_inputs != 0 {
-                    player_anim_state.0 = AnimationState::Walking;
-                    apply_player_movement_input(player_info.player_inputs, &mut player_predicted_state.predicted_linear_velocity, &camera_info.yaw);
-                } else {
-                    player_anim_state.0 = AnimationState::Idle;
-                    info!("No input");
+        let filter = SpatialQueryFilter::from_mask(!LayerMask::from(CollisionLayer::Player));
+
+        for (id, mut player_predicted_state, mut player_anim_state, mut collider) in players.iter_mut() {
+            // The server ignores input from the dead, inputs are still sent so snapshots keep coming
+            if player_info.current_player_id == *id && !spectator.dead {
+                let was_crouching = player_predicted_state.motor.crouching;
+                predict_step(
+                    &mut player_predicted_state,
+                    player_info.player_inputs,
+                    player_info.move_input,
+                    &player_info.movement,
+                    &spatial_query,
+                    &filter,
+                    &props,
+                    time.delta_secs(),
+                );
+                if player_predicted_state.motor.crouching != was_crouching {
+                    *collider = player_collider(player_predicted_state.motor.crouching);
                 }
+                let velocity = player_predicted_state.predicted_linear_velocity;
+                player_anim_state.state = movement_animation(velocity, &player_predicted_state.motor, &player_info.movement);
+                player_anim_state.firing = player_info.player_inputs.contains(ActionFlags::FIRE);
+                player_anim_state.speed = Vec2::new(velocity.x, velocity.z).length();
+                player_anim_state.pitch = player_predicted_state.predicted_pitch;
 
                 if let Some(mut h) = hud.single_mut().ok() {
                     h.clear();
@@ -417,17 +438,19 @@ pub fn player_controller(
                     ));
                 }
 
-                apply_constraint_solver(&spatial_query, &mut player_predicted_state, collider, &time);
-                
-                commands.spawn(ObjectState(Player { player: PlayerState::new(player_predicted_state.predicted_position, player_predicted_state.predicted_linear_velocity, camera_info.yaw, camera_info.pitch, player_anim_state.0) }));
-                commands.spawn(ObjectState(Input { encoded_input: player_info.player_inputs, mouse_delta: player_info.accumulated_mouse_delta - player_info.mouse_delta }));
+                commands.spawn(ObjectState(Player { player: PlayerState::new(player_predicted_state.predicted_position, player_predicted_state.predicted_linear_velocity, player_predicted_state.predicted_yaw, player_predicted_state.predicted_pitch, player_anim_state.state, player_predicted_state.motor.crouching, true, player_anim_state.firing) }));
+                commands.spawn(ObjectState(Input { actions: player_info.player_inputs, movement: player_info.move_input, mouse_delta: player_info.accumulated_mouse_delta - player_info.mouse_delta }));
+            } else if player_info.current_player_id == *id {
+                // Snapshots don't update our own model, so the death has to be played from here
+                *player_anim_state = PlayerAnimationState { state: AnimationState::Dead, ..default() };
             }
         }
 
-        connection.add_message(NetworkMessage(CUdpType::PlayerId {id: player_info.current_player_id}));
+        connection.add_message(NetworkMessage(CUdpType::Session { token: player_info.session_token }));
         
         connection.add_message(NetworkMessage(CUdpType::Input {
-            keymask: player_info.player_inputs,
+            actions: player_info.player_inputs,
+            movement: player_info.move_input,
             mouse_delta: player_info.accumulated_mouse_delta - player_info.mouse_delta,
         }));
 
@@ -439,11 +462,13 @@ pub fn resimulate_player(
     state_timeline: &mut ResMut<StateTimeline>,
     received_seq_num: SequenceNumber,
     mut predicted_player_state: &mut PredictedPlayerState,
-    collider: &Collider,
     spatial_query: &Res<SpatialQueryPipeline>,
+    movement: &MovementSettings,
+    props: &Query<&LinearVelocity, (With<Prop>, Without<PlayerMarker>)>,
     time: &Res<Time>
 ) {
     let mut circular_index;
+    let filter = SpatialQueryFilter::from_mask(!LayerMask::from(CollisionLayer::Player));
 
     for i in received_seq_num + 1.. {
         circular_index = i % BUFFER_SIZE;
@@ -459,20 +484,18 @@ pub fn resimulate_player(
             for r in reconcile_objects {
                 match r.0 {
                     Player { player } => player_state = Some(player),
-                    Input { encoded_input, mouse_delta } =>  input_state = Some((encoded_input, mouse_delta))
+                    Input { actions, movement: move_input, mouse_delta } =>  input_state = Some((actions, move_input, mouse_delta))
                 }
             }
         }
         
-        if let Some(mut player) = player_state {
-            apply_gravity(&mut predicted_player_state.predicted_linear_velocity, &time);
-            if let Some((input_mask, rotation)) = input_state {
-                if input_mask != 0 {
-                    apply_player_movement_input(input_mask, &mut player.linear_velocity, &player.yaw);
-                }
+        let mut firing = false;
+        if player_state.is_some() {
+            if let Some((actions, move_input, rotation)) = input_state {
+                firing = actions.contains(ActionFlags::FIRE);
                 apply_player_camera_input(rotation.into(), &mut predicted_player_state);
+                predict_step(&mut predicted_player_state, actions, move_input, movement, spatial_query, &filter, props, time.delta_secs());
             }
-            apply_constraint_solver(&spatial_query, &mut predicted_player_state, &collider, &time);
         }
 
         if let Some(frame_state) = state_timeline.history.get_mut(&circular_index) {
@@ -484,7 +507,10 @@ pub fn resimulate_player(
                             predicted_player_state.predicted_linear_velocity,
                             predicted_player_state.predicted_yaw,
                             predicted_player_state.predicted_pitch,
-                            AnimationState::Idle
+                            movement_animation(predicted_player_state.predicted_linear_velocity, &predicted_player_state.motor, movement),
+                            predicted_player_state.motor.crouching,
+                            true,
+                            firing,
                         )
                     },
                     _ => {}
@@ -556,7 +582,7 @@ pub fn reconcile_player(
                             match &mut entity_state.0 {
                                 Player { player } => {
                                     // Sets state back to received server state to prepare for resimulation
-                                    *player = PlayerState::new(sps.position, sps.linear_velocity, sps.yaw, sps.pitch, sps.animation_state);
+                                    *player = sps;
                                 }
                                 _ => {}
                             }
@@ -616,14 +642,21 @@ pub fn update_players(
 ) {
     let mut existing_players = HashSet::new();
 
-    for (mut transform, id, entity, _, mut anim_state, _, _) in client_players.iter_mut() {
+    for (mut transform, id, entity, _, mut anim_state, mut predicted_state, _) in client_players.iter_mut() {
         existing_players.insert(id);
 
-        let player = match server_players.get(id) {
-            Some(p) => p,
-            None => continue,
+        // Snapshots list everyone in the lobby, anyone missing has left or gone spectating
+        let Some(player) = server_players.get(id) else {
+            commands.entity(entity).despawn();
+            continue;
         };
 
+        if player.alive {
+            commands.entity(entity).remove::<(Dead, ColliderDisabled)>();
+        } else {
+            commands.entity(entity).insert((Dead::default(), ColliderDisabled));
+        }
+
         if *id != info.current_player_id {
             commands.entity(entity).remove::<LinearVelocity>();
             commands.entity(entity).remove::<RigidBody>();
@@ -636,7 +669,15 @@ pub fn update_players(
 
             transform.translation = player.position.into();
             transform.rotation = Quat::from_euler(YXZ, player.yaw, 0.0, 0.0);
-            anim_state.0 = player.animation_state;
+            anim_state.state = player.animation_state;
+            anim_state.firing = player.firing;
+            anim_state.speed = Vec2::new(player.linear_velocity.x, player.linear_velocity.z).length();
+            anim_state.pitch = player.pitch;
+
+            if predicted_state.motor.crouching != player.crouching {
+                predicted_state.motor.crouching = player.crouching;
+                commands.entity(entity).insert(player_collider(player.crouching));
+            }
         }
     }
 
@@ -647,7 +688,7 @@ pub fn update_players(
 
             let player = commands.spawn((
                 RigidBody::Kinematic,
-                Collider::capsule(0.5, 1.0),
+                player_collider(p.1.crouching),
                 LockedAxes::new()
                     .lock_rotation_x()
                     .lock_rotation_y()
@@ -659,12 +700,16 @@ pub fn update_players(
                 ),
                 CollisionLayers::new(CollisionLayer::Player, [LayerMask::ALL]),
                 CameraInfo::default(),
-                PlayerAnimationState(AnimationState::Idle),
+                PlayerAnimationState::default(),
                 PredictedPlayerState {
                     predicted_position: p.1.position,
                     predicted_linear_velocity: p.1.linear_velocity,
                     predicted_yaw: p.1.yaw,
                     predicted_pitch: p.1.pitch,
+                    motor: MotorState {
+                        crouching: p.1.crouching,
+                        ..default()
+                    },
                 },
                 *p.0,
                 PlayerMarker
@@ -704,7 +749,7 @@ pub fn update_players(
 }
 
 #[derive(Component)]
-pub struct PlayerLabel(Entity);
+pub struct PlayerLabel(pub Entity);
 
 pub fn update_label_pos(
     mut labels: Query<(Entity, &mut Node, &PlayerLabel)>,
@@ -731,13 +776,81 @@ pub fn update_label_pos(
     }
 }
 
+/// Server side. Runs each player's queued input through the same step the client predicts with,
+/// one input per tick. Physics then moves the body by the resulting velocity.
+/// Crates and other players that get walked into are pushed once everyone has moved.
+pub fn simulate_players(
+    mut players: Query<
+        (Entity, &mut PendingInputs, &mut MotorState, &mut CameraInfo, &mut Position, &mut LinearVelocity, &mut Collider, &mut PlayerAnimationState, &CollisionLayers),
+        (With<PlayerMarker>, Without<Dead>),
+    >,
+    mut props: Query<(&mut LinearVelocity, Option<&ComputedMass>, Has<Pushable>), (With<Prop>, Without<PlayerMarker>)>,
+    spatial_query: Res<SpatialQueryPipeline>,
+    server_config: Res<ServerConfig>,
+    time: Res<Time>,
+) {
+    let movement = server_config.simulation.movement();
+    let dt = time.delta_secs();
+    let mut hits = Vec::new();
+    let mut pushes = Vec::new();
+
+    for (entity, mut pending, mut motor, mut camera, mut position, mut velocity, mut collider, mut anim_state, layers) in players.iter_mut() {
+        while pending.buffer.len() > MAX_PENDING_INPUTS {
+            pending.buffer.pop_front();
+        }
+        let input = match pending.buffer.pop_front() {
+            Some(input) => {
+                pending.last = input;
+                input
+            }
+            // Keep moving the way the player was, but don't keep turning
+            None => PlayerInput { mouse_delta: Vec2::ZERO, ..pending.last },
+        };
+
+        apply_look_input(input.mouse_delta.into(), &mut camera.yaw, &mut camera.pitch);
+
+        let filter = SpatialQueryFilter::from_mask(layers.filters).with_excluded_entities([entity]);
+        let was_crouching = motor.crouching;
+        velocity.0 = step_character(
+            input.actions,
+            input.movement,
+            camera.yaw,
+            &mut motor,
+            &mut position.0,
+            &movement,
+            &spatial_query,
+            &filter,
+            &|ground| props.get(ground).map_or(Vec3::ZERO, |(v, _, _)| v.0),
+            &mut hits,
+            dt,
+        );
+        if motor.crouching != was_crouching {
+            *collider = player_collider(motor.crouching);
+        }
+        anim_state.state = movement_animation(velocity.0, &motor, &movement);
+        anim_state.firing = input.actions.contains(ActionFlags::FIRE);
+        pushes.extend(hits.iter().copied());
+    }
+
+    for hit in pushes {
+        if let Ok((mut crate_velocity, mass, true)) = props.get_mut(hit.entity) {
+            push_prop(&mut crate_velocity, mass, hit.normal, server_config.simulation.push_strength, dt);
+        } else if let Ok((_, _, mut motor, ..)) = players.get_mut(hit.entity) {
+            // Only sideways, standing on someone's head doesn't push them into the floor
+            let away = Vec3::new(-hit.normal.x, 0.0, -hit.normal.z);
+            motor.push_velocity = (motor.push_velocity + away * hit.speed * PLAYER_PUSH_SHARE).clamp_length_max(movement.run_speed);
+        }
+    }
+}
+
 //TODO: Add after reconciliation check
 pub fn update_player_kinematics(
     mut player_query: Query<(&mut Position, &mut Rotation, &mut LinearVelocity, &PredictedPlayerState), With<PlayerMarker>>,
 ){
     for (mut position, mut rotation, mut linear_velocity, predicted_state) in player_query.iter_mut() {
         position.0 = predicted_state.predicted_position;
-        linear_velocity.0 = predicted_state.predicted_linear_velocity;
+        // The controller already moved the prediction, physics moving the body as well would put it a tick ahead
+        linear_velocity.0 = Vec3::ZERO;
         rotation.0 = Quat::from_euler(YXZ, predicted_state.predicted_yaw, 0.0, 0.0);
     }
 }
\ No newline at end of file
@@ -1,13 +1,19 @@
 use std::collections::HashSet;
 use bevy::app::{App, FixedPostUpdate, FixedPreUpdate, Plugin, PostUpdate};
 use bevy::math::Vec2;
-use bevy::prelude::{FixedUpdate, IntoScheduleConfigs, PreUpdate, Update};
-use crate::components::camera::{camera_controller, lock_cursor_system};
+use bevy::prelude::{FixedUpdate, IntoScheduleConfigs, PreUpdate, Startup, Update};
+use bevy::transform::TransformSystems;
+use crate::components::actions::{ActionFlags, AnalogMove};
+use crate::components::camera::{camera_controller, lock_cursor_system, PlayerCamera};
 use crate::components::common::Id;
 use crate::components::player::{player_controller, update_label_pos, update_player_kinematics, PlayerInfo};
+use crate::components::player::controller::MovementSettings;
 use crate::components::player::animation::{animation_control, player_animations, setup_player_animations};
 use crate::components::player::input::input_system;
+use crate::components::spectator::{spectator_camera, Spectator};
+use crate::components::killcam::{play_killcam, Killcam};
 use crate::components::weapon::weapon_controller;
+use crate::config::DEFAULT_GROUND_SIZE;
 
 pub struct PlayerPlugin;
 
@@ -15,11 +21,19 @@ impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(PlayerInfo {
             current_player_id: Id(0),
-            player_inputs: 0,
+            session_token: 0,
+            player_inputs: ActionFlags::empty(),
+            move_input: AnalogMove::default(),
             mouse_delta: Vec2::ZERO.into(),
             accumulated_mouse_delta: Vec2::ZERO.into(),
-            player_movement_state: HashSet::new()
+            player_movement_state: HashSet::new(),
+            movement: MovementSettings::default(),
+            ground_size: DEFAULT_GROUND_SIZE,
         });
+        app.init_resource::<PlayerCamera>();
+        app.init_resource::<Spectator>();
+        app.init_resource::<Killcam>();
+        app.add_systems(Startup, setup_player_animations);
         app.add_systems(PreUpdate, (
             input_system,
         ));
@@ -28,11 +42,13 @@ impl Plugin for PlayerPlugin {
             (
                 lock_cursor_system,
                 camera_controller,
+                spectator_camera,
                 update_label_pos,
-                setup_player_animations,
                 weapon_controller,
             )
         );
+        // After the snapshots and the other cameras, before the transforms are propagated
+        app.add_systems(PostUpdate, play_killcam.before(TransformSystems::Propagate));
         // app.add_systems(
         //     FixedPreUpdate, (
         //         
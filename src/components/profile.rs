@@ -0,0 +1,205 @@
+use bevy::prelude::{Children, Color, DetectChanges, Local, Query, Res, ResMut, Resource, Single, Text, TextColor, Without};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use crate::components::chat_command::kick;
+use crate::components::chat_moderation::ChatModeration;
+use crate::components::common::Id;
+use crate::components::player::PlayerLabel;
+use crate::network::net_bans::BanList;
+use crate::network::net_manage::TcpConnection;
+use crate::network::net_message::{CTcpType, NetworkMessage, STcpType};
+
+pub const MAX_PLAYER_NAME_LENGTH: usize = 16;
+pub const DEFAULT_PLAYER_NAME: &str = "Player";
+
+/// Colours a player can pick for their name
+pub const PROFILE_COLORS: [[u8; 3]; 6] = [
+    [255, 255, 255],
+    [230, 80, 80],
+    [80, 140, 230],
+    [90, 200, 110],
+    [240, 200, 70],
+    [190, 110, 220],
+];
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PlayerProfile {
+    pub name: String,
+    pub color: [u8; 3],
+}
+
+impl Default for PlayerProfile {
+    fn default() -> Self {
+        Self {
+            name: DEFAULT_PLAYER_NAME.to_string(),
+            color: PROFILE_COLORS[0],
+        }
+    }
+}
+
+/// Profiles of every connected player. The server owns them and replicates the whole map to clients.
+#[derive(Resource, Default)]
+pub struct PlayerProfiles {
+    pub profiles: HashMap<Id, PlayerProfile>,
+}
+
+impl PlayerProfiles {
+    pub fn display_name(&self, id: Id) -> String {
+        match self.profiles.get(&id) {
+            Some(p) => p.name.clone(),
+            None => id.0.to_string(),
+        }
+    }
+
+    pub fn color(&self, id: Id) -> Color {
+        let [r, g, b] = self.profiles.get(&id).map(|p| p.color).unwrap_or(PROFILE_COLORS[0]);
+        Color::srgb_u8(r, g, b)
+    }
+}
+
+/// The profile this client introduces itself with
+#[derive(Resource, Default)]
+pub struct LocalProfile(pub PlayerProfile);
+
+/// Strips everything but letters, digits, spaces, '-' and '_' and clamps the length
+pub fn sanitize_name(name: &str) -> String {
+    let filtered: String = name
+        .chars()
+        .filter(|c| c.is_alphanumeric() || *c == ' ' || *c == '-' || *c == '_')
+        .collect();
+    let collapsed = filtered.split_whitespace().collect::<Vec<_>>().join(" ");
+    let name: String = collapsed.chars().take(MAX_PLAYER_NAME_LENGTH).collect();
+
+    if name.trim().is_empty() {
+        DEFAULT_PLAYER_NAME.to_string()
+    } else {
+        name.trim().to_string()
+    }
+}
+
+/// Appends a number to names another player already uses
+pub(crate) fn unique_name(name: String, player_id: Id, profiles: &PlayerProfiles) -> String {
+    let taken = |candidate: &str| {
+        profiles
+            .profiles
+            .iter()
+            .any(|(id, p)| *id != player_id && p.name.eq_ignore_ascii_case(candidate))
+    };
+
+    if !taken(&name) {
+        return name;
+    }
+
+    let mut suffix = 2;
+    loop {
+        let suffix_text = format!(" {}", suffix);
+        let base: String = name.chars().take(MAX_PLAYER_NAME_LENGTH - suffix_text.len()).collect();
+        let candidate = format!("{}{}", base.trim_end(), suffix_text);
+        if !taken(&candidate) {
+            return candidate;
+        }
+        suffix += 1;
+    }
+}
+
+pub fn handle_set_profile(
+    profile: &PlayerProfile,
+    connection: &mut TcpConnection<STcpType>,
+    profiles: &mut ResMut<PlayerProfiles>,
+    bans: &BanList,
+    moderation: &ChatModeration,
+) {
+    let Some(player_id) = connection.player_id else {
+        return;
+    };
+
+    let name = sanitize_name(&profile.name);
+    if let Some(ban) = bans.find_name(&name) {
+        println!("Player {:?} tried to use the banned name {:?}", player_id, name);
+        kick(connection, ban.message());
+        return;
+    }
+
+    // Names show up next to every chat message, so they go through the same filter
+    let name = unique_name(moderation.filter_words(&name), player_id, profiles);
+    // Only the colours on offer, anything else would go out to every client as sent
+    let color = if PROFILE_COLORS.contains(&profile.color) { profile.color } else { PROFILE_COLORS[0] };
+    let profile = PlayerProfile {
+        name,
+        color,
+    };
+
+    if profiles.profiles.get(&player_id) == Some(&profile) {
+        return;
+    }
+
+    println!("Player {:?} is now known as {:?}", player_id, profile.name);
+    profiles.profiles.insert(player_id, profile);
+}
+
+pub fn send_profiles_to_all_connections(
+    profiles: Res<PlayerProfiles>,
+    mut connections: Query<&mut TcpConnection<STcpType>>,
+) {
+    if !profiles.is_changed() {
+        return;
+    }
+
+    for mut c in connections.iter_mut() {
+        if c.player_id.is_none() {
+            continue;
+        }
+        c.add_message(NetworkMessage(STcpType::Profiles {
+            profiles: profiles.profiles.clone(),
+        }));
+    }
+}
+
+/// Introduces the player to the server as soon as the TCP connection comes up
+pub fn send_profile_on_connect(
+    mut connection: Single<&mut TcpConnection<CTcpType>>,
+    local_profile: Res<LocalProfile>,
+    mut was_connected: Local<bool>,
+) {
+    let connected = connection.stream.is_some();
+    if connected && (!*was_connected || local_profile.is_changed()) {
+        connection.add_message(NetworkMessage(CTcpType::SetProfile {
+            profile: local_profile.0.clone(),
+        }));
+    }
+    *was_connected = connected;
+}
+
+pub fn client_set_profiles(
+    profiles: &HashMap<Id, PlayerProfile>,
+    player_profiles: &mut PlayerProfiles,
+) {
+    player_profiles.profiles = profiles.clone();
+}
+
+/// Keeps the floating name labels in sync with the replicated profiles
+pub fn update_label_names(
+    profiles: Res<PlayerProfiles>,
+    labels: Query<(&PlayerLabel, &Children)>,
+    players: Query<&Id>,
+    mut texts: Query<(&mut Text, &mut TextColor), Without<PlayerLabel>>,
+) {
+    for (label, children) in labels.iter() {
+        let Some(id) = players.get(label.0).ok() else {
+            continue;
+        };
+
+        let name = profiles.display_name(*id);
+        let color = profiles.color(*id);
+        for child in children.iter() {
+            if let Some((mut text, mut text_color)) = texts.get_mut(child).ok() {
+                if text.0 != name {
+                    text.0 = name.clone();
+                }
+                if text_color.0 != color {
+                    text_color.0 = color;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use avian3d::prelude::{Collider, CollisionLayers, ComputedMass, LayerMask, LinearVelocity, Position, RigidBody, Rotation};
+use bevy::asset::Assets;
+use bevy::color::Color;
+use bevy::pbr::{MeshMaterial3d, StandardMaterial};
+use bevy::prelude::{Commands, Component, Cuboid, Entity, Mesh, Mesh3d, Quat, Query, Res, ResMut, Time, Transform, Vec3, With, Without};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use crate::components::lobby::InLobby;
+use crate::components::common::Id;
+use crate::components::player::PlayerMarker;
+use crate::components::{lobby_collision_layers, CollisionLayer};
+
+/// Force a player pushes crates with, in newtons
+pub const DEFAULT_PUSH_STRENGTH: f32 = 400.0;
+const PLATFORM_SPEED: f32 = 1.5;
+const PLATFORM_SIZE: Vec3 = Vec3::new(3.0, 0.3, 3.0);
+const CRATE_SIZE: Vec3 = Vec3::splat(1.0);
+const PLATFORM_COLOR: Color = Color::srgb(0.35, 0.45, 0.6);
+const CRATE_COLOR: Color = Color::srgb(0.6, 0.45, 0.25);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum PropKind {
+    Platform,
+    Crate,
+}
+
+/// A body in the level that moves and is sent to clients with every snapshot
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Prop {
+    /// Unique within its lobby
+    pub id: u32,
+    pub kind: PropKind,
+    pub size: Vec3,
+}
+
+/// Kinematic platform going back and forth between two points
+#[derive(Component, Debug)]
+pub struct MovingPlatform {
+    pub from: Vec3,
+    pub to: Vec3,
+    pub speed: f32,
+    pub towards_end: bool,
+}
+
+/// Dynamic body players can shove around
+#[derive(Component)]
+pub struct Pushable;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct PropState {
+    pub kind: PropKind,
+    pub size: Vec3,
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub linear_velocity: Vec3,
+}
+
+/// Puts an elevator, a sliding platform and a couple of crates into a lobby
+pub fn spawn_lobby_props(commands: &mut Commands, lobby_id: Id, partition: u8) {
+    let platforms = [
+        (Vec3::new(8.0, 0.4, 0.0), Vec3::new(8.0, 3.0, 0.0)),
+        (Vec3::new(-10.0, 1.0, -6.0), Vec3::new(-10.0, 1.0, 6.0)),
+    ];
+    let crates = [Vec3::new(3.0, 0.75, 5.0), Vec3::new(-3.0, 0.75, 5.0)];
+
+    let mut id = 0;
+    for (from, to) in platforms {
+        commands.spawn((
+            RigidBody::Kinematic,
+            Collider::cuboid(PLATFORM_SIZE.x, PLATFORM_SIZE.y, PLATFORM_SIZE.z),
+            Transform::from_translation(from),
+            lobby_collision_layers(CollisionLayer::Prop, partition),
+            Prop { id, kind: PropKind::Platform, size: PLATFORM_SIZE },
+            MovingPlatform { from, to, speed: PLATFORM_SPEED, towards_end: true },
+            InLobby(lobby_id),
+        ));
+        id += 1;
+    }
+
+    for position in crates {
+        commands.spawn((
+            RigidBody::Dynamic,
+            Collider::cuboid(CRATE_SIZE.x, CRATE_SIZE.y, CRATE_SIZE.z),
+            Transform::from_translation(position),
+            lobby_collision_layers(CollisionLayer::Prop, partition),
+            Prop { id, kind: PropKind::Crate, size: CRATE_SIZE },
+            Pushable,
+            InLobby(lobby_id),
+        ));
+        id += 1;
+    }
+}
+
+/// Server side, turns platforms around at the ends of their track. Runs before the players so riders see this tick's velocity.
+pub fn move_platforms(
+    mut platforms: Query<(&mut MovingPlatform, &Position, &mut LinearVelocity)>,
+    time: Res<Time>,
+) {
+    for (mut platform, position, mut velocity) in platforms.iter_mut() {
+        let target = if platform.towards_end { platform.to } else { platform.from };
+        let to_target = target - position.0;
+
+        if to_target.length() <= platform.speed * time.delta_secs() {
+            platform.towards_end = !platform.towards_end;
+        }
+
+        let target = if platform.towards_end { platform.to } else { platform.from };
+        velocity.0 = (target - position.0).normalize_or_zero() * platform.speed;
+    }
+}
+
+/// Pushes a crate away from a player that walked into it
+pub fn push_prop(velocity: &mut LinearVelocity, mass: Option<&ComputedMass>, normal: Vec3, strength: f32, dt: f32) {
+    let mass = mass.map_or(1.0, |m| m.value()).max(0.001);
+    velocity.0 -= normal * strength * dt / mass;
+}
+
+pub fn build_prop_states(
+    props: &Query<(&Prop, &InLobby, &Position, &Rotation, &LinearVelocity)>,
+) -> HashMap<Id, HashMap<u32, PropState>> {
+    let mut lobby_props: HashMap<Id, HashMap<u32, PropState>> = HashMap::new();
+    for (prop, lobby, position, rotation, velocity) in props.iter() {
+        lobby_props.entry(lobby.0).or_default().insert(prop.id, PropState {
+            kind: prop.kind,
+            size: prop.size,
+            position: position.0,
+            rotation: rotation.0,
+            linear_velocity: velocity.0,
+        });
+    }
+    lobby_props
+}
+
+/// Client side. Props are kinematic copies of the server's, their velocity keeps them moving between snapshots
+/// and is what riding players predict with.
+pub fn update_props(
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
+    server_props: &HashMap<u32, PropState>,
+    client_props: &mut Query<(Entity, &Prop, &mut Position, &mut Rotation, &mut LinearVelocity), Without<PlayerMarker>>,
+) {
+    let mut existing = Vec::new();
+
+    for (entity, prop, mut position, mut rotation, mut velocity) in client_props.iter_mut() {
+        let Some(state) = server_props.get(&prop.id) else {
+            commands.entity(entity).despawn();
+            continue;
+        };
+        existing.push(prop.id);
+
+        position.0 = state.position;
+        rotation.0 = state.rotation;
+        velocity.0 = state.linear_velocity;
+    }
+
+    for (id, state) in server_props.iter() {
+        if existing.contains(id) {
+            continue;
+        }
+
+        let color = match state.kind {
+            PropKind::Platform => PLATFORM_COLOR,
+            PropKind::Crate => CRATE_COLOR,
+        };
+        commands.spawn((
+            RigidBody::Kinematic,
+            Collider::cuboid(state.size.x, state.size.y, state.size.z),
+            CollisionLayers::new(CollisionLayer::Prop, [LayerMask::ALL]),
+            Mesh3d(meshes.add(Cuboid::from_size(state.size))),
+            MeshMaterial3d(materials.add(color)),
+            Transform::from_translation(state.position).with_rotation(state.rotation),
+            Position(state.position),
+            Rotation(state.rotation),
+            LinearVelocity(state.linear_velocity),
+            Prop { id: *id, kind: state.kind, size: state.size },
+        ));
+    }
+}
+
+/// Velocity of a prop, zero for anything else. What a player standing on it gets carried along with.
+pub fn prop_velocity(props: &Query<&LinearVelocity, (With<Prop>, Without<PlayerMarker>)>, entity: Entity) -> Vec3 {
+    props.get(entity).map_or(Vec3::ZERO, |v| v.0)
+}
@@ -0,0 +1,149 @@
+use crate::components::actions::{Actions, InputAction};
+use crate::components::common::Id;
+use crate::components::lobby::Lobby;
+use crate::components::profile::PlayerProfiles;
+use crate::network::net_manage::{TcpConnection, UdpConnection};
+use crate::network::net_message::{NetworkMessage, STcpType, SUdpType};
+use bevy::prelude::{Changed, Component, DetectChangesMut, Local, Query, Res, Text, Time, Visibility};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// How often the server copies connection pings into the scoreboard
+const PING_UPDATE_INTERVAL: f32 = 1.0;
+const KILL_SCORE: i32 = 1;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct ScoreEntry {
+    pub kills: u32,
+    pub deaths: u32,
+    pub score: i32,
+    pub ping: u32,
+}
+
+#[derive(Component, Default)]
+pub struct Scoreboard {
+    pub entries: HashMap<Id, ScoreEntry>,
+}
+
+impl Scoreboard {
+    pub fn record_kill(&mut self, killer: Id, victim: Id) {
+        if killer == victim {
+            let entry = self.entries.entry(victim).or_default();
+            entry.deaths += 1;
+            entry.score -= KILL_SCORE;
+            return;
+        }
+
+        let killer_entry = self.entries.entry(killer).or_default();
+        killer_entry.kills += 1;
+        killer_entry.score += KILL_SCORE;
+
+        self.entries.entry(victim).or_default().deaths += 1;
+    }
+
+    /// Returns the entries ordered for display, highest score first
+    pub fn sorted_entries(&self) -> Vec<(Id, ScoreEntry)> {
+        let mut entries: Vec<(Id, ScoreEntry)> = self.entries.iter().map(|(id, e)| (*id, *e)).collect();
+        entries.sort_by(|a, b| {
+            b.1.score
+                .cmp(&a.1.score)
+                .then(b.1.kills.cmp(&a.1.kills))
+                .then(a.1.deaths.cmp(&b.1.deaths))
+                .then(a.0.0.cmp(&b.0.0))
+        });
+        entries
+    }
+}
+
+pub fn update_scoreboard(
+    mut lobbies: Query<(&Lobby, &mut Scoreboard)>,
+    connections: Query<&UdpConnection<SUdpType>>,
+    time: Res<Time>,
+    mut since_ping_update: Local<f32>,
+) {
+    *since_ping_update += time.delta_secs();
+    let update_pings = *since_ping_update >= PING_UPDATE_INTERVAL;
+    if update_pings {
+        *since_ping_update = 0.0;
+    }
+
+    for (lobby, mut scoreboard) in lobbies.iter_mut() {
+        // Keeps an entry for every player and drops entries of players that left or went spectating
+        let missing_player = lobby.players().any(|id| !scoreboard.entries.contains_key(id));
+        let stale_entry = scoreboard.entries.keys().any(|id| !lobby.is_player(id));
+        if missing_player || stale_entry {
+            scoreboard.entries.retain(|id, _| lobby.is_player(id));
+            for id in lobby.players() {
+                scoreboard.entries.entry(*id).or_default();
+            }
+        }
+
+        if !update_pings {
+            continue;
+        }
+
+        for c in connections.iter() {
+            let Some(id) = c.player_id else {
+                continue;
+            };
+
+            let ping_changed = scoreboard.entries.get(&id).is_some_and(|e| e.ping != c.ping);
+            if ping_changed {
+                if let Some(entry) = scoreboard.entries.get_mut(&id) {
+                    entry.ping = c.ping;
+                }
+            }
+        }
+    }
+}
+
+pub fn send_scoreboard_to_all_connections(
+    lobbies: Query<(&Lobby, &Scoreboard), Changed<Scoreboard>>,
+    mut connections: Query<&mut TcpConnection<STcpType>>,
+) {
+    for (lobby, scoreboard) in lobbies.iter() {
+        for mut c in connections.iter_mut() {
+            if !c.player_id.is_some_and(|id| lobby.members.contains(&id)) {
+                continue;
+            }
+            c.add_message(NetworkMessage(STcpType::Scoreboard {
+                entries: scoreboard.entries.clone(),
+            }));
+        }
+    }
+}
+
+pub fn client_set_scoreboard(
+    entries: &HashMap<Id, ScoreEntry>,
+    scoreboard: &mut Query<&mut Scoreboard>,
+) {
+    if let Some(mut scoreboard) = scoreboard.single_mut().ok() {
+        scoreboard.entries = entries.clone();
+    }
+}
+
+pub fn scoreboard_overlay(
+    actions: Actions,
+    mut scoreboard: Query<(&mut Text, &mut Visibility, &Scoreboard)>,
+    profiles: Res<PlayerProfiles>,
+) {
+    let Some((mut text, mut visibility, scoreboard)) = scoreboard.single_mut().ok() else {
+        return;
+    };
+
+    if !actions.pressed(InputAction::Scoreboard) {
+        visibility.set_if_neq(Visibility::Hidden);
+        return;
+    }
+
+    visibility.set_if_neq(Visibility::Visible);
+
+    text.0.clear();
+    text.0.push_str(&format!("{:<18}{:>6}{:>6}{:>7}{:>7}\n", "Player", "K", "D", "Score", "Ping"));
+    for (id, entry) in scoreboard.sorted_entries() {
+        text.0.push_str(&format!(
+            "{:<18}{:>6}{:>6}{:>7}{:>7}\n",
+            profiles.display_name(id), entry.kills, entry.deaths, entry.score, entry.ping
+        ));
+    }
+}
@@ -0,0 +1,258 @@
+use bevy::dev_tools::fps_overlay::FpsOverlayConfig;
+use bevy::prelude::{Camera3d, DetectChanges, DirectionalLight, Msaa, Projection, Query, Res, ResMut, Resource, Single, Window, With};
+use bevy::window::PresentMode;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use crate::components::actions::{default_gamepad_bindings, GamepadBinding, InputBindings};
+use crate::components::profile::{sanitize_name, PlayerProfile, DEFAULT_PLAYER_NAME, PROFILE_COLORS};
+
+const SETTINGS_DIR_NAME: &str = "mpclient";
+const SETTINGS_FILE_NAME: &str = "settings.toml";
+pub const DEFAULT_SERVER_ADDRESS: &str = "127.0.0.1";
+
+pub const MIN_SENSITIVITY: f32 = 0.1;
+pub const MAX_SENSITIVITY: f32 = 5.0;
+// Vertical field of view in degrees
+pub const MIN_FOV: f32 = 30.0;
+pub const MAX_FOV: f32 = 100.0;
+// How far the camera can be zoomed out behind the player, negative values are in front of the pivot
+pub const MIN_ZOOM: f32 = -0.2;
+pub const MAX_ZOOM: f32 = 20.0;
+pub const MAX_DEAD_ZONE: f32 = 0.5;
+pub const MIN_STICK_LOOK_SPEED: f32 = 200.0;
+pub const MAX_STICK_LOOK_SPEED: f32 = 5000.0;
+const MAX_LOOK_ACCELERATION: f32 = 4.0;
+const MSAA_SAMPLES: [u32; 4] = [1, 2, 4, 8];
+
+/// Everything the player can change about the client, kept in the user's config directory
+#[derive(Resource, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ClientSettings {
+    pub display_name: String,
+    pub color: [u8; 3],
+    /// Connected to when no address is given on the command line
+    pub last_server: String,
+    pub mouse: MouseSettings,
+    pub camera: CameraSettings,
+    pub graphics: GraphicsSettings,
+    pub key_bindings: InputBindings,
+    pub controller: ControllerSettings,
+}
+
+impl Default for ClientSettings {
+    fn default() -> Self {
+        Self {
+            display_name: DEFAULT_PLAYER_NAME.to_string(),
+            color: PROFILE_COLORS[0],
+            last_server: DEFAULT_SERVER_ADDRESS.to_string(),
+            mouse: MouseSettings::default(),
+            camera: CameraSettings::default(),
+            graphics: GraphicsSettings::default(),
+            key_bindings: InputBindings::default(),
+            controller: ControllerSettings::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct MouseSettings {
+    /// Multiplier on top of the base look speed
+    pub sensitivity: f32,
+    pub invert_y: bool,
+}
+
+impl Default for MouseSettings {
+    fn default() -> Self {
+        Self {
+            sensitivity: 1.0,
+            invert_y: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ControllerSettings {
+    pub enabled: bool,
+    /// Fraction of stick travel ignored around the centre
+    pub move_dead_zone: f32,
+    pub look_dead_zone: f32,
+    /// Look speed at full deflection, in the same units as mouse movement per second
+    pub look_speed: f32,
+    /// How much faster looking gets once the stick has been held all the way over for a second, 1 doubles it
+    pub look_acceleration: f32,
+    pub invert_y: bool,
+    pub bindings: Vec<GamepadBinding>,
+}
+
+impl Default for ControllerSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            move_dead_zone: 0.15,
+            look_dead_zone: 0.1,
+            look_speed: 1500.0,
+            look_acceleration: 1.0,
+            invert_y: false,
+            bindings: default_gamepad_bindings(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct CameraSettings {
+    /// Vertical, in degrees
+    pub fov: f32,
+    pub min_zoom: f32,
+    pub max_zoom: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            fov: 45.0,
+            min_zoom: MIN_ZOOM,
+            max_zoom: 10.0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct GraphicsSettings {
+    /// 1 turns anti-aliasing off, otherwise 2, 4 or 8
+    pub msaa_samples: u32,
+    pub vsync: bool,
+    pub shadows: bool,
+    pub show_fps: bool,
+}
+
+impl Default for GraphicsSettings {
+    fn default() -> Self {
+        Self {
+            msaa_samples: 8,
+            vsync: true,
+            shadows: true,
+            show_fps: true,
+        }
+    }
+}
+
+impl GraphicsSettings {
+    pub fn msaa(&self) -> Msaa {
+        match self.msaa_samples {
+            2 => Msaa::Sample2,
+            4 => Msaa::Sample4,
+            8 => Msaa::Sample8,
+            _ => Msaa::Off,
+        }
+    }
+
+    pub fn cycle_msaa(&mut self) {
+        let index = MSAA_SAMPLES.iter().position(|s| *s == self.msaa_samples).unwrap_or(0);
+        self.msaa_samples = MSAA_SAMPLES[(index + 1) % MSAA_SAMPLES.len()];
+    }
+}
+
+pub fn settings_path() -> PathBuf {
+    dirs::config_dir()
+        .map(|d| d.join(SETTINGS_DIR_NAME))
+        .unwrap_or_default()
+        .join(SETTINGS_FILE_NAME)
+}
+
+impl ClientSettings {
+    /// Falls back to the defaults when the file is missing. A file that can't be read is moved aside instead of being overwritten.
+    pub fn load() -> Self {
+        let path = settings_path();
+        let contents = match fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                eprintln!("Couldn't read settings {}: {}", path.display(), e);
+                return Self::default();
+            }
+        };
+
+        match toml::from_str::<ClientSettings>(&contents) {
+            Ok(mut settings) => {
+                settings.clamp();
+                settings
+            }
+            Err(e) => {
+                let backup = path.with_extension("toml.bak");
+                eprintln!("Invalid settings {}, moved to {}: {}", path.display(), backup.display(), e);
+                let _ = fs::rename(&path, &backup);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        let path = settings_path();
+        let result = toml::to_string_pretty(self)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| {
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+                }
+                fs::write(&path, contents).map_err(|e| e.to_string())
+            });
+        if let Err(e) = result {
+            eprintln!("Couldn't save settings {}: {}", path.display(), e);
+        }
+    }
+
+    /// Pulls hand edited values back into the ranges the menu allows
+    pub fn clamp(&mut self) {
+        self.mouse.sensitivity = self.mouse.sensitivity.clamp(MIN_SENSITIVITY, MAX_SENSITIVITY);
+        self.camera.fov = self.camera.fov.clamp(MIN_FOV, MAX_FOV);
+        self.camera.max_zoom = self.camera.max_zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+        self.camera.min_zoom = self.camera.min_zoom.clamp(MIN_ZOOM, self.camera.max_zoom);
+        self.controller.move_dead_zone = self.controller.move_dead_zone.clamp(0.0, MAX_DEAD_ZONE);
+        self.controller.look_dead_zone = self.controller.look_dead_zone.clamp(0.0, MAX_DEAD_ZONE);
+        self.controller.look_speed = self.controller.look_speed.clamp(MIN_STICK_LOOK_SPEED, MAX_STICK_LOOK_SPEED);
+        self.controller.look_acceleration = self.controller.look_acceleration.clamp(0.0, MAX_LOOK_ACCELERATION);
+        if !MSAA_SAMPLES.contains(&self.graphics.msaa_samples) {
+            self.graphics.msaa_samples = GraphicsSettings::default().msaa_samples;
+        }
+    }
+
+    pub fn profile(&self) -> PlayerProfile {
+        PlayerProfile {
+            name: sanitize_name(&self.display_name),
+            color: self.color,
+        }
+    }
+}
+
+/// Pushes graphics settings onto the camera, window and lights whenever they change
+pub fn apply_client_settings(
+    settings: Res<ClientSettings>,
+    mut cameras: Query<(&mut Msaa, &mut Projection), With<Camera3d>>,
+    mut window: Single<&mut Window>,
+    mut lights: Query<&mut DirectionalLight>,
+    mut fps_overlay: ResMut<FpsOverlayConfig>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+
+    for (mut msaa, mut projection) in cameras.iter_mut() {
+        *msaa = settings.graphics.msaa();
+        if let Projection::Perspective(perspective) = projection.as_mut() {
+            perspective.fov = settings.camera.fov.to_radians();
+        }
+    }
+
+    window.present_mode = if settings.graphics.vsync { PresentMode::AutoVsync } else { PresentMode::AutoNoVsync };
+
+    for mut light in lights.iter_mut() {
+        light.shadows_enabled = settings.graphics.shadows;
+    }
+
+    fps_overlay.enabled = settings.graphics.show_fps;
+}
@@ -0,0 +1,208 @@
+use avian3d::prelude::SpatialQueryPipeline;
+use bevy::prelude::{Camera3d, Component, Quat, Query, Res, ResMut, Resource, Single, State, Text, Time, Transform, Vec3, With, Without};
+use bevy::prelude::EulerRot::YXZ;
+use std::f32::consts::FRAC_PI_2;
+use crate::components::actions::{ActionFlags, Actions, InputAction};
+use crate::components::camera::{apply_look_input, boom_length, CAMERA_HEIGHT};
+use crate::components::chat::ChatInput;
+use crate::components::common::Id;
+use crate::components::killcam::Killcam;
+use crate::components::menu::MenuState;
+use crate::components::player::{Dead, PlayerInfo, PlayerMarker};
+use crate::components::profile::PlayerProfiles;
+use crate::components::settings::ClientSettings;
+
+const FLY_SPEED: f32 = 8.0;
+const FLY_SPRINT_MULTIPLIER: f32 = 3.0;
+const FOLLOW_DISTANCE: f32 = 4.0;
+const OVERHEAD_HEIGHT: f32 = 25.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum SpectatorMode {
+    /// Orbits a living player
+    #[default]
+    Follow,
+    /// Noclip camera moved with the movement keys
+    FreeFly,
+    /// Straight down onto a living player
+    Overhead,
+}
+
+impl SpectatorMode {
+    fn next(self) -> Self {
+        match self {
+            SpectatorMode::Follow => SpectatorMode::FreeFly,
+            SpectatorMode::FreeFly => SpectatorMode::Overhead,
+            SpectatorMode::Overhead => SpectatorMode::Follow,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            SpectatorMode::Follow => "Following",
+            SpectatorMode::FreeFly => "Free camera",
+            SpectatorMode::Overhead => "Overhead",
+        }
+    }
+}
+
+/// Client side. Drives the camera while there's no player of our own to control,
+/// because we chose to watch, the lobby was full or we're waiting to respawn.
+#[derive(Resource, Default)]
+pub struct Spectator {
+    /// Watching the lobby instead of playing in it
+    pub spectating: bool,
+    /// Killed and counting down to the respawn
+    pub dead: bool,
+    pub respawn_in: f32,
+    pub mode: SpectatorMode,
+    /// Player the follow and overhead cameras are on
+    pub target: Option<Id>,
+    position: Vec3,
+    yaw: f32,
+    pitch: f32,
+}
+
+impl Spectator {
+    pub fn active(&self) -> bool {
+        self.spectating || self.dead
+    }
+}
+
+#[derive(Component)]
+pub struct SpectatorStatus;
+
+pub fn client_set_spectating(spectating: bool, spectator: &mut Spectator) {
+    spectator.spectating = spectating;
+    spectator.dead = false;
+    spectator.target = None;
+}
+
+/// Watches the killer until the respawn, or whoever is around after a suicide
+pub fn client_player_killed(killer: Id, victim: Id, respawn_time: f32, player_info: &PlayerInfo, spectator: &mut Spectator) {
+    if victim != player_info.current_player_id {
+        return;
+    }
+
+    spectator.dead = true;
+    spectator.respawn_in = respawn_time;
+    spectator.mode = SpectatorMode::Follow;
+    spectator.target = (killer != victim).then_some(killer);
+}
+
+pub fn client_respawned(spectator: &mut Spectator) {
+    spectator.dead = false;
+}
+
+pub fn spectator_camera(
+    mut camera: Single<&mut Transform, (With<Camera3d>, Without<PlayerMarker>)>,
+    players: Query<(&Id, &Transform), (With<PlayerMarker>, Without<Dead>, Without<Camera3d>)>,
+    actions: Actions,
+    spatial_query: Res<SpatialQueryPipeline>,
+    player_info: Res<PlayerInfo>,
+    chat_input: Res<ChatInput>,
+    menu_state: Res<State<MenuState>>,
+    killcam: Res<Killcam>,
+    time: Res<Time>,
+    mut spectator: ResMut<Spectator>,
+) {
+    if !spectator.active() || killcam.playing() {
+        return;
+    }
+    let spectator = &mut *spectator;
+
+    let mut living: Vec<(Id, Vec3)> = players.iter().map(|(id, t)| (*id, t.translation)).collect();
+    living.sort_by(|a, b| a.0.0.cmp(&b.0.0));
+
+    if !chat_input.active && *menu_state.get() != MenuState::Options {
+        if actions.just_pressed(InputAction::ToggleView) {
+            spectator.mode = spectator.mode.next();
+        }
+        if actions.just_pressed(InputAction::Fire) {
+            let current = living.iter().position(|(id, _)| Some(*id) == spectator.target);
+            let next = current.map_or(0, |i| i + 1) % living.len().max(1);
+            spectator.target = living.get(next).map(|(id, _)| *id);
+        }
+    }
+
+    // The one being watched may have died or left
+    if !living.iter().any(|(id, _)| Some(*id) == spectator.target) {
+        spectator.target = living.first().map(|(id, _)| *id);
+    }
+    let target = living.iter().find(|(id, _)| Some(*id) == spectator.target).map(|(_, p)| *p);
+
+    apply_look_input(player_info.mouse_delta.into(), &mut spectator.yaw, &mut spectator.pitch);
+    let rotation = Quat::from_euler(YXZ, spectator.yaw, -spectator.pitch, 0.0);
+
+    match (spectator.mode, target) {
+        (SpectatorMode::Follow, Some(target)) => {
+            let pivot = target + Vec3::new(0.0, CAMERA_HEIGHT, 0.0);
+            let boom = boom_length(&spatial_query, pivot, rotation, FOLLOW_DISTANCE);
+            camera.rotation = rotation;
+            camera.translation = pivot + rotation * Vec3::new(0.0, 0.0, boom);
+        }
+        (SpectatorMode::Overhead, target) => {
+            camera.rotation = Quat::from_euler(YXZ, spectator.yaw, -FRAC_PI_2, 0.0);
+            camera.translation = target.unwrap_or(Vec3::ZERO) + Vec3::new(0.0, OVERHEAD_HEIGHT, 0.0);
+        }
+        // Following nobody flies freely until someone is alive again
+        (SpectatorMode::FreeFly, _) | (SpectatorMode::Follow, None) => {
+            let direction = player_info.move_input.to_vec2();
+            let mut velocity = rotation * Vec3::new(direction.x, 0.0, -direction.y);
+            if player_info.player_inputs.contains(ActionFlags::JUMP) {
+                velocity.y += 1.0;
+            }
+            if player_info.player_inputs.contains(ActionFlags::CROUCH) {
+                velocity.y -= 1.0;
+            }
+            let speed = if player_info.player_inputs.contains(ActionFlags::SPRINT) { FLY_SPEED * FLY_SPRINT_MULTIPLIER } else { FLY_SPEED };
+
+            spectator.position += velocity.clamp_length_max(1.0) * speed * time.delta_secs();
+            camera.rotation = rotation;
+            camera.translation = spectator.position;
+        }
+    }
+
+    // Free flying picks up from wherever the other views left the camera
+    spectator.position = camera.translation;
+}
+
+pub fn spectator_hud(
+    mut status: Query<&mut Text, With<SpectatorStatus>>,
+    mut spectator: ResMut<Spectator>,
+    profiles: Res<PlayerProfiles>,
+    settings: Res<ClientSettings>,
+    killcam: Res<Killcam>,
+    time: Res<Time>,
+) {
+    let Some(mut text) = status.single_mut().ok() else {
+        return;
+    };
+
+    if spectator.dead {
+        spectator.respawn_in = (spectator.respawn_in - time.delta_secs()).max(0.0);
+    }
+
+    let mut value = String::new();
+    if spectator.active() {
+        if let Some(killer) = killcam.killer() {
+            value.push_str(&format!("Killed by {}\n", profiles.display_name(killer)));
+        }
+        if spectator.dead {
+            value.push_str(&format!("Respawning in {}\n", spectator.respawn_in.ceil() as u32));
+        }
+        match (spectator.mode, spectator.target) {
+            (SpectatorMode::FreeFly, _) | (_, None) => value.push_str(spectator.mode.label()),
+            (mode, Some(target)) => value.push_str(&format!("{} {}", mode.label(), profiles.display_name(target))),
+        }
+        value.push_str(&format!(
+            "\n[{}] Change view  [{}] Next player",
+            settings.key_bindings.get(InputAction::ToggleView),
+            settings.key_bindings.get(InputAction::Fire),
+        ));
+    }
+
+    if text.0 != value {
+        text.0 = value;
+    }
+}
@@ -1,28 +1,78 @@
 use std::ops::Neg;
 use avian3d::math::Quaternion;
-use avian3d::prelude::{LayerMask, SpatialQueryFilter, SpatialQueryPipeline};
+use avian3d::prelude::{CollisionLayers, LayerMask, Position, SpatialQueryFilter, SpatialQueryPipeline};
 use bevy::color::palettes::css::{BLACK, BLUE, YELLOW};
-use bevy::input::ButtonState;
 use bevy::input::keyboard::KeyboardInput;
-use bevy::input::mouse::MouseButtonInput;
 use bevy::math::{Isometry3d, Vec3};
-use bevy::prelude::{Camera3d, Commands, Component, Dir3, Entity, EulerRot, Gizmo, Gizmos, KeyCode, MessageReader, MouseButton, Query, Res, Single, Transform, With};
-use crate::components::camera::CameraInfo;
+use bevy::prelude::{info, Camera3d, Commands, Component, Dir3, Entity, EulerRot, Gizmo, Gizmos, KeyCode, Message, MessageReader, MessageWriter, Quat, Query, Res, Single, State, Time, Transform, With};
+use crate::components::actions::{Actions, InputAction};
+use crate::components::camera::{CameraInfo, CAMERA_HEIGHT};
+use crate::components::chat::ChatInput;
+use crate::components::common::Id;
 use crate::components::CollisionLayer;
+use crate::components::game_mode::{Match, MatchPhase, Team};
+use crate::components::lobby::{InLobby, Lobby};
+use crate::components::menu::MenuState;
 use crate::components::player::PlayerMarker;
+use crate::components::spectator::Spectator;
+use crate::network::net_manage::TcpConnection;
+use crate::network::net_message::{CTcpType, NetworkMessage};
+
+const MAX_HEALTH: u32 = 100;
+// Extra reach allowed on the server since the client casts from the camera, not the player position
+const HIT_RANGE_TOLERANCE: f32 = 12.0;
+// How far off to the side of the target the shooter's aim may pass, about half a standing player plus latency
+const AIM_TOLERANCE: f32 = 1.5;
 
 #[derive(Component)]
 pub struct Weapon {
     pub damage: u32,
     pub range: f32,
+    /// Seconds between shots
+    pub fire_interval: f32,
+}
+
+/// Server side. Seconds until the player's next shot counts, so a burst of hit messages can't land at once.
+#[derive(Component, Default, Debug)]
+pub struct WeaponCooldown {
+    pub remaining: f32,
+}
+
+#[derive(Component, Debug)]
+pub struct Health {
+    pub current: u32,
+    pub max: u32,
+}
+
+#[derive(Message, Clone, Copy, Debug)]
+pub struct PlayerKilled {
+    pub killer: Id,
+    pub victim: Id,
 }
 
 impl Weapon {
+    pub fn rifle() -> Self {
+        Self {
+            damage: 10,
+            range: 100.0,
+            fire_interval: 0.15,
+        }
+    }
+
     pub fn fire(&self) {
 
     }
 }
 
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            current: MAX_HEALTH,
+            max: MAX_HEALTH,
+        }
+    }
+}
+
 // pub fn weapon_equip (
 //     mut commands: Commands,
 //     mut key_input: EventReader<KeyboardInput>
@@ -41,21 +91,136 @@ impl Weapon {
 pub fn weapon_controller(
     weapon: Single<&mut Weapon>,
     spatial_query: Res<SpatialQueryPipeline>,
-    mut mouse_input: MessageReader<MouseButtonInput>,
+    actions: Actions,
+    chat_input: Res<ChatInput>,
+    menu_state: Res<State<MenuState>>,
     camera_transform: Single<&Transform, With<Camera3d>>,
-    player_marker_query: Query<&PlayerMarker>,
+    player_query: Query<&Id, With<PlayerMarker>>,
+    spectator: Res<Spectator>,
+    mut connection: Single<&mut TcpConnection<CTcpType>>,
     mut gizmos: Gizmos,
-    mut commands: Commands
 ) {
-    for mouse_in in mouse_input.read() {
-        if mouse_in.state == ButtonState::Pressed && mouse_in.button == MouseButton::Left {
-            if let Some(hit) = spatial_query.cast_ray(camera_transform.translation, camera_transform.forward(), weapon.range, false, &SpatialQueryFilter::from_mask(!LayerMask::from(CollisionLayer::Player))) {
-                println!("Hit: {:?}", hit);
-                gizmos.sphere(Isometry3d::new(camera_transform.translation + (*camera_transform.forward() * hit.distance), Quaternion::default()), 1.0, BLACK);
-                if player_marker_query.get(hit.entity).ok().is_some() {
-                    commands.entity(hit.entity).despawn();
-                }
+    // Clicks on the options menu or while typing aren't shots, spectators use fire to switch players
+    if chat_input.active || *menu_state.get() == MenuState::Options || spectator.active() {
+        return;
+    }
+
+    if !actions.just_pressed(InputAction::Fire) {
+        return;
+    }
+
+    if let Some(hit) = spatial_query.cast_ray(camera_transform.translation, camera_transform.forward(), weapon.range, false, &SpatialQueryFilter::from_mask(!LayerMask::from(CollisionLayer::Player))) {
+        println!("Hit: {:?}", hit);
+        gizmos.sphere(Isometry3d::new(camera_transform.translation + (*camera_transform.forward() * hit.distance), Quaternion::default()), 1.0, BLACK);
+        // The server decides whether the hit counts
+        if let Some(target) = player_query.get(hit.entity).ok() {
+            if connection.stream.is_some() {
+                connection.add_message(NetworkMessage(CTcpType::Hit { target: *target }));
             }
         }
     }
-}
\ No newline at end of file
+}
+/// Whether a player looking this way could have hit something this far away from their eyes. Measured as how far
+/// the aim passes beside the target rather than as an angle, so point blank shots at the edge of a player still count.
+pub fn aimed_at(yaw: f32, pitch: f32, to_target: Vec3) -> bool {
+    let look = Quat::from_euler(EulerRot::YXZ, yaw, -pitch, 0.0) * Vec3::NEG_Z;
+    look.dot(to_target) > 0.0 && look.cross(to_target).length() <= AIM_TOLERANCE
+}
+
+pub fn tick_weapon_cooldowns(mut cooldowns: Query<&mut WeaponCooldown>, time: Res<Time>) {
+    for mut cooldown in cooldowns.iter_mut() {
+        cooldown.remaining = (cooldown.remaining - time.delta_secs()).max(0.0);
+    }
+}
+
+pub fn handle_hit(
+    shooter: Id,
+    target: Id,
+    lobbies: &Query<(Entity, &mut Lobby, &Match)>,
+    players: &mut Query<(&Id, &InLobby, &Position, &mut Health, Option<&Team>, Entity, &CollisionLayers, &mut WeaponCooldown, &CameraInfo), With<PlayerMarker>>,
+    spatial_query: &SpatialQueryPipeline,
+    kills: &mut MessageWriter<PlayerKilled>,
+) {
+    if shooter == target {
+        return;
+    }
+
+    let weapon = Weapon::rifle();
+
+    // The dead and spectators can't shoot
+    let Some((_, shooter_lobby, shooter_position, shooter_health, shooter_team, shooter_entity, shooter_layers, mut cooldown, shooter_camera)) =
+        players.iter_mut().find(|p| *p.0 == shooter)
+    else {
+        return;
+    };
+    if shooter_health.current == 0 {
+        return;
+    }
+    if cooldown.remaining > 0.0 {
+        info!("Rejected hit from {:?} on {:?}: firing too fast", shooter, target);
+        return;
+    }
+    cooldown.remaining = weapon.fire_interval;
+
+    let shooter_lobby = *shooter_lobby;
+    let eye = shooter_position.0 + Vec3::new(0.0, CAMERA_HEIGHT, 0.0);
+    let (shooter_yaw, shooter_pitch) = (shooter_camera.yaw, shooter_camera.pitch);
+    let shooter_team = shooter_team.copied().unwrap_or_default();
+    let filter = SpatialQueryFilter::from_mask(shooter_layers.filters).with_excluded_entities([shooter_entity]);
+
+    let Some((_, _, game_match)) = lobbies.iter().find(|(_, l, _)| l.id == shooter_lobby.0) else {
+        return;
+    };
+    if game_match.phase == MatchPhase::PostMatch {
+        return;
+    }
+
+    for (id, in_lobby, position, mut health, team, entity, _, _, _) in players.iter_mut() {
+        if *id != target {
+            continue;
+        }
+
+        // Already down and waiting to respawn
+        if health.current == 0 {
+            return;
+        }
+
+        if *in_lobby != shooter_lobby {
+            info!("Rejected hit from {:?} on {:?}: not in the same lobby", shooter, target);
+            return;
+        }
+
+        let to_target = position.0 - eye;
+        let distance = to_target.length();
+        if distance > weapon.range + HIT_RANGE_TOLERANCE {
+            info!("Rejected hit from {:?} on {:?}: out of range", shooter, target);
+            return;
+        }
+
+        if !aimed_at(shooter_yaw, shooter_pitch, to_target) {
+            info!("Rejected hit from {:?} on {:?}: not aiming at them", shooter, target);
+            return;
+        }
+
+        // Whatever the ray from the shooter's eyes reaches first has to be the target, not a wall in front of it
+        let Ok(direction) = Dir3::new(to_target) else {
+            return;
+        };
+        if spatial_query.cast_ray(eye, direction, distance, true, &filter).is_none_or(|hit| hit.entity != entity) {
+            info!("Rejected hit from {:?} on {:?}: no line of sight", shooter, target);
+            return;
+        }
+
+        if !game_match.mode.can_damage(shooter_team, team.copied().unwrap_or_default(), &game_match.settings) {
+            return;
+        }
+
+        health.current = health.current.saturating_sub(weapon.damage);
+        if health.current == 0 {
+            kills.write(PlayerKilled {
+                killer: shooter,
+                victim: target,
+            });
+        }
+    }
+}
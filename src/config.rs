@@ -0,0 +1,369 @@
+use bevy::prelude::Resource;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use crate::admin::DEFAULT_REMOTE_ADMIN_PORT;
+use crate::components::chat_moderation::DEFAULT_WORD_FILTER_PATH;
+use crate::components::game_mode::MatchSettings;
+use crate::components::lobby::DEFAULT_MAX_PLAYERS;
+use crate::components::prop::DEFAULT_PUSH_STRENGTH;
+use crate::components::player::controller::{
+    MovementSettings, DEFAULT_CROUCH_SPEED, DEFAULT_GRAVITY, DEFAULT_JUMP_SPEED, DEFAULT_RUN_SPEED, DEFAULT_WALK_SPEED,
+};
+use crate::network::net_bans::{BanTarget, DEFAULT_BAN_FILE, DEFAULT_MAX_CONNECTIONS_PER_IP};
+use crate::network::net_plugin::DEFAULT_PORT;
+use crate::server_log::DEFAULT_LOG_DIR;
+
+pub const DEFAULT_SERVER_CONFIG_PATH: &str = "server.toml";
+pub const MIN_TICK_RATE: f64 = 10.0;
+pub const MAX_TICK_RATE: f64 = 240.0;
+/// A snapshot of a full lobby still has to fit in MAX_DATAGRAM_SIZE
+pub const MAX_PLAYERS_PER_LOBBY: usize = 20;
+pub const DEFAULT_GROUND_SIZE: [f32; 2] = [40.0, 40.0];
+
+/// Settings read from the server config file at startup, anything left out keeps its default
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub network: NetworkSettings,
+    pub simulation: SimulationSettings,
+    pub gameplay: GameplaySettings,
+    pub moderation: ModerationSettings,
+    pub admin: AdminSettings,
+    pub logging: LoggingSettings,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkSettings {
+    pub bind_address: IpAddr,
+    /// Used for both TCP and UDP
+    pub port: u16,
+    /// Tasks receiving UDP packets
+    pub udp_pool_size: usize,
+    /// Packets handled per connection each tick, the rest are dropped
+    pub messages_per_tick: usize,
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        Self {
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: DEFAULT_PORT,
+            udp_pool_size: 8,
+            messages_per_tick: 20,
+        }
+    }
+}
+
+impl NetworkSettings {
+    pub fn socket_address(&self) -> SocketAddr {
+        SocketAddr::new(self.bind_address, self.port)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimulationSettings {
+    /// Fixed updates per second
+    pub tick_rate: f64,
+    pub walk_speed: f32,
+    /// While sprinting
+    pub run_speed: f32,
+    pub crouch_speed: f32,
+    /// Upward speed a jump starts with
+    pub jump_speed: f32,
+    pub gravity: f32,
+    /// Force players push crates with, in newtons
+    pub push_strength: f32,
+}
+
+impl Default for SimulationSettings {
+    fn default() -> Self {
+        Self {
+            tick_rate: 60.0,
+            walk_speed: DEFAULT_WALK_SPEED,
+            run_speed: DEFAULT_RUN_SPEED,
+            crouch_speed: DEFAULT_CROUCH_SPEED,
+            jump_speed: DEFAULT_JUMP_SPEED,
+            gravity: DEFAULT_GRAVITY,
+            push_strength: DEFAULT_PUSH_STRENGTH,
+        }
+    }
+}
+
+impl SimulationSettings {
+    /// What clients are told to predict with
+    pub fn movement(&self) -> MovementSettings {
+        MovementSettings {
+            walk_speed: self.walk_speed,
+            run_speed: self.run_speed,
+            crouch_speed: self.crouch_speed,
+            jump_speed: self.jump_speed,
+            gravity: self.gravity,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameplaySettings {
+    pub max_players_per_lobby: usize,
+    /// Width and depth of each lobby's ground
+    pub ground_size: [f32; 2],
+    pub score_limit: i32,
+    /// Seconds
+    pub time_limit: f32,
+    pub warmup_time: f32,
+    pub post_match_time: f32,
+    /// How long killed players spectate before they spawn again
+    pub respawn_time: f32,
+    pub min_players: usize,
+    pub friendly_fire: bool,
+}
+
+impl Default for GameplaySettings {
+    fn default() -> Self {
+        let match_settings = MatchSettings::default();
+        Self {
+            max_players_per_lobby: DEFAULT_MAX_PLAYERS,
+            ground_size: DEFAULT_GROUND_SIZE,
+            score_limit: match_settings.score_limit,
+            time_limit: match_settings.time_limit,
+            warmup_time: match_settings.warmup_time,
+            post_match_time: match_settings.post_match_time,
+            respawn_time: match_settings.respawn_time,
+            min_players: match_settings.min_players,
+            friendly_fire: match_settings.friendly_fire,
+        }
+    }
+}
+
+impl GameplaySettings {
+    pub fn match_settings(&self) -> MatchSettings {
+        MatchSettings {
+            score_limit: self.score_limit,
+            time_limit: self.time_limit,
+            warmup_time: self.warmup_time,
+            post_match_time: self.post_match_time,
+            respawn_time: self.respawn_time,
+            min_players: self.min_players,
+            friendly_fire: self.friendly_fire,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModerationSettings {
+    /// JSON file bans are loaded from and saved to
+    pub ban_file: String,
+    pub max_connections_per_ip: usize,
+    /// One filtered chat word per line
+    pub word_filter_file: String,
+}
+
+impl Default for ModerationSettings {
+    fn default() -> Self {
+        Self {
+            ban_file: DEFAULT_BAN_FILE.to_string(),
+            max_connections_per_ip: DEFAULT_MAX_CONNECTIONS_PER_IP,
+            word_filter_file: DEFAULT_WORD_FILTER_PATH.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminSettings {
+    /// Reads commands from the terminal the server runs in
+    pub console: bool,
+    pub remote_address: SocketAddr,
+    /// Remote admin stays off without one. The MPCLIENT_ADMIN_PASSWORD environment variable takes precedence.
+    pub remote_password: Option<String>,
+    /// Addresses and ranges whose players can use the admin chat commands, the server's own machine always can
+    pub chat_admins: Vec<BanTarget>,
+}
+
+impl Default for AdminSettings {
+    fn default() -> Self {
+        Self {
+            console: true,
+            remote_address: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), DEFAULT_REMOTE_ADMIN_PORT),
+            remote_password: None,
+            chat_admins: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingSettings {
+    /// Where the JSON lines server log is written
+    pub dir: String,
+}
+
+impl Default for LoggingSettings {
+    fn default() -> Self {
+        Self {
+            dir: DEFAULT_LOG_DIR.to_string(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Falls back to the defaults when the file doesn't exist
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents).map_err(|e| format!("Invalid config {}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("Couldn't read config {}: {}", path.display(), e)),
+        }
+    }
+
+    /// Lists every setting that is out of range, not just the first
+    pub fn validate(&self) -> Result<(), String> {
+        let mut errors = Vec::new();
+        let mut check = |ok: bool, message: String| {
+            if !ok {
+                errors.push(message);
+            }
+        };
+
+        let network = &self.network;
+        check(network.port != 0, "network.port can't be 0".to_string());
+        check(network.udp_pool_size >= 1, "network.udp_pool_size has to be at least 1".to_string());
+        check(network.messages_per_tick >= 1, "network.messages_per_tick has to be at least 1".to_string());
+
+        let simulation = &self.simulation;
+        check(
+            (MIN_TICK_RATE..=MAX_TICK_RATE).contains(&simulation.tick_rate),
+            format!("simulation.tick_rate has to be between {} and {}, got {}", MIN_TICK_RATE, MAX_TICK_RATE, simulation.tick_rate),
+        );
+        for (name, value) in [
+            ("walk_speed", simulation.walk_speed),
+            ("run_speed", simulation.run_speed),
+            ("crouch_speed", simulation.crouch_speed),
+            ("jump_speed", simulation.jump_speed),
+        ] {
+            check(value.is_finite() && value > 0.0, format!("simulation.{} has to be above 0, got {}", name, value));
+        }
+        check(
+            simulation.gravity.is_finite() && simulation.gravity >= 0.0,
+            format!("simulation.gravity can't be negative, got {}", simulation.gravity),
+        );
+        check(
+            simulation.push_strength.is_finite() && simulation.push_strength >= 0.0,
+            format!("simulation.push_strength can't be negative, got {}", simulation.push_strength),
+        );
+
+        let gameplay = &self.gameplay;
+        check(
+            (1..=MAX_PLAYERS_PER_LOBBY).contains(&gameplay.max_players_per_lobby),
+            format!("gameplay.max_players_per_lobby has to be between 1 and {}, got {}", MAX_PLAYERS_PER_LOBBY, gameplay.max_players_per_lobby),
+        );
+        check(
+            gameplay.ground_size.iter().all(|s| s.is_finite() && *s > 0.0),
+            format!("gameplay.ground_size has to be above 0, got {:?}", gameplay.ground_size),
+        );
+        check(gameplay.score_limit > 0, format!("gameplay.score_limit has to be above 0, got {}", gameplay.score_limit));
+        for (name, value) in [
+            ("time_limit", gameplay.time_limit),
+            ("warmup_time", gameplay.warmup_time),
+            ("post_match_time", gameplay.post_match_time),
+            ("respawn_time", gameplay.respawn_time),
+        ] {
+            check(value.is_finite() && value >= 0.0, format!("gameplay.{} can't be negative, got {}", name, value));
+        }
+        check(
+            gameplay.min_players >= 1 && gameplay.min_players <= gameplay.max_players_per_lobby,
+            format!("gameplay.min_players has to be between 1 and max_players_per_lobby, got {}", gameplay.min_players),
+        );
+
+        let moderation = &self.moderation;
+        check(
+            moderation.max_connections_per_ip >= 1,
+            "moderation.max_connections_per_ip has to be at least 1".to_string(),
+        );
+        check(!moderation.ban_file.trim().is_empty(), "moderation.ban_file can't be empty".to_string());
+
+        check(
+            self.admin.remote_address.port() != self.network.port || self.admin.remote_address.ip() != self.network.bind_address,
+            "admin.remote_address can't be the same as the game port".to_string(),
+        );
+        for admin in &self.admin.chat_admins {
+            check(
+                !matches!(admin, BanTarget::Name(_)),
+                format!("admin.chat_admins only takes addresses and ranges, anyone can take the name in {}", admin),
+            );
+        }
+        check(!self.logging.dir.trim().is_empty(), "logging.dir can't be empty".to_string());
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Invalid server config:\n  - {}", errors.join("\n  - ")))
+        }
+    }
+}
+
+/// Command line flags that override the config file
+#[derive(Args, Debug)]
+pub struct ServerArgs {
+    /// Config file to load, the defaults are used if it doesn't exist
+    #[arg(long, default_value = DEFAULT_SERVER_CONFIG_PATH)]
+    pub config: PathBuf,
+    /// Address to listen on
+    #[arg(long)]
+    pub bind: Option<IpAddr>,
+    #[arg(long)]
+    pub port: Option<u16>,
+    /// Fixed updates per second
+    #[arg(long)]
+    pub tick_rate: Option<f64>,
+    #[arg(long)]
+    pub max_players: Option<usize>,
+    /// Where the server log is written
+    #[arg(long)]
+    pub log_dir: Option<String>,
+    /// Address the remote admin endpoint listens on
+    #[arg(long)]
+    pub admin_address: Option<SocketAddr>,
+    /// Don't read console commands from stdin, for running as a service
+    #[arg(long)]
+    pub no_console: bool,
+}
+
+impl ServerArgs {
+    /// Loads the config file, applies the flags on top and validates the result
+    pub fn load_config(&self) -> Result<ServerConfig, String> {
+        let mut config = ServerConfig::load(&self.config)?;
+
+        if let Some(bind) = self.bind {
+            config.network.bind_address = bind;
+        }
+        if let Some(port) = self.port {
+            config.network.port = port;
+        }
+        if let Some(tick_rate) = self.tick_rate {
+            config.simulation.tick_rate = tick_rate;
+        }
+        if let Some(max_players) = self.max_players {
+            config.gameplay.max_players_per_lobby = max_players;
+        }
+        if let Some(log_dir) = &self.log_dir {
+            config.logging.dir = log_dir.clone();
+        }
+        if let Some(admin_address) = self.admin_address {
+            config.admin.remote_address = admin_address;
+        }
+        if self.no_console {
+            config.admin.console = false;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+}
@@ -1,22 +1,74 @@
+mod admin;
 mod components;
+mod config;
 mod network;
+#[cfg(test)]
 mod test;
 mod client_plugin;
 mod server_plugin;
+mod server_log;
 
 use bevy::prelude::*;
-use std::io;
+use clap::{Parser, Subcommand};
+use std::process::ExitCode;
+use crate::client_plugin::ClientPlugin;
+use crate::config::ServerArgs;
+use crate::server_log::query::QueryArgs;
+use crate::server_plugin::ServerPlugin;
+
+#[derive(Parser)]
+#[command(about = "Multiplayer shooter client and dedicated server")]
+struct Cli {
+    #[command(subcommand)]
+    mode: Option<Mode>,
+}
+
+#[derive(Subcommand)]
+enum Mode {
+    /// Starts the game and connects to a server, the default
+    Client {
+        /// Server address, "host" or "host:port". Defaults to the last server connected to.
+        address: Option<String>,
+    },
+    /// Runs a headless dedicated server
+    Server(ServerArgs),
+    /// Filters the server log by player, time range and event type
+    QueryLog(QueryArgs),
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let mode = cli.mode.unwrap_or(Mode::Client { address: None });
 
-fn main() -> io::Result<()> {
-    let args: Vec<String> = std::env::args().collect();
-    let default_address = "127.0.0.1:4444".to_string();
-    let remote_address = args.get(1).unwrap_or(&default_address);
-    
     let mut app = App::new();
-    
-    
-    
+
+    match mode {
+        Mode::Client { address } => {
+            app.add_plugins(ClientPlugin { remote_address: address });
+        }
+        Mode::Server(args) => {
+            let config = match args.load_config() {
+                Ok(c) => c,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return ExitCode::from(2);
+                }
+            };
+            app.add_plugins(ServerPlugin::new(config));
+        }
+        // Just reads files, no app needed
+        Mode::QueryLog(args) => {
+            return match server_log::query::run(args) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    ExitCode::FAILURE
+                }
+            };
+        }
+    }
+
     app.run();
 
-    Ok(())
+    ExitCode::SUCCESS
 }
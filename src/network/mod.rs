@@ -1,3 +1,4 @@
+pub mod net_bans;
 pub mod net_manage;
 pub mod net_message;
 pub mod net_reconciliation;
@@ -0,0 +1,256 @@
+use bevy::prelude::{info, Local, Res, ResMut, Resource, Time};
+use chrono::{TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub const DEFAULT_BAN_FILE: &str = "bans.json";
+pub const DEFAULT_MAX_CONNECTIONS_PER_IP: usize = 4;
+const NAME_BAN_PREFIX: &str = "name:";
+/// Leading bits of an IPv4 mapped IPv6 address, "::ffff:"
+const MAPPED_PREFIX: u8 = 96;
+// How often expired bans are dropped from the file
+const BAN_EXPIRY_CHECK_SECONDS: f32 = 60.0;
+
+/// What a ban applies to, written as "1.2.3.4", "10.0.0.0/8" or "name:Player" in the ban file
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum BanTarget {
+    Address(IpAddr),
+    Range { network: IpAddr, prefix: u8 },
+    Name(String),
+}
+
+impl BanTarget {
+    pub fn matches_ip(&self, ip: IpAddr) -> bool {
+        match self {
+            BanTarget::Address(address) => address.to_canonical() == ip.to_canonical(),
+            BanTarget::Range { network, prefix } => match (network, ip.to_canonical()) {
+                (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                    let mask = u32::MAX.checked_shl(32 - *prefix as u32).unwrap_or(0);
+                    u32::from(*network) & mask == u32::from(ip) & mask
+                }
+                // Ranges wider than the mapped IPv4 space can still cover IPv4 clients
+                (IpAddr::V6(network), ip) => {
+                    let ip = match ip {
+                        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                        IpAddr::V6(ip) => ip,
+                    };
+                    let mask = u128::MAX.checked_shl(128 - *prefix as u32).unwrap_or(0);
+                    u128::from(*network) & mask == u128::from(ip) & mask
+                }
+                _ => false,
+            },
+            BanTarget::Name(_) => false,
+        }
+    }
+
+    pub fn matches_name(&self, name: &str) -> bool {
+        match self {
+            BanTarget::Name(banned) => banned.eq_ignore_ascii_case(name.trim()),
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for BanTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(name) = s.strip_prefix(NAME_BAN_PREFIX) {
+            if name.trim().is_empty() {
+                return Err("Name bans need a name".to_string());
+            }
+            return Ok(BanTarget::Name(name.trim().to_string()));
+        }
+
+        match s.split_once('/') {
+            Some((network, prefix)) => {
+                let network = network.parse::<IpAddr>().map_err(|_| format!("{} isn't an IP range", s))?;
+                let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+                let prefix = prefix
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|p| *p <= max_prefix)
+                    .ok_or_else(|| format!("{} has an invalid prefix length", s))?;
+                // "::ffff:10.0.0.0/104" is the IPv4 range 10.0.0.0/8
+                match network.to_canonical() {
+                    IpAddr::V4(v4) if network.is_ipv6() && prefix >= MAPPED_PREFIX => Ok(BanTarget::Range {
+                        network: IpAddr::V4(v4),
+                        prefix: prefix - MAPPED_PREFIX,
+                    }),
+                    _ => Ok(BanTarget::Range { network, prefix }),
+                }
+            }
+            None => s
+                .parse::<IpAddr>()
+                .map(|ip| BanTarget::Address(ip.to_canonical()))
+                .map_err(|_| format!("{} isn't an IP address, range or name:<player>", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for BanTarget {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<BanTarget> for String {
+    fn from(value: BanTarget) -> Self {
+        value.to_string()
+    }
+}
+
+impl fmt::Display for BanTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BanTarget::Address(address) => write!(f, "{}", address),
+            BanTarget::Range { network, prefix } => write!(f, "{}/{}", network, prefix),
+            BanTarget::Name(name) => write!(f, "{}{}", NAME_BAN_PREFIX, name),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Ban {
+    pub target: BanTarget,
+    pub reason: String,
+    /// Unix time in milliseconds
+    pub created: i64,
+    /// Unix time in milliseconds, None for permanent bans
+    pub expires: Option<i64>,
+}
+
+impl Ban {
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires.is_some_and(|e| e <= now)
+    }
+
+    /// What a banned player is told when they get disconnected
+    pub fn message(&self) -> String {
+        let until = self
+            .expires
+            .and_then(|e| Utc.timestamp_millis_opt(e).single())
+            .map(|t| format!(" until {} UTC", t.format("%Y-%m-%d %H:%M")))
+            .unwrap_or_default();
+        format!("Banned{}: {}", until, self.reason)
+    }
+}
+
+/// Bans checked when a player connects or picks a name, saved to a JSON file after every change
+#[derive(Resource)]
+pub struct BanList {
+    bans: Vec<Ban>,
+    path: Option<PathBuf>,
+    pub max_connections_per_ip: usize,
+}
+
+impl Default for BanList {
+    fn default() -> Self {
+        Self {
+            bans: Vec::new(),
+            path: None,
+            max_connections_per_ip: DEFAULT_MAX_CONNECTIONS_PER_IP,
+        }
+    }
+}
+
+impl BanList {
+    /// Starts empty if the file doesn't exist yet
+    pub fn load(path: impl Into<PathBuf>, max_connections_per_ip: usize) -> Result<Self, String> {
+        let path = path.into();
+        let bans = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str::<Vec<Ban>>(&contents)
+                .map_err(|e| format!("Couldn't read ban list {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(format!("Couldn't open ban list {}: {}", path.display(), e)),
+        };
+        info!("Loaded {} bans from {}", bans.len(), path.display());
+
+        Ok(Self {
+            bans,
+            path: Some(path),
+            max_connections_per_ip,
+        })
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let result = serde_json::to_string_pretty(&self.bans)
+            .map_err(|e| e.to_string())
+            .and_then(|json| fs::write(path, json).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            eprintln!("Couldn't save ban list {}: {}", path.display(), e);
+        }
+    }
+
+    /// Replaces any existing ban on the same target
+    pub fn ban(&mut self, target: BanTarget, reason: String, duration: Option<Duration>) -> &Ban {
+        let now = Utc::now().timestamp_millis();
+        self.bans.retain(|b| b.target != target);
+        self.bans.push(Ban {
+            target,
+            reason,
+            created: now,
+            expires: duration.map(|d| now + d.as_millis() as i64),
+        });
+        self.save();
+        self.bans.last().unwrap()
+    }
+
+    pub fn unban(&mut self, target: &BanTarget) -> bool {
+        let count = self.bans.len();
+        self.bans.retain(|b| b.target != *target);
+        let removed = self.bans.len() != count;
+        if removed {
+            self.save();
+        }
+        removed
+    }
+
+    pub fn bans(&self) -> impl Iterator<Item = &Ban> {
+        let now = Utc::now().timestamp_millis();
+        self.bans.iter().filter(move |b| !b.is_expired(now))
+    }
+
+    pub fn find_ip(&self, ip: IpAddr) -> Option<&Ban> {
+        self.bans().find(|b| b.target.matches_ip(ip))
+    }
+
+    pub fn find_name(&self, name: &str) -> Option<&Ban> {
+        self.bans().find(|b| b.target.matches_name(name))
+    }
+
+    fn remove_expired(&mut self) {
+        let now = Utc::now().timestamp_millis();
+        let count = self.bans.len();
+        self.bans.retain(|b| !b.is_expired(now));
+        if self.bans.len() != count {
+            info!("Removed {} expired bans", count - self.bans.len());
+            self.save();
+        }
+    }
+}
+
+pub fn expire_bans(
+    mut bans: ResMut<BanList>,
+    time: Res<Time>,
+    mut since_check: Local<f32>,
+) {
+    *since_check += time.delta_secs();
+    if *since_check < BAN_EXPIRY_CHECK_SECONDS {
+        return;
+    }
+    *since_check = 0.0;
+    bans.remove_expired();
+}
@@ -1,15 +1,21 @@
 use std::any::Any;
+use crate::components::common::Id;
 use crate::network::net_message::{NetworkMessage, CTcpType, CUdpType, SUdpType, NetworkMessageType};
 use bevy::prelude::{Component, Resource};
 use std::collections::{HashSet, VecDeque};
 use std::io::Error;
-use std::net::SocketAddr;
+use std::net::{IpAddr, Shutdown, SocketAddr};
 use std::sync::Arc;
+use socket2::SockRef;
 use tokio::io;
 use tokio::io::Interest;
 use tokio::net::{TcpSocket, TcpStream, UdpSocket};
 use tokio::sync::mpsc::{Receiver, Sender};
 
+/// Largest datagram either side reads, small enough to go out unfragmented.
+/// A full lobby's snapshot has to fit, which is what caps MAX_PLAYERS_PER_LOBBY.
+pub const MAX_DATAGRAM_SIZE: usize = 1200;
+
 #[derive(Resource)]
 pub struct Communication {
     pub udp_tx: Sender<(Vec<u8>, SocketAddr)>,
@@ -23,7 +29,8 @@ pub struct UdpConnection<T> where T: NetworkMessageType {
     pub socket: Option<SocketAddr>,
     pub input_packet_buffer: VecDeque<Packet>,
     output_message: Vec<NetworkMessage<T>>,
-    pub ping: u32
+    pub ping: u32,
+    pub player_id: Option<Id>,
 }
 
 #[derive(Component, Debug)]
@@ -31,7 +38,14 @@ pub struct TcpConnection<T> where T: NetworkMessageType {
     pub stream: Option<Arc<TcpStream>>,
     pub input_packet_buffer: VecDeque<Packet>,
     output_message: Vec<NetworkMessage<T>>,
-    pub ping: u32
+    pub ping: u32,
+    pub player_id: Option<Id>,
+    /// Secret the client echoes over UDP so its UDP connection can be bound to this player
+    pub session_token: Option<u64>,
+    /// Set once the remote end has gone away, the connection gets cleaned up on the next tick
+    pub closed: bool,
+    /// Set to hang up on the remote end once the queued messages have been sent
+    pub disconnecting: bool,
 }
 
 #[derive(Component, Debug)]
@@ -62,7 +76,8 @@ where T: NetworkMessageType + 'static {
             socket: ip_addrs,
             input_packet_buffer: VecDeque::new(),
             output_message: Vec::new(),
-            ping: 0
+            ping: 0,
+            player_id: None,
         }
     }
 
@@ -98,7 +113,11 @@ impl<T> TcpConnection<T> where T: NetworkMessageType {
             stream,
             input_packet_buffer: Default::default(),
             output_message: vec![],
-            ping: 0
+            ping: 0,
+            player_id: None,
+            session_token: None,
+            closed: false,
+            disconnecting: false,
         }
     }
 
@@ -117,6 +136,11 @@ impl<T> TcpConnection<T> where T: NetworkMessageType {
     pub fn clear_messages(&mut self) {
         self.output_message.clear();
     }
+
+    /// Address the remote end connects from, IPv4 clients on a dual stack socket come out as plain IPv4
+    pub fn peer_ip(&self) -> Option<IpAddr> {
+        self.stream.as_ref().and_then(|s| s.peer_addr().ok()).map(|a| a.ip().to_canonical())
+    }
 }
 
 pub async fn start_udp_connection(
@@ -136,7 +160,7 @@ pub async fn start_udp_connection(
         let s = socket.clone();
 
         tokio::spawn(async move {
-            let mut buf = vec![0u8; 1024];
+            let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
             loop {
                 match s.clone().recv_from(&mut buf).await {
                     Ok((len, addr)) => {
@@ -167,7 +191,7 @@ pub async fn start_tcp_connection(
     mut outbound: Receiver<(Vec<u8>, Arc<TcpStream>)>,
     inbound: Sender<(Vec<u8>, Arc<TcpStream>)>,
 ) -> Result<(), Error> {
-    let socket = TcpSocket::new_v4()?;
+    let socket = if remote_addr.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
 
     let inbound_accept = inbound.clone();
     // Task responsible for accepting new TCP connections
@@ -245,7 +269,7 @@ pub async fn start_udp_listener(
         let inbound_tx = inbound.clone();
 
         tokio::spawn(async move {
-            let mut buf = vec![0u8; 1024];
+            let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
             loop {
                 match recv_sock.recv_from(&mut buf).await {
                     Ok((len, addr)) => {
@@ -277,7 +301,7 @@ pub async fn start_tcp_listener(
     mut outbound: Receiver<(Vec<u8>, Arc<TcpStream>)>,
     inbound: Sender<(Vec<u8>, Arc<TcpStream>)>,
 ) -> Result<(), Error> {
-    let socket = TcpSocket::new_v4()?;
+    let socket = if bind_addr.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
     //TODO: Figure out the equivalent on windows. I've read that one way is to create a raw
     // socket and set the windows equivalent of this and then cast it as a tokio socket
     // https://stackoverflow.com/questions/40468685/how-to-set-the-socket-option-so-reuseport-in-rust
@@ -310,15 +334,22 @@ pub async fn start_tcp_listener(
                                 let mut buf = vec![0u8; 1024];
 
                                 match stream_arc_inner.try_read(&mut buf) {
-                                    Ok(0) => break,
+                                    Ok(0) => {
+                                        // An empty message tells the game loop the connection closed
+                                        let _ = inbound_arc.send((vec![], stream_arc_inner.clone())).await;
+                                        break;
+                                    }
                                     Ok(len) => {
                                         println!("buf: {:?}", &buf[..len]);
                                         let _ = inbound_arc
                                             .send((buf[..len].to_vec(), stream_arc_inner.clone()))
                                             .await;
                                     }
+                                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
                                     Err(e) => {
                                         println!("Couldn't read: {:?}", e);
+                                        let _ = inbound_arc.send((vec![], stream_arc_inner.clone())).await;
+                                        break;
                                     }
                                 }
                             }
@@ -334,6 +365,14 @@ pub async fn start_tcp_listener(
 
     tokio::spawn(async move {
         while let Some((bytes, stream)) = outbound.recv().await {
+            // An empty message asks for the connection to be closed, the read task then reports it as gone
+            if bytes.is_empty() {
+                if let Err(e) = SockRef::from(&*stream).shutdown(Shutdown::Both) {
+                    println!("Couldn't shut down connection: {:?}", e);
+                }
+                continue;
+            }
+
             let ready = stream.ready(Interest::WRITABLE).await.unwrap();
 
             if ready.is_writable() {
@@ -1,7 +1,14 @@
 use std::collections::HashMap;
-use crate::components::chat::ChatMessage;
-use crate::components::common::{Id};
+use crate::components::actions::{ActionFlags, AnalogMove};
+use crate::components::chat::{ChatMessage, ChatTarget};
+use crate::components::common::{Id, Vec3};
+use crate::components::game_mode::{GameModeKind, MatchState};
+use crate::components::lobby::LobbyInfo;
+use crate::components::player::controller::MovementSettings;
 use crate::components::player::PlayerState;
+use crate::components::prop::PropState;
+use crate::components::profile::PlayerProfile;
+use crate::components::scoreboard::ScoreEntry;
 use bevy::prelude::{Component, Vec2};
 use serde::{Deserialize, Serialize};
 
@@ -11,17 +18,18 @@ pub trait NetworkMessageType {}
 pub struct NetworkMessage<T: NetworkMessageType>(pub T);
 
 pub type SequenceNumber = u16;
-pub type BitMask = u16;
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum CUdpType {
-    PlayerId {
-        id: Id,
+    /// Binds the UDP connection to the player, the token is handed out over TCP
+    Session {
+        token: u64,
     },
     Sequence {
         sequence_number: SequenceNumber,
     },
     Input {
-        keymask: BitMask,
+        actions: ActionFlags,
+        movement: AnalogMove,
         mouse_delta: Vec2,
     },
     Ping {
@@ -38,6 +46,10 @@ pub enum SUdpType {
     Players {
         players: HashMap<Id, PlayerState>,
     },
+    /// Moving platforms and crates of the lobby, keyed by prop id
+    Props {
+        props: HashMap<u32, PropState>,
+    },
     Pong {
         initiation_time: u32,
         server_received_time: u32,
@@ -50,22 +62,99 @@ impl NetworkMessageType for SUdpType {}
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum CTcpType {
     ChatMessage {
-        player_id: Id,
-        message: ChatMessage,
+        target: ChatTarget,
+        message: String,
     },
     Join {
         lobby_id: Id,
+        /// Watch instead of play. Joining a full lobby spectates either way.
+        spectate: bool,
+    },
+    SetSpectating {
+        spectating: bool,
+    },
+    Hit {
+        target: Id,
+    },
+    ListLobbies,
+    CreateLobby {
+        name: String,
+        mode: GameModeKind,
+    },
+    LeaveLobby,
+    SetReady {
+        ready: bool,
+    },
+    SetProfile {
+        profile: PlayerProfile,
+    },
+    /// Asks for the last few messages of the lobby's chat, sent after joining
+    ChatBacklog {
+        count: usize,
     },
-    
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum STcpType {
     PlayerId {
         player_uid: Id,
+        session_token: u64,
+        movement: MovementSettings,
+        /// Simulation ticks per second, the client predicts one input per tick at the same rate
+        tick_rate: f64,
+        /// Width and depth of the lobby ground, predicted against on the client
+        ground_size: [f32; 2],
     },
     Chat {
-        messages: Vec<(Id, ChatMessage)>
+        messages: Vec<ChatMessage>
+    },
+    Scoreboard {
+        entries: HashMap<Id, ScoreEntry>,
+    },
+    MatchState {
+        state: MatchState,
+    },
+    PlayerKilled {
+        killer: Id,
+        victim: Id,
+        /// Seconds until the victim spawns again
+        respawn_time: f32,
+    },
+    Respawn {
+        position: Vec3,
+    },
+    /// The server's simulation rate changed while connected
+    TickRate {
+        tick_rate: f64,
+    },
+    LobbyList {
+        lobbies: Vec<LobbyInfo>,
+    },
+    LobbyJoined {
+        lobby: LobbyInfo,
+        spectating: bool,
+    },
+    /// Sent when the player switches between playing and watching
+    Spectating {
+        spectating: bool,
+    },
+    LobbyLeft,
+    JoinRejected {
+        reason: String,
+    },
+    LobbyMembers {
+        members: Vec<(Id, bool)>,
+        spectators: Vec<Id>,
+    },
+    Profiles {
+        profiles: HashMap<Id, PlayerProfile>,
+    },
+    /// Text meant only for this player, like command replies and whispers
+    Notice {
+        message: String,
+    },
+    Kicked {
+        reason: String,
     },
 }
 
@@ -1,5 +1,5 @@
 use std::collections::HashMap;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs};
 use std::str::FromStr;
 use std::sync::Arc;
 use avian3d::parry::na::DimAdd;
@@ -10,6 +10,10 @@ use bevy_tokio_tasks::{TokioTasksPlugin, TokioTasksRuntime};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc;
 use crate::components::chat::send_chat_to_all_connections;
+use crate::components::common::IdPool;
+use crate::components::lobby::{handle_disconnects, send_lobby_members};
+use crate::components::profile::{send_profiles_to_all_connections, PlayerProfiles};
+use crate::components::scoreboard::{send_scoreboard_to_all_connections, update_scoreboard};
 use crate::components::player::PlayerState;
 use crate::network;
 use crate::network::net_manage::{start_tcp_connection, start_tcp_listener, start_udp_connection, start_udp_listener, Communication, TcpConnection, UdpConnection};
@@ -19,12 +23,42 @@ use crate::network::net_reconciliation::StateType::{Input, Player};
 use crate::network::net_system::{client_tcp_net_receive, client_tcp_net_send, server_tcp_net_receive, client_udp_net_receive, client_udp_net_send, server_udp_net_receive, server_udp_net_send, server_tcp_net_send};
 use crate::network::net_tasks::{add_ping_message, build_connection_messages, client_handle_tcp_message, client_handle_udp_message, server_handle_tcp_message, server_handle_udp_message};
 
+pub const DEFAULT_PORT: u16 = 4444;
+
+/// Server the client connects to, "host" or "host:port"
 #[derive(Resource)]
 pub struct RemoteAddress(pub String);
 
+impl RemoteAddress {
+    pub fn socket_address(&self) -> Option<SocketAddr> {
+        let address = self.0.trim();
+        SocketAddr::from_str(address)
+            .ok()
+            .or_else(|| IpAddr::from_str(address).ok().map(|ip| SocketAddr::new(ip, DEFAULT_PORT)))
+            .or_else(|| {
+                // Host names are resolved once when connecting
+                let with_port = if address.contains(':') { address.to_string() } else { format!("{}:{}", address, DEFAULT_PORT) };
+                with_port.to_socket_addrs().ok()?.find(|a| a.is_ipv4())
+            })
+    }
+}
+
 #[derive(Resource, Clone, Copy)]
 pub struct NetworkConfig {
     pub host_type: HostType,
+    /// Where the server listens, unused by clients
+    pub bind_address: SocketAddr,
+    pub udp_pool_size: usize,
+}
+
+impl NetworkConfig {
+    pub fn client() -> Self {
+        Self {
+            host_type: HostType::Client,
+            bind_address: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            udp_pool_size: 1,
+        }
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -85,6 +119,9 @@ impl Plugin for NetworkPlugin {
             }
             HostType::Server => {
                 app.add_plugins(TokioTasksPlugin::default())
+                    .insert_resource(self.config)
+                    .init_resource::<IdPool>()
+                    .init_resource::<PlayerProfiles>()
                     .add_systems(PreStartup, setup_communications)
                     .add_systems(
                         FixedPreUpdate,
@@ -93,15 +130,24 @@ impl Plugin for NetworkPlugin {
                             server_tcp_net_receive,
                             server_handle_udp_message.after(server_udp_net_receive),
                             server_handle_tcp_message.after(server_tcp_net_receive),
+                            handle_disconnects.after(server_handle_tcp_message),
                         ),
                     )
                     .add_systems(
                         FixedPostUpdate,
                         (
                             send_chat_to_all_connections,
+                            send_lobby_members,
+                            send_profiles_to_all_connections,
+                            update_scoreboard,
+                            send_scoreboard_to_all_connections.after(update_scoreboard),
                             build_connection_messages,
                             server_udp_net_send.after(build_connection_messages),
-                            server_tcp_net_send.after(send_chat_to_all_connections),
+                            server_tcp_net_send
+                                .after(send_chat_to_all_connections)
+                                .after(send_lobby_members)
+                                .after(send_profiles_to_all_connections)
+                                .after(send_scoreboard_to_all_connections),
                         ),
                     );
             }
@@ -114,7 +160,7 @@ impl Plugin for NetworkPlugin {
 fn setup_communications(
     mut commands: Commands,
     network_config: Res<NetworkConfig>,
-    remote_addr_resource: Res<RemoteAddress>,
+    remote_addr_resource: Option<Res<RemoteAddress>>,
     runtime: Res<TokioTasksRuntime>
 ) {
     println!("Setting up communications...");
@@ -125,27 +171,27 @@ fn setup_communications(
 
     match network_config.host_type {
         HostType::Client => {
-            let remote_string = remote_addr_resource.0.clone();
-            runtime.spawn_background_task(|_| async move {
+            let remote_addr = remote_addr_resource
+                .and_then(|r| r.socket_address())
+                .unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], DEFAULT_PORT)));
+            runtime.spawn_background_task(move |_| async move {
                 println!("starting communication");
-                println!("remote address: {}", remote_string);
-                let remote_addr = SocketAddr::from_str(format!("{}:4444", remote_string).as_str())
-                    .ok()
-                    .unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 4444)));
+                println!("remote address: {}", remote_addr);
 
                 start_tcp_connection(remote_addr, tcp_send_rx, tcp_receive_tx).await.unwrap();
                 start_udp_connection(remote_addr, udp_send_rx, udp_receive_tx, 1).await.unwrap();
             });
         }
         HostType::Server => {
-            runtime.spawn_background_task(|_| async move {
-                let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 4444);
-                println!("Server starting; listening on 0.0.0.0:4444...");
+            let addr = network_config.bind_address;
+            let udp_pool_size = network_config.udp_pool_size;
+            runtime.spawn_background_task(move |_| async move {
+                println!("Server starting; listening on {}...", addr);
 
                 start_tcp_listener(addr, tcp_send_rx, tcp_receive_tx)
                     .await
                     .unwrap();
-                start_udp_listener(addr, udp_send_rx, udp_receive_tx, 8)
+                start_udp_listener(addr, udp_send_rx, udp_receive_tx, udp_pool_size)
                     .await
                     .unwrap();
             });
@@ -1,5 +1,6 @@
+use crate::components::actions::{ActionFlags, AnalogMove};
 use crate::components::player::PlayerState;
-use crate::network::net_message::{BitMask, NetworkMessage, SequenceNumber, CUdpType};
+use crate::network::net_message::{NetworkMessage, SequenceNumber, CUdpType};
 use bevy::prelude::{info, Commands, Component, Entity, Query, ResMut, Resource, Vec2};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -16,7 +17,7 @@ pub struct ObjectState(pub StateType);
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum StateType {
     Player { player: PlayerState },
-    Input { encoded_input: BitMask, mouse_delta: Vec2 }
+    Input { actions: ActionFlags, movement: AnalogMove, mouse_delta: Vec2 }
 }
 
 /// Holds a circular buffer of the last BUFFER_SIZE amount of game states
@@ -1,10 +1,15 @@
-use crate::network::net_manage::{Communication, Packet, TcpConnection, UdpConnection};
+use crate::network::net_manage::{Communication, Packet, TcpConnection, UdpConnection, MAX_DATAGRAM_SIZE};
 use crate::network::net_reconciliation::{StateTimeline, ObjectState, build_game_state, sequence_message, store_game_state};
-use bevy::prelude::{info, Commands, Entity, Query, ResMut, Single};
+use bevy::prelude::{info, Commands, Entity, Query, Res, ResMut, Single};
 use bincode::config;
+use rand::Rng;
+use std::sync::Arc;
 use tokio::net::TcpStream;
 use tokio::sync::mpsc::error::{TryRecvError, TrySendError};
-use crate::network::net_message::{CTcpType, CUdpType, STcpType, SUdpType};
+use crate::components::common::IdPool;
+use crate::config::ServerConfig;
+use crate::network::net_bans::BanList;
+use crate::network::net_message::{CTcpType, CUdpType, NetworkMessage, STcpType, SUdpType};
 
 pub fn client_udp_net_receive(
     mut comm: ResMut<Communication>,
@@ -111,10 +116,12 @@ pub fn client_tcp_net_send(
 pub fn server_udp_net_receive(
     mut comm: ResMut<Communication>,
     mut connections: Query<&mut UdpConnection<SUdpType>>,
+    bans: Res<BanList>,
     mut commands: Commands,
 ) {
     while !comm.udp_rx.is_empty() {
         match comm.udp_rx.try_recv() {
+            Ok((_, socket)) if bans.find_ip(socket.ip()).is_some() => {}
             Ok((bytes, socket)) => {
                 let c = connections
                     .iter_mut()
@@ -157,6 +164,9 @@ pub fn server_udp_net_send(comm: ResMut<Communication>, mut connections: Query<&
                     continue;
                 }
             };
+        if encoded_message.len() > MAX_DATAGRAM_SIZE {
+            eprintln!("UDP message of {} bytes is over the {} clients read", encoded_message.len(), MAX_DATAGRAM_SIZE);
+        }
 
         match comm.udp_tx.try_send((encoded_message.clone(), c.socket.unwrap())) {
             Ok(()) => {
@@ -172,25 +182,67 @@ pub fn server_tcp_net_receive(
     mut commands: Commands,
     mut connections: Query<&mut TcpConnection<STcpType>>,
     mut comm: ResMut<Communication>,
+    mut id_pool: ResMut<IdPool>,
+    bans: Res<BanList>,
+    server_config: Res<ServerConfig>,
 ) {
     while !comm.tcp_rx.is_empty() {
         match comm.tcp_rx.try_recv() {
             Ok((bytes, stream)) => {
                 let c = connections
                     .iter_mut()
-                    .find(|x| same_stream(&*x.stream.clone().unwrap(), &*stream));
+                    .find(|x| x.stream.as_ref().is_some_and(|s| Arc::ptr_eq(s, &stream)));
 
                 match c {
+                    // Empty messages mean the connection was closed
+                    Some(mut c) if bytes.is_empty() => {
+                        c.closed = true;
+                    }
                     Some(mut c) => {
                         c.input_packet_buffer.push_back(Packet {
                             bytes: bytes.clone(),
                         });
                     }
+                    None if bytes.is_empty() => {}
                     None => {
+                        let Ok(address) = stream.peer_addr() else {
+                            continue;
+                        };
+                        if let Some(ban) = bans.find_ip(address.ip()) {
+                            println!("Refused connection from banned address {}", address);
+                            refuse_connection(&comm, stream, ban.message());
+                            continue;
+                        }
+                        let from_same_ip = connections
+                            .iter()
+                            .filter(|c| c.stream.as_ref().and_then(|s| s.peer_addr().ok()).is_some_and(|a| a.ip() == address.ip()))
+                            .count();
+                        if from_same_ip >= bans.max_connections_per_ip {
+                            println!("Refused connection from {}, already {} connections from there", address, from_same_ip);
+                            refuse_connection(&comm, stream, "Too many connections from your address".to_string());
+                            continue;
+                        }
+
+                        let Some(player_id) = id_pool.allocate() else {
+                            println!("No player ids left, refused connection from {}", address);
+                            refuse_connection(&comm, stream, "Server is full".to_string());
+                            continue;
+                        };
+                        let session_token = rand::rng().random::<u64>();
+
                         let mut conn = TcpConnection::<STcpType>::new(Some(stream));
+                        conn.player_id = Some(player_id);
+                        conn.session_token = Some(session_token);
                         conn.input_packet_buffer.push_back(Packet {
                             bytes: bytes.clone(),
                         });
+                        conn.add_message(NetworkMessage(STcpType::PlayerId {
+                            player_uid: player_id,
+                            session_token,
+                            movement: server_config.simulation.movement(),
+                            tick_rate: server_config.simulation.tick_rate,
+                            ground_size: server_config.gameplay.ground_size,
+                        }));
                         commands.spawn(conn);
                     }
                 }
@@ -201,35 +253,43 @@ pub fn server_tcp_net_receive(
     }
 }
 
+/// Tells a client why it isn't let in and hangs up, used before a connection entity exists
+fn refuse_connection(comm: &Communication, stream: Arc<TcpStream>, reason: String) {
+    let message = vec![NetworkMessage(STcpType::Kicked { reason })];
+    if let Ok(bytes) = bincode::serde::encode_to_vec(&message, config::standard()) {
+        let _ = comm.tcp_tx.try_send((bytes, stream.clone()));
+    }
+    // An empty message makes the writer task hang up
+    let _ = comm.tcp_tx.try_send((vec![], stream));
+}
+
 pub fn server_tcp_net_send(comm: ResMut<Communication>, mut connections: Query<&mut TcpConnection<STcpType>>) {
     for mut c in connections.iter_mut() {
-        if c.is_empty_messages() {
+        let Some(stream) = c.stream.clone() else {
             continue;
-        }
+        };
 
-        let encoded_message =
-            match bincode::serde::encode_to_vec(c.get_current_messages(), config::standard()) {
-                Ok(m) => m,
-                Err(e) => {
-                    println!("Couldn't encode TCP message: {:?}", e);
-                    continue;
-                }
-            };
+        if !c.is_empty_messages() {
+            let encoded_message =
+                match bincode::serde::encode_to_vec(c.get_current_messages(), config::standard()) {
+                    Ok(m) => m,
+                    Err(e) => {
+                        println!("Couldn't encode TCP message: {:?}", e);
+                        continue;
+                    }
+                };
 
-        match comm
-            .tcp_tx
-            .try_send((encoded_message.clone(), c.stream.clone().unwrap()))
-        {
-            Ok(()) => {
-                println!("OK");
-                c.clear_messages();
+            match comm.tcp_tx.try_send((encoded_message, stream.clone())) {
+                Ok(()) => c.clear_messages(),
+                // Kept queued for the next tick, the other connections still get a go
+                Err(TrySendError::Full(_)) => continue,
+                Err(TrySendError::Closed(_)) => break,
             }
-            Err(TrySendError::Full(_)) => break,
-            Err(TrySendError::Closed(_)) => break,
+        }
+
+        // Retried every tick until the writer takes it, always after the kick message
+        if c.disconnecting && comm.tcp_tx.try_send((vec![], stream)).is_ok() {
+            c.disconnecting = false;
         }
     }
 }
-
-fn same_stream(a: &TcpStream, b: &TcpStream) -> bool {
-    a.peer_addr().ok() == b.peer_addr().ok() && a.local_addr().ok() == b.local_addr().ok()
-}
\ No newline at end of file
@@ -1,30 +1,44 @@
 use std::cmp::min;
 use std::collections::HashMap;
 use std::time::SystemTime;
-use avian3d::prelude::{Collider, LinearVelocity, Position, Rotation, SpatialQueryPipeline};
-use crate::components::chat::{Chat, client_add_chat_message, server_add_chat_message};
+use avian3d::prelude::{Collider, CollisionLayers, LinearVelocity, Position, Rotation, SpatialQueryPipeline};
+use crate::components::chat::{Chat, ChatSequence, CHAT_HISTORY_LEN, client_add_chat_message, client_add_notice, client_clear_chat, handle_chat_backlog, handle_chat_message};
+use crate::components::chat_command::{handle_chat_command, is_command, reply, CommandIssued, Permissions};
+use crate::components::chat_moderation::ChatModeration;
+use crate::config::ServerConfig;
+use crate::network::net_bans::BanList;
 use crate::components::common::Id;
-use crate::components::player::{PlayerInfo, reconcile_player, set_player_id, update_players, PlayerMarker, PredictedPlayerState, PlayerState, PendingInputs, PlayerInput};
+use crate::components::actions::{ActionFlags, AnalogMove};
+use crate::components::player::controller::MotorState;
+use crate::components::player::{PlayerInfo, client_set_tick_rate, reconcile_player, respawn_player, set_player_id, update_players, Dead, PlayerMarker, PredictedPlayerState, PlayerState, PendingInputs, PlayerInput};
 use crate::network::net_manage::{TcpConnection, UdpConnection};
-use crate::network::net_message::{BitMask, CTcpType, CUdpType, NetworkMessage, STcpType, SUdpType, SequenceNumber};
+use crate::network::net_message::{CTcpType, CUdpType, NetworkMessage, STcpType, SUdpType, SequenceNumber};
 use crate::network::net_reconciliation::StateTimeline;
 use bevy::asset::{AssetServer, Assets};
 use bevy::pbr::StandardMaterial;
-use bevy::prelude::{info, warn, AnimationGraph, Commands, Entity, Gizmos, Mesh, Quat, Query, Res, ResMut, Single, Time, Transform, Vec2, With};
+use bevy::prelude::{info, warn, AnimationGraph, Has, Commands, Entity, Fixed, Gizmos, Mesh, MessageWriter, NextState, Or, Quat, Query, Res, ResMut, Single, Time, Transform, Vec2, With, Without};
 use bincode::config;
 use crate::client_plugin::DefaultFont;
 use crate::components::camera::CameraInfo;
-use crate::components::lobby::handle_join;
-use crate::components::player::animation::PlayerAnimationState;
-use crate::network::net_message::CUdpType::{Input, Ping, PlayerId, Sequence};
+use crate::components::lobby::{client_clear_lobby, handle_create_lobby, handle_join, handle_leave, handle_list_lobbies, handle_set_ready, handle_set_spectating, InLobby, Lobby, LobbyIds};
+use crate::components::scoreboard::{client_set_scoreboard, Scoreboard};
+use crate::components::game_mode::{client_set_match_state, Match, MatchStatus, Team};
+use crate::components::hud::{client_add_kill, KillFeed};
+use crate::components::menu::{client_lobby_joined, client_lobby_left, client_set_lobby_list, LobbyBrowser, MenuState};
+use crate::components::weapon::{handle_hit, Health, PlayerKilled, WeaponCooldown};
+use crate::components::profile::{client_set_profiles, handle_set_profile, PlayerProfiles};
+use crate::components::player::animation::{AnimationState, PlayerAnimationState};
+use crate::components::prop::{build_prop_states, update_props, Prop};
+use crate::components::killcam::{client_clear_killcam, client_record_snapshot, client_start_killcam, client_stop_killcam, Killcam};
+use crate::components::spectator::{client_player_killed, client_respawned, client_set_spectating, Spectator};
+use crate::network::net_message::CUdpType::{Input, Ping, Session, Sequence};
 use crate::network::net_message::SUdpType::Pong;
 
-const MESSAGE_PER_TICK_MAX: usize = 20;
 
 struct MessageBuffer {
     sequence_number: i32,
-    player_id: Option<Id>,
-    keymask: BitMask,
+    actions: ActionFlags,
+    movement: AnalogMove,
     mouse_delta: Vec2,
     pong_message: Option<SUdpType>,
 }
@@ -33,6 +47,7 @@ pub fn client_handle_udp_message(
     mut gizmos: Gizmos,
     mut connection: Single<&mut UdpConnection<CUdpType>>,
     mut client_players: Query<(&mut Transform, &Id, Entity, &CameraInfo, &mut PlayerAnimationState, &mut PredictedPlayerState, &Collider), With<PlayerMarker>>,
+    mut client_props: Query<(Entity, &Prop, &mut Position, &mut Rotation, &mut LinearVelocity), Without<PlayerMarker>>,
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     mut animation_graphs: ResMut<Assets<AnimationGraph>>,
@@ -42,6 +57,7 @@ pub fn client_handle_udp_message(
     default_font: Res<DefaultFont>,
     player_info: Res<PlayerInfo>,
     spatial_query: Res<SpatialQueryPipeline>,
+    mut killcam: ResMut<Killcam>,
     time: Res<Time>,
 ) {
     while let Some(p) = connection.input_packet_buffer.pop_front() {
@@ -72,6 +88,7 @@ pub fn client_handle_udp_message(
         for m in decoded_message.0.iter() {
             match m {
                 SUdpType::Players { players } => {
+                    client_record_snapshot(players, time.elapsed_secs(), &mut killcam);
                     reconcile_player(
                         &mut commands,
                         &mut gizmos,
@@ -92,6 +109,9 @@ pub fn client_handle_udp_message(
                         &player_info,
                     );
                 },
+                SUdpType::Props { props } => {
+                    update_props(&mut commands, &mut meshes, &mut materials, props, &mut client_props);
+                }
                 SUdpType::Pong { initiation_time, server_received_time } => {
                     let time_now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis() as u32;
                     let rtt = time_now - *initiation_time;
@@ -107,8 +127,20 @@ pub fn client_handle_udp_message(
 pub fn client_handle_tcp_message(
     mut player_info: ResMut<PlayerInfo>,
     mut chat: Query<&mut Chat>,
+    mut scoreboard: Query<&mut Scoreboard>,
+    mut match_status: Query<&mut MatchStatus>,
+    mut kill_feed: Query<&mut KillFeed>,
+    mut players: Query<(&Id, &mut PredictedPlayerState), With<PlayerMarker>>,
+    lobby_entities: Query<Entity, Or<(With<PlayerMarker>, With<Prop>)>>,
     mut connection: Single<&mut TcpConnection<CTcpType>>,
-    mut reconcile_buffer: ResMut<StateTimeline>
+    mut reconcile_buffer: ResMut<StateTimeline>,
+    mut lobby_browser: ResMut<LobbyBrowser>,
+    mut menu_state: ResMut<NextState<MenuState>>,
+    mut player_profiles: ResMut<PlayerProfiles>,
+    mut spectator: ResMut<Spectator>,
+    mut killcam: ResMut<Killcam>,
+    mut fixed_time: ResMut<Time<Fixed>>,
+    mut commands: Commands,
 ) {
     while let Some(p) = connection.input_packet_buffer.pop_front() {
         let mut decoded_message: (Vec<STcpType>, usize) = match bincode::serde::decode_from_slice(&p.bytes, config::standard()) {
@@ -124,8 +156,74 @@ pub fn client_handle_tcp_message(
                 STcpType::Chat { messages } => {
                     client_add_chat_message(messages, &mut chat);
                 },
-                STcpType::PlayerId { player_uid } => {
-                    set_player_id(&mut player_info, *player_uid, &mut reconcile_buffer);
+                STcpType::PlayerId { player_uid, session_token, movement, tick_rate, ground_size } => {
+                    set_player_id(&mut player_info, *player_uid, *session_token, *movement, *tick_rate, &mut fixed_time, &mut reconcile_buffer);
+                    player_info.ground_size = *ground_size;
+                }
+                STcpType::Scoreboard { entries } => {
+                    client_set_scoreboard(entries, &mut scoreboard);
+                }
+                STcpType::MatchState { state } => {
+                    client_set_match_state(state, &mut match_status);
+                }
+                STcpType::PlayerKilled { killer, victim, respawn_time } => {
+                    client_add_kill(*killer, *victim, &mut kill_feed);
+                    client_player_killed(*killer, *victim, *respawn_time, &player_info, &mut spectator);
+                    client_start_killcam(*killer, *victim, &player_info, &mut killcam);
+                }
+                STcpType::TickRate { tick_rate } => {
+                    client_set_tick_rate(*tick_rate, &mut fixed_time, &mut reconcile_buffer);
+                }
+                STcpType::Respawn { position } => {
+                    respawn_player(*position, &player_info, &mut players, &mut reconcile_buffer);
+                    client_respawned(&mut spectator);
+                    client_stop_killcam(&mut killcam);
+                }
+                STcpType::LobbyList { lobbies } => {
+                    client_set_lobby_list(lobbies, &mut lobby_browser);
+                }
+                STcpType::LobbyJoined { lobby, spectating } => {
+                    info!("Joined lobby {:?}", lobby);
+                    client_clear_lobby(&lobby_entities, &mut commands);
+                    client_set_spectating(*spectating, &mut spectator);
+                    client_clear_killcam(&mut killcam);
+                    client_clear_chat(&mut chat);
+                    connection.add_message(NetworkMessage(CTcpType::ChatBacklog { count: CHAT_HISTORY_LEN }));
+                    client_lobby_joined(lobby, &mut lobby_browser, &mut menu_state);
+                }
+                STcpType::Spectating { spectating } => {
+                    client_set_spectating(*spectating, &mut spectator);
+                    reconcile_buffer.history.clear();
+                }
+                STcpType::LobbyLeft => {
+                    client_clear_lobby(&lobby_entities, &mut commands);
+                    client_set_spectating(false, &mut spectator);
+                    client_clear_killcam(&mut killcam);
+                    client_clear_chat(&mut chat);
+                    reconcile_buffer.history.clear();
+                    client_lobby_left(&mut lobby_browser, &mut menu_state);
+                }
+                STcpType::JoinRejected { reason } => {
+                    warn!("Couldn't join lobby: {}", reason);
+                    lobby_browser.status = Some(reason.clone());
+                }
+                STcpType::LobbyMembers { members, spectators } => {
+                    lobby_browser.members = members.clone();
+                    lobby_browser.spectators = spectators.clone();
+                }
+                STcpType::Profiles { profiles } => {
+                    client_set_profiles(profiles, &mut player_profiles);
+                }
+                STcpType::Notice { message } => {
+                    client_add_notice(message, &mut chat);
+                }
+                STcpType::Kicked { reason } => {
+                    warn!("Kicked from the server: {}", reason);
+                    client_clear_lobby(&lobby_entities, &mut commands);
+                    client_set_spectating(false, &mut spectator);
+                    client_clear_killcam(&mut killcam);
+                    client_lobby_left(&mut lobby_browser, &mut menu_state);
+                    lobby_browser.status = Some(format!("Kicked: {}", reason));
                 }
             }
         }
@@ -134,10 +232,12 @@ pub fn client_handle_tcp_message(
 
 pub fn server_handle_udp_message(
     mut connections: Query<&mut UdpConnection<SUdpType>>,
+    tcp_connections: Query<&TcpConnection<STcpType>>,
     mut players: Query<
         (&Id, &mut LinearVelocity, &mut Rotation, &mut CameraInfo, &mut PlayerAnimationState, &Position, &mut PendingInputs),
         With<PlayerMarker>,
     >,
+    server_config: Res<ServerConfig>,
 ) {
     for mut c in connections.iter_mut() {
         if c.input_packet_buffer.is_empty() {
@@ -146,13 +246,13 @@ pub fn server_handle_udp_message(
 
         let mut current_message = MessageBuffer {
             sequence_number: -1,
-            player_id: None,
-            keymask: 0,
+            actions: ActionFlags::empty(),
+            movement: AnalogMove::default(),
             mouse_delta: Vec2::new(0.0,0.0),
             pong_message: None,
         };
 
-        for _ in 0..min(MESSAGE_PER_TICK_MAX, c.input_packet_buffer.len()) {
+        for _ in 0..min(server_config.network.messages_per_tick, c.input_packet_buffer.len()) {
             match c.input_packet_buffer.pop_front() {
                 Some(p) => {
                     let decoded_message: (Vec<CUdpType>, usize) =
@@ -168,21 +268,32 @@ pub fn server_handle_udp_message(
 
                     for m in decoded_message.0.iter() {
                         match m {
-                            PlayerId {id} => {
-                                current_message.player_id = Some(id.clone())
+                            Session { token } => {
+                                // The player id only ever comes from the TCP connection that owns the token
+                                if c.player_id.is_none() {
+                                    let owner = tcp_connections.iter().find(|t| {
+                                        t.session_token == Some(*token)
+                                            && t.stream.as_ref().and_then(|s| s.peer_addr().ok()).map(|a| a.ip()) == c.socket.map(|s| s.ip())
+                                    });
+                                    if let Some(owner) = owner {
+                                        c.player_id = owner.player_id;
+                                    }
+                                }
                             }
                             Sequence { sequence_number } => {
                                 info!("Received Sequence: {:?}", sequence_number);
                                 current_message.sequence_number = sequence_number.clone() as i32;
                             }
                             Input {
-                                keymask,
+                                actions,
+                                movement,
                                 mouse_delta,
                             } => {
-                                current_message.keymask = *keymask;
+                                current_message.actions = *actions;
+                                current_message.movement = *movement;
                                 current_message.mouse_delta = *mouse_delta;
 
-                                info!("Received Keymask: {:?}", keymask);
+                                info!("Received actions: {:?}", actions);
                             },
                             Ping { start_time: initiation_time, last_rtt } => {
                                 c.ping = *last_rtt;
@@ -192,10 +303,10 @@ pub fn server_handle_udp_message(
                         }
                     }
 
-                    if let Some(id) = current_message.player_id {
+                    if let Some(id) = c.player_id {
                         for mut p in players.iter_mut() {
                             if id == *p.0 {
-                                p.6.buffer.push_back(PlayerInput{keymask: current_message.keymask, mouse_delta: current_message.mouse_delta})
+                                p.6.buffer.push_back(PlayerInput{actions: current_message.actions, movement: current_message.movement, mouse_delta: current_message.mouse_delta})
                             }
                         }
                     }
@@ -225,6 +336,19 @@ pub fn server_handle_udp_message(
 pub fn server_handle_tcp_message(
     mut chat: Query<&mut Chat>,
     mut connections: Query<&mut TcpConnection<STcpType>>,
+    mut lobbies: Query<(Entity, &mut Lobby, &Match)>,
+    mut lobby_ids: ResMut<LobbyIds>,
+    mut profiles: ResMut<PlayerProfiles>,
+    mut players: Query<(&Id, &InLobby, &Position, &mut Health, Option<&Team>, Entity, &CollisionLayers, &mut WeaponCooldown, &CameraInfo), With<PlayerMarker>>,
+    player_entities: Query<(Entity, &Id), With<PlayerMarker>>,
+    mut kills: MessageWriter<PlayerKilled>,
+    permissions: Res<Permissions>,
+    mut chat_sequence: ResMut<ChatSequence>,
+    mut moderation: ResMut<ChatModeration>,
+    bans: Res<BanList>,
+    server_config: Res<ServerConfig>,
+    mut issued_commands: MessageWriter<CommandIssued>,
+    spatial_query: Res<SpatialQueryPipeline>,
     mut commands: Commands,
 ) {
     for mut c in connections.iter_mut() {
@@ -232,7 +356,7 @@ pub fn server_handle_tcp_message(
             continue;
         }
 
-        for _ in 0..min(MESSAGE_PER_TICK_MAX, c.input_packet_buffer.len()) {
+        for _ in 0..min(server_config.network.messages_per_tick, c.input_packet_buffer.len()) {
             match c.input_packet_buffer.pop_front() {
                 Some(p) => {
                     let mut decoded_message: (Vec<CTcpType>, usize) =
@@ -246,13 +370,81 @@ pub fn server_handle_tcp_message(
 
                     for m in decoded_message.0.iter_mut() {
                         match m {
-                            CTcpType::ChatMessage { player_id, message } => {
-                                server_add_chat_message((*player_id, message.clone()), &mut chat);
+                            CTcpType::ChatMessage { target, message } => {
+                                let Some(player_id) = c.player_id else {
+                                    continue;
+                                };
+                                if is_command(message) {
+                                    handle_chat_command(message, &mut c, &permissions, &mut moderation, &mut issued_commands);
+                                    continue;
+                                }
+                                let message = match moderation.check(player_id, c.peer_ip(), message) {
+                                    Ok(m) => m,
+                                    Err(e) => {
+                                        reply(&mut c, e);
+                                        continue;
+                                    }
+                                };
+                                let team = players
+                                    .iter()
+                                    .find(|p| *p.0 == player_id)
+                                    .and_then(|p| p.4.copied())
+                                    .unwrap_or_default();
+                                let lobby = lobbies
+                                    .iter()
+                                    .find(|(_, l, _)| l.members.contains(&player_id));
+                                if let Some(mut lobby_chat) = lobby.and_then(|(e, _, _)| chat.get_mut(e).ok()) {
+                                    let sender_name = profiles.display_name(player_id);
+                                    handle_chat_message(player_id, sender_name, team, *target, &message, &mut c, &mut lobby_chat, &mut chat_sequence);
+                                }
+                            }
+                            CTcpType::ChatBacklog { count } => {
+                                let Some(player_id) = c.player_id else {
+                                    continue;
+                                };
+                                let team = players
+                                    .iter()
+                                    .find(|p| *p.0 == player_id)
+                                    .and_then(|p| p.4.copied())
+                                    .unwrap_or_default();
+                                let lobby = lobbies
+                                    .iter()
+                                    .find(|(_, l, _)| l.members.contains(&player_id));
+                                if let Some(lobby_chat) = lobby.and_then(|(e, _, _)| chat.get(e).ok()) {
+                                    handle_chat_backlog(*count, team, &mut c, lobby_chat);
+                                }
+                            }
+                            CTcpType::Join { lobby_id, spectate } => {
+                                handle_join(*lobby_id, *spectate, &mut c, &mut lobbies, &player_entities, &mut commands);
+                            }
+                            CTcpType::SetSpectating { spectating } => {
+                                handle_set_spectating(*spectating, &mut c, &mut lobbies, &player_entities, &mut commands);
+                            }
+                            CTcpType::Hit { target } => {
+                                if let Some(shooter) = c.player_id {
+                                    handle_hit(shooter, *target, &lobbies, &mut players, &spatial_query, &mut kills);
+                                }
+                            }
+                            CTcpType::ListLobbies => {
+                                handle_list_lobbies(&mut c, &lobbies);
+                            }
+                            CTcpType::CreateLobby { name, mode } => {
+                                handle_create_lobby(name, *mode, &server_config.gameplay, &mut c, &mut lobbies, &mut lobby_ids, &player_entities, &mut commands);
                             }
-                            CTcpType::Join { lobby_id } => {
-                                handle_join(*lobby_id, &mut c, &mut commands);
+                            CTcpType::LeaveLobby => {
+                                handle_leave(&mut c, &mut lobbies, &player_entities, &mut commands);
+                            }
+                            CTcpType::SetReady { ready } => {
+                                handle_set_ready(*ready, &mut c, &mut lobbies);
+                            }
+                            CTcpType::SetProfile { profile } => {
+                                // Every change goes out to all connections, so it's rate limited like /name
+                                if let Some(Err(e)) = c.player_id.map(|id| moderation.check_command(id, None)) {
+                                    reply(&mut c, e);
+                                    continue;
+                                }
+                                handle_set_profile(profile, &mut c, &mut profiles, &bans, &moderation);
                             }
-                            _ => {}
                         }
                     }
                 }
@@ -265,29 +457,43 @@ pub fn server_handle_tcp_message(
 pub fn build_connection_messages(
     mut connections: Query<&mut UdpConnection<SUdpType>>,
     players: Query<
-        (&Id, &Position, &LinearVelocity, &CameraInfo, &PlayerAnimationState),
+        (&Id, &InLobby, &Position, &LinearVelocity, &CameraInfo, &PlayerAnimationState, &MotorState, Has<Dead>),
         With<PlayerMarker>, /*, Changed<Transform>*/
     >,
+    props: Query<(&Prop, &InLobby, &Position, &Rotation, &LinearVelocity)>,
+    lobbies: Query<&Lobby>,
 ) {
-    let changed_players: HashMap<Id, PlayerState> = players
-        .iter()
-        .map(|(i, p, l, c, pas)| {
-            let player = PlayerState::new(
-                p.0.into(),
-                p.0.into(),
-                c.yaw,
-                c.pitch,
-                pas.0
-            );
+    // Snapshots only contain the players sharing a lobby with the receiving connection
+    let mut lobby_players: HashMap<Id, HashMap<Id, PlayerState>> = HashMap::new();
+    for (i, lobby, p, l, c, pas, motor, dead) in players.iter() {
+        let player = PlayerState::new(
+            p.0.into(),
+            l.0.into(),
+            c.yaw,
+            c.pitch,
+            if dead { AnimationState::Dead } else { pas.state },
+            motor.crouching,
+            !dead,
+            pas.firing,
+        );
+
+        lobby_players.entry(lobby.0).or_default().insert(*i, player);
+    }
 
-            (*i, player)
-        })
-        .collect();
+    let lobby_props = build_prop_states(&props);
 
     for mut c in connections.iter_mut() {
+        // Looked up by membership rather than by player so spectators get snapshots too
+        let Some(lobby_id) = c.player_id.and_then(|id| lobbies.iter().find(|l| l.members.contains(&id))).map(|l| l.id) else {
+            continue;
+        };
+
         if c.contains_message_type(SUdpType::Sequence { sequence_number: 0 }) {
             c.add_message(NetworkMessage(SUdpType::Players {
-                players: changed_players.clone(),
+                players: lobby_players.get(&lobby_id).cloned().unwrap_or_default(),
+            }));
+            c.add_message(NetworkMessage(SUdpType::Props {
+                props: lobby_props.get(&lobby_id).cloned().unwrap_or_default(),
             }));
         }
     }
@@ -0,0 +1,104 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+// Shared by the server log writer and the query-log subcommand
+
+pub const LOG_FILE_NAME: &str = "server.log";
+
+/// One line of the server log
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LogEntry {
+    /// Unix time in milliseconds
+    pub timestamp: i64,
+    #[serde(flatten)]
+    pub event: LogEvent,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum LogEvent {
+    Connect {
+        player_id: u32,
+        address: String,
+    },
+    Disconnect {
+        player_id: u32,
+        name: String,
+    },
+    Join {
+        player_id: u32,
+        name: String,
+        lobby_id: u32,
+        lobby_name: String,
+    },
+    Leave {
+        player_id: u32,
+        name: String,
+        lobby_id: u32,
+    },
+    Chat {
+        message_id: u64,
+        lobby_id: u32,
+        sender: u32,
+        sender_name: String,
+        channel: String,
+        /// Only set for whispers
+        recipient: Option<u32>,
+        message: String,
+    },
+    Kill {
+        lobby_id: Option<u32>,
+        killer: u32,
+        killer_name: String,
+        victim: u32,
+        victim_name: String,
+    },
+    Admin {
+        admin: u32,
+        admin_name: String,
+        command: String,
+        details: String,
+    },
+}
+
+impl LogEvent {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LogEvent::Connect { .. } => "connect",
+            LogEvent::Disconnect { .. } => "disconnect",
+            LogEvent::Join { .. } => "join",
+            LogEvent::Leave { .. } => "leave",
+            LogEvent::Chat { .. } => "chat",
+            LogEvent::Kill { .. } => "kill",
+            LogEvent::Admin { .. } => "admin",
+        }
+    }
+
+    /// Whether the player with this id or display name took part in the event
+    pub fn involves(&self, player: &str) -> bool {
+        let matches = |id: u32, name: &str| id.to_string() == player || name.eq_ignore_ascii_case(player);
+        match self {
+            LogEvent::Connect { player_id, .. } => player_id.to_string() == player,
+            LogEvent::Disconnect { player_id, name }
+            | LogEvent::Join { player_id, name, .. }
+            | LogEvent::Leave { player_id, name, .. } => matches(*player_id, name),
+            LogEvent::Chat { sender, sender_name, recipient, .. } => {
+                matches(*sender, sender_name) || recipient.is_some_and(|r| r.to_string() == player)
+            }
+            LogEvent::Kill { killer, killer_name, victim, victim_name, .. } => {
+                matches(*killer, killer_name) || matches(*victim, victim_name)
+            }
+            LogEvent::Admin { admin, admin_name, details, .. } => {
+                matches(*admin, admin_name) || details.contains(player)
+            }
+        }
+    }
+}
+
+/// Path of the current log file for index 0, older rotated files count up from 1
+pub fn log_file_path(dir: &Path, index: usize) -> PathBuf {
+    match index {
+        0 => dir.join(LOG_FILE_NAME),
+        i => dir.join(format!("{}.{}", LOG_FILE_NAME, i)),
+    }
+}
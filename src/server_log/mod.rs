@@ -0,0 +1,248 @@
+pub mod entry;
+pub mod query;
+
+use bevy::prelude::{error, Local, MessageReader, Query, Res, ResMut, Resource, With, Changed};
+use chrono::Utc;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, LineWriter, Write};
+use std::path::PathBuf;
+use crate::components::chat::{Chat, ChatChannel};
+use crate::components::chat_command::{required_permission, CommandIssued, PermissionLevel};
+use crate::components::common::Id;
+use crate::components::lobby::{InLobby, Lobby};
+use crate::components::player::PlayerMarker;
+use crate::components::profile::PlayerProfiles;
+use crate::components::weapon::PlayerKilled;
+use crate::network::net_manage::TcpConnection;
+use crate::network::net_message::STcpType;
+use crate::server_log::entry::{log_file_path, LogEntry, LogEvent};
+
+pub const DEFAULT_LOG_DIR: &str = "logs";
+const MAX_LOG_FILE_BYTES: u64 = 10 * 1024 * 1024;
+// The current file plus this many rotated ones are kept
+const MAX_ROTATED_LOG_FILES: usize = 5;
+
+/// Appends server events as JSON lines, starting a new file once the current one gets too big
+#[derive(Resource)]
+pub struct ServerLog {
+    dir: PathBuf,
+    file: Option<LineWriter<File>>,
+    size: u64,
+}
+
+impl Default for ServerLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_LOG_DIR)
+    }
+}
+
+impl ServerLog {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            file: None,
+            size: 0,
+        }
+    }
+
+    pub fn write(&mut self, event: LogEvent) {
+        let entry = LogEntry {
+            timestamp: Utc::now().timestamp_millis(),
+            event,
+        };
+        if let Err(e) = self.write_entry(&entry) {
+            error!("Couldn't write to the server log in {}: {}", self.dir.display(), e);
+            // Tries to reopen on the next write
+            self.file = None;
+        }
+    }
+
+    fn write_entry(&mut self, entry: &LogEntry) -> io::Result<()> {
+        let mut line = serde_json::to_string(entry).map_err(io::Error::other)?;
+        line.push('\n');
+
+        if self.file.is_none() {
+            self.open()?;
+        }
+        if self.size + line.len() as u64 > MAX_LOG_FILE_BYTES && self.size > 0 {
+            self.rotate()?;
+        }
+
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(line.as_bytes())?;
+            self.size += line.len() as u64;
+        }
+        Ok(())
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let file = OpenOptions::new().create(true).append(true).open(log_file_path(&self.dir, 0))?;
+        self.size = file.metadata()?.len();
+        self.file = Some(LineWriter::new(file));
+        Ok(())
+    }
+
+    /// server.log becomes server.log.1, server.log.1 becomes server.log.2 and so on, the oldest is dropped
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+
+        let oldest = log_file_path(&self.dir, MAX_ROTATED_LOG_FILES);
+        if oldest.exists() {
+            fs::remove_file(oldest)?;
+        }
+        for i in (0..MAX_ROTATED_LOG_FILES).rev() {
+            let from = log_file_path(&self.dir, i);
+            if from.exists() {
+                fs::rename(from, log_file_path(&self.dir, i + 1))?;
+            }
+        }
+
+        self.open()
+    }
+}
+
+/// Logs chat messages added to lobby chats since the last run
+pub fn log_chat(
+    lobbies: Query<(&Lobby, &Chat), Changed<Chat>>,
+    mut last_logged_id: Local<u64>,
+    mut log: ResMut<ServerLog>,
+) {
+    let mut newest = *last_logged_id;
+    // Whispers between lobbies are in both chats with the same id
+    let mut logged = HashSet::new();
+
+    for (lobby, chat) in lobbies.iter() {
+        for m in chat.chat_history.iter().filter(|m| m.id > *last_logged_id) {
+            newest = newest.max(m.id);
+            if !logged.insert(m.id) {
+                continue;
+            }
+
+            let (channel, recipient) = match m.channel {
+                ChatChannel::Global => ("global".to_string(), None),
+                ChatChannel::Team(team) => (format!("team {:?}", team).to_lowercase(), None),
+                ChatChannel::Direct { to } => ("direct".to_string(), Some(to.0)),
+            };
+            log.write(LogEvent::Chat {
+                message_id: m.id,
+                lobby_id: lobby.id.0,
+                sender: m.sender.0,
+                sender_name: m.sender_name.clone(),
+                channel,
+                recipient,
+                message: m.message.clone(),
+            });
+        }
+    }
+
+    *last_logged_id = newest;
+}
+
+/// Logs connects, disconnects, joins and leaves by comparing against what was seen on the last run
+pub fn log_membership(
+    lobbies: Query<&Lobby>,
+    connections: Query<&TcpConnection<STcpType>>,
+    profiles: Res<PlayerProfiles>,
+    mut known_members: Local<HashMap<Id, HashSet<Id>>>,
+    // Names are kept here since the profile is already gone once a disconnect is noticed
+    mut known_players: Local<HashMap<Id, String>>,
+    mut log: ResMut<ServerLog>,
+) {
+    let mut connected = HashSet::new();
+    for c in connections.iter() {
+        let Some(player_id) = c.player_id else {
+            continue;
+        };
+        connected.insert(player_id);
+
+        if !known_players.contains_key(&player_id) {
+            let address = c
+                .stream
+                .as_ref()
+                .and_then(|s| s.peer_addr().ok())
+                .map(|a| a.to_string())
+                .unwrap_or_default();
+            log.write(LogEvent::Connect { player_id: player_id.0, address });
+        }
+        known_players.insert(player_id, profiles.display_name(player_id));
+    }
+
+    let mut current_members: HashMap<Id, HashSet<Id>> = HashMap::new();
+    for lobby in lobbies.iter() {
+        let previous = known_members.remove(&lobby.id).unwrap_or_default();
+        for joined in lobby.members.difference(&previous) {
+            log.write(LogEvent::Join {
+                player_id: joined.0,
+                name: profiles.display_name(*joined),
+                lobby_id: lobby.id.0,
+                lobby_name: lobby.name.clone(),
+            });
+        }
+        for left in previous.difference(&lobby.members) {
+            log.write(LogEvent::Leave {
+                player_id: left.0,
+                name: known_players.get(left).cloned().unwrap_or_else(|| left.0.to_string()),
+                lobby_id: lobby.id.0,
+            });
+        }
+        current_members.insert(lobby.id, lobby.members.clone());
+    }
+    // Whatever is left belonged to lobbies that were closed
+    for (lobby_id, members) in known_members.drain() {
+        for left in members {
+            log.write(LogEvent::Leave {
+                player_id: left.0,
+                name: known_players.get(&left).cloned().unwrap_or_else(|| left.0.to_string()),
+                lobby_id: lobby_id.0,
+            });
+        }
+    }
+    *known_members = current_members;
+
+    known_players.retain(|player_id, name| {
+        if connected.contains(player_id) {
+            return true;
+        }
+        log.write(LogEvent::Disconnect { player_id: player_id.0, name: name.clone() });
+        false
+    });
+}
+
+pub fn log_kills(
+    mut kills: MessageReader<PlayerKilled>,
+    players: Query<(&Id, &InLobby), With<PlayerMarker>>,
+    profiles: Res<PlayerProfiles>,
+    mut log: ResMut<ServerLog>,
+) {
+    for kill in kills.read() {
+        let lobby_id = players.iter().find(|(id, _)| **id == kill.victim).map(|(_, l)| l.0 .0);
+        log.write(LogEvent::Kill {
+            lobby_id,
+            killer: kill.killer.0,
+            killer_name: profiles.display_name(kill.killer),
+            victim: kill.victim.0,
+            victim_name: profiles.display_name(kill.victim),
+        });
+    }
+}
+
+/// Logs every admin-only command that got past the permission check
+pub fn log_admin_commands(
+    mut issued: MessageReader<CommandIssued>,
+    profiles: Res<PlayerProfiles>,
+    mut log: ResMut<ServerLog>,
+) {
+    for CommandIssued { sender, command } in issued.read() {
+        if required_permission(command) < PermissionLevel::Admin {
+            continue;
+        }
+        log.write(LogEvent::Admin {
+            admin: sender.0,
+            admin_name: profiles.display_name(*sender),
+            command: command.name().to_string(),
+            details: format!("{:?}", command),
+        });
+    }
+}
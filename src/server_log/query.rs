@@ -0,0 +1,140 @@
+// Reads the JSON lines log written by the server and prints the entries matching the filters.
+//
+//   MPClient query-log --player Alice --since "2025-01-31 18:00" --event chat
+
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use clap::Args;
+use crate::server_log::entry::{log_file_path, LogEntry, LogEvent};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::PathBuf;
+
+// Highest rotated file index looked at, more than the server ever keeps
+const MAX_LOG_FILES: usize = 64;
+
+#[derive(Args)]
+pub struct QueryArgs {
+    /// Directory the server writes its log to
+    #[arg(long, default_value = "logs")]
+    dir: PathBuf,
+    /// Player id or display name
+    #[arg(long)]
+    player: Option<String>,
+    /// Only entries at or after this time, as unix milliseconds, RFC 3339 or local "YYYY-MM-DD[ HH:MM[:SS]]"
+    #[arg(long)]
+    since: Option<String>,
+    /// Only entries before this time, same formats as --since
+    #[arg(long)]
+    until: Option<String>,
+    /// connect, disconnect, join, leave, chat, kill or admin
+    #[arg(long)]
+    event: Option<String>,
+    /// Prints the matching lines as they are stored instead of formatted
+    #[arg(long)]
+    json: bool,
+}
+
+fn parse_time(input: &str) -> Result<i64, String> {
+    if let Ok(ms) = input.parse::<i64>() {
+        return Ok(ms);
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(input) {
+        return Ok(time.timestamp_millis());
+    }
+
+    let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(input, f).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(input, "%Y-%m-%d")
+                .ok()
+                .and_then(|d| d.and_hms_opt(0, 0, 0))
+        })
+        .ok_or_else(|| format!("Can't read {:?} as a time", input))?;
+
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|t| t.timestamp_millis())
+        .ok_or_else(|| format!("{:?} doesn't exist in the local time zone", input))
+}
+
+fn describe(event: &LogEvent) -> String {
+    match event {
+        LogEvent::Connect { player_id, address } => format!("{} connected from {}", player_id, address),
+        LogEvent::Disconnect { player_id, name } => format!("{} ({}) disconnected", name, player_id),
+        LogEvent::Join { player_id, name, lobby_id, lobby_name } => {
+            format!("{} ({}) joined lobby {} ({})", name, player_id, lobby_name, lobby_id)
+        }
+        LogEvent::Leave { player_id, name, lobby_id } => format!("{} ({}) left lobby {}", name, player_id, lobby_id),
+        LogEvent::Chat { lobby_id, sender, sender_name, channel, recipient, message, .. } => match recipient {
+            Some(to) => format!("[lobby {}] {} ({}) to {}: {}", lobby_id, sender_name, sender, to, message),
+            None => format!("[lobby {}] [{}] {} ({}): {}", lobby_id, channel, sender_name, sender, message),
+        },
+        LogEvent::Kill { lobby_id, killer, killer_name, victim, victim_name } => {
+            let lobby = lobby_id.map(|l| format!("[lobby {}] ", l)).unwrap_or_default();
+            format!("{}{} ({}) killed {} ({})", lobby, killer_name, killer, victim_name, victim)
+        }
+        LogEvent::Admin { admin, admin_name, command, details } => {
+            format!("{} ({}) ran /{}: {}", admin_name, admin, command, details)
+        }
+    }
+}
+
+/// Prints the log entries matching the filters, oldest first
+pub fn run(args: QueryArgs) -> io::Result<()> {
+    let since = args.since.as_deref().map(parse_time).transpose().map_err(io::Error::other)?;
+    let until = args.until.as_deref().map(parse_time).transpose().map_err(io::Error::other)?;
+
+    // Oldest rotated file first so the output is in order
+    let files: Vec<PathBuf> = (0..=MAX_LOG_FILES)
+        .rev()
+        .map(|i| log_file_path(&args.dir, i))
+        .filter(|p| p.exists())
+        .collect();
+    if files.is_empty() {
+        eprintln!("No log files in {}", args.dir.display());
+        return Ok(());
+    }
+
+    for path in files {
+        let reader = BufReader::new(File::open(&path)?);
+        for (line_number, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let entry: LogEntry = match serde_json::from_str(&line) {
+                Ok(e) => e,
+                Err(e) => {
+                    eprintln!("{}:{}: {}", path.display(), line_number + 1, e);
+                    continue;
+                }
+            };
+
+            if since.is_some_and(|s| entry.timestamp < s) || until.is_some_and(|u| entry.timestamp >= u) {
+                continue;
+            }
+            if args.event.as_deref().is_some_and(|e| !e.eq_ignore_ascii_case(entry.event.kind())) {
+                continue;
+            }
+            if args.player.as_deref().is_some_and(|p| !entry.event.involves(p)) {
+                continue;
+            }
+
+            if args.json {
+                println!("{}", line);
+            } else {
+                let time = Utc
+                    .timestamp_millis_opt(entry.timestamp)
+                    .single()
+                    .map(|t| t.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_default();
+                println!("{} {:<10} {}", time, entry.event.kind(), describe(&entry.event));
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -1,20 +1,40 @@
-use std::collections::VecDeque;
 use avian3d::PhysicsPlugins;
-use avian3d::prelude::{Collider, CollisionLayers, LayerMask, Physics, PhysicsTime, RigidBody};
+use avian3d::prelude::{Physics, PhysicsTime};
 use bevy::app::App;
 use bevy::log::LogPlugin;
 use bevy::MinimalPlugins;
-use bevy::prelude::{AssetPlugin, Assets, Commands, Fixed, Mesh, Plugin, Startup, Time, Transform, TransformPlugin, Update};
+use bevy::prelude::{AssetPlugin, Assets, Commands, Fixed, Res, FixedPostUpdate, FixedUpdate, Mesh, Plugin, Startup, Time, TransformPlugin, Update};
 use bevy::scene::ScenePlugin;
-use crate::components::chat::Chat;
-use crate::components::CollisionLayer;
+use crate::admin::AdminConfig;
+use crate::admin::plugin::AdminPlugin;
+use crate::components::chat::ChatSequence;
+use crate::components::chat_moderation::{load_chat_filter, ChatModeration};
+use crate::components::chat_command::{run_chat_commands, CommandIssued, Permissions};
+use crate::components::game_mode::plugin::GameModePlugin;
+use crate::components::lobby::{cleanup_closed_lobbies, spawn_default_lobby};
 use crate::components::player::plugin::PlayerPlugin;
+use crate::components::player::simulate_players;
+use crate::components::prop::move_platforms;
+use crate::components::weapon::tick_weapon_cooldowns;
+use crate::config::ServerConfig;
+use crate::network::net_bans::{expire_bans, BanList};
 use crate::network::net_plugin::{HostType, NetworkConfig, NetworkPlugin};
+use crate::server_log::{log_admin_commands, log_chat, log_kills, log_membership, ServerLog};
 
-pub struct ServerPlugin;
+pub struct ServerPlugin {
+    pub config: ServerConfig,
+}
+
+impl ServerPlugin {
+    pub fn new(config: ServerConfig) -> Self {
+        Self { config }
+    }
+}
 
 impl Plugin for ServerPlugin {
     fn build(&self, app: &mut App) {
+        let config = self.config.clone();
+
         app.add_plugins((
             MinimalPlugins,
             TransformPlugin::default(),
@@ -22,25 +42,41 @@ impl Plugin for ServerPlugin {
             ScenePlugin,
             LogPlugin::default(),
             PhysicsPlugins::default(),
-            NetworkPlugin::new(NetworkConfig{ host_type: HostType::Server }),
+            NetworkPlugin::new(NetworkConfig {
+                host_type: HostType::Server,
+                bind_address: config.network.socket_address(),
+                udp_pool_size: config.network.udp_pool_size,
+            }),
+            AdminPlugin,
             PlayerPlugin,
+            GameModePlugin,
         ));
+        let bans = BanList::load(&config.moderation.ban_file, config.moderation.max_connections_per_ip).unwrap_or_else(|e| {
+            // The file is left alone so the bans in it aren't lost on the next save
+            eprintln!("{}, starting without bans", e);
+            let mut bans = BanList::default();
+            bans.max_connections_per_ip = config.moderation.max_connections_per_ip;
+            bans
+        });
+        app.insert_resource(bans);
+        app.insert_resource(AdminConfig::from_settings(&config.admin));
+        app.insert_resource(Permissions::from_settings(&config.admin));
+        app.insert_resource(ServerLog::new(&config.logging.dir));
+        app.insert_resource(Time::<Fixed>::from_hz(config.simulation.tick_rate));
+        app.insert_resource(config);
+
         app.init_resource::<Assets<Mesh>>();
-        app.insert_resource(Time::<Fixed>::from_hz(60.0));
         app.insert_resource(Time::<Physics>::default());
-        app.add_systems(Startup, setup);
+        app.add_systems(Startup, (setup, load_chat_filter));
+        app.init_resource::<ChatSequence>();
+        app.init_resource::<ChatModeration>();
+        app.add_message::<CommandIssued>();
+        app.add_systems(Update, (cleanup_closed_lobbies, expire_bans));
+        app.add_systems(FixedUpdate, (run_chat_commands, tick_weapon_cooldowns, (move_platforms, simulate_players).chain()));
+        app.add_systems(FixedPostUpdate, (log_chat, log_membership, log_kills, log_admin_commands));
     }
 }
 
-fn setup(mut commands: Commands) {
-    commands.spawn(Chat {
-        chat_history: VecDeque::new(),
-    });
-
-    commands.spawn((
-        RigidBody::Static,
-        Collider::cuboid(40.0, 0.5, 40.0),
-        Transform::from_xyz(0.0, 0.0, 0.0),
-        CollisionLayers::new(CollisionLayer::Ground, [LayerMask::ALL]),
-    ));
-}
\ No newline at end of file
+fn setup(mut commands: Commands, config: Res<ServerConfig>) {
+    spawn_default_lobby(&mut commands, &config.gameplay);
+}
@@ -0,0 +1,48 @@
+use bevy::prelude::Vec2;
+use bincode::config;
+use crate::components::actions::{apply_dead_zone, ActionFlags, AnalogMove};
+
+#[test]
+fn analog_move_quantizes_to_full_range() {
+    assert_eq!(AnalogMove::from_vec2(Vec2::Y), AnalogMove { x: 0, y: 127 });
+    assert_eq!(AnalogMove::from_vec2(Vec2::NEG_X), AnalogMove { x: -127, y: 0 });
+    assert_eq!(AnalogMove::from_vec2(Vec2::new(0.0, 0.25)), AnalogMove { x: 0, y: 32 });
+    assert!(AnalogMove::from_vec2(Vec2::ZERO).is_zero());
+
+    // Longer than a full deflection is clamped, not wrapped
+    assert_eq!(AnalogMove::from_vec2(Vec2::new(3.0, 0.0)), AnalogMove { x: 127, y: 0 });
+    assert_eq!(AnalogMove::from_vec2(Vec2::ONE), AnalogMove { x: 90, y: 90 });
+}
+
+#[test]
+fn analog_move_round_trips() {
+    for direction in [Vec2::ZERO, Vec2::X, Vec2::NEG_Y, Vec2::ONE, Vec2::new(-0.3, 0.6), Vec2::new(0.25, 0.0)] {
+        let analog = AnalogMove::from_vec2(direction);
+        assert_eq!(AnalogMove::from_vec2(analog.to_vec2()), analog, "{:?}", direction);
+        assert!(analog.to_vec2().length() <= 1.0);
+        assert!((analog.to_vec2() - direction.clamp_length_max(1.0)).length() < 0.01, "{:?}", direction);
+    }
+}
+
+#[test]
+fn analog_move_survives_the_network() {
+    let analog = AnalogMove::from_vec2(Vec2::new(-0.3, 0.6));
+    let encoded = bincode::serde::encode_to_vec(analog, config::standard()).unwrap();
+    let (decoded, _): (AnalogMove, usize) = bincode::serde::decode_from_slice(&encoded, config::standard()).unwrap();
+    assert_eq!(decoded, analog);
+    assert_eq!(encoded.len(), 2);
+}
+
+#[test]
+fn keyboard_diagonals_match_the_stick() {
+    let keys = ActionFlags::MOVE_FORWARD | ActionFlags::MOVE_RIGHT;
+    assert_eq!(AnalogMove::from_vec2(keys.move_direction()), AnalogMove::from_vec2(Vec2::ONE));
+}
+
+#[test]
+fn dead_zone_reads_as_zero() {
+    assert_eq!(apply_dead_zone(Vec2::new(0.1, 0.1), 0.2), Vec2::ZERO);
+    assert_eq!(apply_dead_zone(Vec2::X, 1.0), Vec2::ZERO);
+    assert!((apply_dead_zone(Vec2::X, 0.2) - Vec2::X).length() < 1e-6);
+    assert!((apply_dead_zone(Vec2::new(0.0, -0.6), 0.2) - Vec2::new(0.0, -0.5)).length() < 1e-6);
+}
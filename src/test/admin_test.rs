@@ -0,0 +1,36 @@
+use crate::admin::{parse_admin_command, AdminCommand};
+use crate::components::common::Id;
+use crate::network::net_bans::BanTarget;
+
+#[test]
+fn parses_changemap() {
+    assert!(matches!(
+        parse_admin_command("changemap 3  Flatland "),
+        Ok(AdminCommand::ChangeMap { lobby: Id(3), map }) if map == "Flatland"
+    ));
+    assert!(parse_admin_command("changemap 3").is_err());
+    assert!(parse_admin_command("changemap lobby Flatland").is_err());
+}
+
+#[test]
+fn parses_moderation_commands() {
+    assert!(matches!(parse_admin_command("KICK 7 afk"), Ok(AdminCommand::Kick { player: Id(7), reason: Some(r) }) if r == "afk"));
+    assert!(matches!(
+        parse_admin_command("ban 10.0.0.0/8 30 spam bots"),
+        Ok(AdminCommand::Ban { target: BanTarget::Range { prefix: 8, .. }, minutes: Some(30), reason: Some(r) }) if r == "spam bots"
+    ));
+    assert!(matches!(
+        parse_admin_command("ban name:Bob cheating"),
+        Ok(AdminCommand::Ban { minutes: None, reason: Some(r), .. }) if r == "cheating"
+    ));
+    assert!(parse_admin_command("ban nonsense").is_err());
+    assert!(parse_admin_command("kick bob").is_err());
+}
+
+#[test]
+fn rejects_unknown_and_incomplete_commands() {
+    assert!(parse_admin_command("").is_err());
+    assert!(parse_admin_command("say").is_err());
+    assert!(parse_admin_command("settickrate fast").is_err());
+    assert!(parse_admin_command("reboot").is_err());
+}
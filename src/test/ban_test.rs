@@ -0,0 +1,87 @@
+use std::net::{IpAddr, Ipv4Addr};
+use crate::network::net_bans::BanTarget;
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+fn target(s: &str) -> BanTarget {
+    s.parse().unwrap()
+}
+
+#[test]
+fn parses_addresses_ranges_and_names() {
+    assert_eq!(target("203.0.113.7"), BanTarget::Address(ip("203.0.113.7")));
+    assert_eq!(target(" 10.0.0.0/8 "), BanTarget::Range { network: ip("10.0.0.0"), prefix: 8 });
+    assert_eq!(target("2001:db8::/32"), BanTarget::Range { network: ip("2001:db8::"), prefix: 32 });
+    assert_eq!(target("name: Alice "), BanTarget::Name("Alice".to_string()));
+}
+
+#[test]
+fn rejects_bad_targets() {
+    assert!("10.0.0.0/33".parse::<BanTarget>().is_err());
+    assert!("2001:db8::/129".parse::<BanTarget>().is_err());
+    assert!("10.0.0.0/x".parse::<BanTarget>().is_err());
+    assert!("not an address".parse::<BanTarget>().is_err());
+    assert!("name:  ".parse::<BanTarget>().is_err());
+}
+
+#[test]
+fn display_parses_back() {
+    for s in ["203.0.113.7", "10.0.0.0/8", "2001:db8::/32", "name:Alice"] {
+        assert_eq!(target(s).to_string(), s);
+        assert_eq!(target(&target(s).to_string()), target(s));
+    }
+}
+
+#[test]
+fn ipv4_ranges_match_their_addresses() {
+    let range = target("10.0.0.0/8");
+    assert!(range.matches_ip(ip("10.1.2.3")));
+    assert!(range.matches_ip(ip("10.255.255.255")));
+    assert!(!range.matches_ip(ip("11.0.0.1")));
+    assert!(!range.matches_ip(ip("2001:db8::1")));
+
+    assert!(target("0.0.0.0/0").matches_ip(ip("198.51.100.1")));
+    assert!(target("198.51.100.1/32").matches_ip(ip("198.51.100.1")));
+    assert!(!target("198.51.100.1/32").matches_ip(ip("198.51.100.2")));
+}
+
+#[test]
+fn ipv6_ranges_match_their_addresses() {
+    let range = target("2001:db8::/32");
+    assert!(range.matches_ip(ip("2001:db8::1")));
+    assert!(range.matches_ip(ip("2001:db8:ffff::1")));
+    assert!(!range.matches_ip(ip("2001:db9::1")));
+    assert!(!range.matches_ip(ip("10.0.0.1")));
+}
+
+#[test]
+fn mapped_clients_match_ipv4_bans() {
+    assert!(target("10.0.0.0/8").matches_ip(ip("::ffff:10.1.2.3")));
+    assert!(target("203.0.113.7").matches_ip(ip("::ffff:203.0.113.7")));
+    assert!(target("::ffff:203.0.113.7").matches_ip(ip("203.0.113.7")));
+}
+
+#[test]
+fn mapped_ranges_become_ipv4_ranges() {
+    let range = target("::ffff:10.0.0.0/104");
+    assert_eq!(range, BanTarget::Range { network: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), prefix: 8 });
+    assert!(range.matches_ip(ip("10.9.9.9")));
+    assert!(range.matches_ip(ip("::ffff:10.9.9.9")));
+    assert!(!range.matches_ip(ip("11.0.0.1")));
+
+    // Wider than the mapped space, stays IPv6 and still covers IPv4 clients
+    let wide = target("::ffff:0.0.0.0/95");
+    assert!(wide.matches_ip(ip("192.0.2.1")));
+    assert!(!wide.matches_ip(ip("2001:db8::1")));
+}
+
+#[test]
+fn names_match_ignoring_case() {
+    let name = target("name:Alice");
+    assert!(name.matches_name("alice"));
+    assert!(name.matches_name(" ALICE "));
+    assert!(!name.matches_name("Bob"));
+    assert!(!name.matches_ip(ip("10.0.0.1")));
+}
@@ -0,0 +1,66 @@
+use crate::components::chat_command::{parse_command, required_permission, ChatCommand, PermissionLevel};
+use crate::components::game_mode::Team;
+
+#[test]
+fn parses_commands_and_aliases() {
+    assert!(matches!(parse_command("/help"), Ok(ChatCommand::Help { command: None })));
+    assert!(matches!(parse_command("/? /Kick"), Ok(ChatCommand::Help { command: Some(c) }) if c == "kick"));
+    assert!(matches!(parse_command("/nick  New Name "), Ok(ChatCommand::Name { name }) if name == "New Name"));
+    assert!(matches!(parse_command("/TEAM Blue"), Ok(ChatCommand::Team { team: Team::Blue })));
+    assert!(matches!(parse_command("/team red"), Ok(ChatCommand::Team { team: Team::Red })));
+}
+
+#[test]
+fn whisper_takes_a_target_and_a_message() {
+    let Ok(ChatCommand::Whisper { target, message }) = parse_command("/msg Bob hello there") else {
+        panic!("expected a whisper");
+    };
+    assert_eq!(target, "Bob");
+    assert_eq!(message, "hello there");
+
+    assert!(parse_command("/w Bob").is_err());
+    assert!(parse_command("/w").is_err());
+}
+
+#[test]
+fn quoted_names_keep_their_spaces() {
+    let Ok(ChatCommand::Whisper { target, message }) = parse_command("/w \"Big Bob\" hi") else {
+        panic!("expected a whisper");
+    };
+    assert_eq!(target, "Big Bob");
+    assert_eq!(message, "hi");
+
+    assert!(matches!(parse_command("/kick \"Big Bob\""), Ok(ChatCommand::Kick { target, reason: None }) if target == "Big Bob"));
+    assert!(parse_command("/kick \"Big Bob").is_err());
+}
+
+#[test]
+fn kick_and_mute_arguments_are_optional() {
+    assert!(matches!(
+        parse_command("/kick Bob spamming chat"),
+        Ok(ChatCommand::Kick { target, reason: Some(r) }) if target == "Bob" && r == "spamming chat"
+    ));
+    assert!(matches!(parse_command("/mute Bob"), Ok(ChatCommand::Mute { minutes: None, .. })));
+    assert!(matches!(parse_command("/mute Bob 5"), Ok(ChatCommand::Mute { minutes: Some(5), .. })));
+    assert!(matches!(parse_command("/unmute Bob"), Ok(ChatCommand::Unmute { target }) if target == "Bob"));
+}
+
+#[test]
+fn rejects_bad_commands() {
+    assert!(parse_command("/dance").is_err());
+    assert!(parse_command("/name").is_err());
+    assert!(parse_command("/team green").is_err());
+    assert!(parse_command("/mute Bob x").is_err());
+    assert!(parse_command("/kick").is_err());
+    assert!(parse_command("/unmute").is_err());
+}
+
+#[test]
+fn moderation_needs_an_admin() {
+    for line in ["/kick Bob", "/mute Bob", "/unmute Bob"] {
+        assert_eq!(required_permission(&parse_command(line).unwrap()), PermissionLevel::Admin, "{}", line);
+    }
+    for line in ["/help", "/name Bob", "/w Bob hi", "/team red"] {
+        assert_eq!(required_permission(&parse_command(line).unwrap()), PermissionLevel::Player, "{}", line);
+    }
+}
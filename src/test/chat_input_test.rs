@@ -0,0 +1,106 @@
+use crate::components::chat::{ChatInput, ChatTarget};
+use crate::components::chat_moderation::MAX_CHAT_MESSAGE_LENGTH;
+
+fn typed(text: &str) -> ChatInput {
+    let mut input = ChatInput::default();
+    input.open(ChatTarget::All, text);
+    input
+}
+
+#[test]
+fn opens_with_the_caret_at_the_end() {
+    assert_eq!(typed("/w").prompt(), "> /w|");
+
+    let mut input = ChatInput::default();
+    input.open(ChatTarget::Team, "");
+    assert!(input.active);
+    assert_eq!(input.prompt(), "[Team] > |");
+}
+
+#[test]
+fn inserts_at_the_caret() {
+    let mut input = typed("hllo");
+    input.move_to_start();
+    input.move_right(false);
+    input.insert("e");
+    assert_eq!(input.prompt(), "> he|llo");
+
+    // Control characters from a paste are dropped
+    input.move_to_end();
+    input.insert(" there\n\t!");
+    assert_eq!(input.prompt(), "> hello there!|");
+}
+
+#[test]
+fn insert_stops_at_the_length_limit() {
+    let mut input = typed("");
+    input.insert(&"a".repeat(MAX_CHAT_MESSAGE_LENGTH + 10));
+    input.insert("b");
+    assert_eq!(input.submit(), "a".repeat(MAX_CHAT_MESSAGE_LENGTH));
+}
+
+#[test]
+fn caret_counts_characters_not_bytes() {
+    let mut input = typed("héé");
+    input.move_left(false);
+    input.backspace(false);
+    assert_eq!(input.prompt(), "> h|é");
+    input.delete(false);
+    assert_eq!(input.prompt(), "> h|");
+    input.move_right(false);
+    assert_eq!(input.prompt(), "> h|");
+}
+
+#[test]
+fn word_movement_skips_spaces() {
+    let mut input = typed("one two  three");
+    input.move_left(true);
+    assert_eq!(input.prompt(), "> one two  |three");
+    input.move_left(true);
+    assert_eq!(input.prompt(), "> one |two  three");
+    input.move_right(true);
+    assert_eq!(input.prompt(), "> one two|  three");
+    input.move_right(true);
+    assert_eq!(input.prompt(), "> one two  three|");
+}
+
+#[test]
+fn word_deletion() {
+    let mut input = typed("one two  three");
+    input.backspace(true);
+    assert_eq!(input.prompt(), "> one two  |");
+    input.backspace(true);
+    assert_eq!(input.prompt(), "> one |");
+
+    let mut input = typed("one two three");
+    input.move_to_start();
+    input.delete(true);
+    assert_eq!(input.prompt(), "> | two three");
+    input.delete(true);
+    assert_eq!(input.prompt(), "> | three");
+}
+
+#[test]
+fn history_browses_sent_lines_and_keeps_the_draft() {
+    let mut input = ChatInput::default();
+    for line in ["first", "second", "second", "  "] {
+        input.open(ChatTarget::All, line);
+        input.submit();
+    }
+    assert!(!input.active);
+
+    input.open(ChatTarget::All, "draft");
+    input.browse_history(true);
+    assert_eq!(input.prompt(), "> second|");
+    input.browse_history(true);
+    assert_eq!(input.prompt(), "> first|");
+    // Stays on the oldest line
+    input.browse_history(true);
+    assert_eq!(input.prompt(), "> first|");
+    input.browse_history(false);
+    assert_eq!(input.prompt(), "> second|");
+    input.browse_history(false);
+    assert_eq!(input.prompt(), "> draft|");
+    input.browse_history(false);
+    assert_eq!(input.prompt(), "> draft|");
+}
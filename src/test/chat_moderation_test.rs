@@ -0,0 +1,119 @@
+use std::fs;
+use std::net::IpAddr;
+use std::time::Duration;
+use crate::components::chat_moderation::{ChatModeration, MAX_CHAT_MESSAGE_LENGTH};
+use crate::components::common::Id;
+
+const PLAYER: Id = Id(1);
+
+fn ip(s: &str) -> Option<IpAddr> {
+    Some(s.parse().unwrap())
+}
+
+#[test]
+fn allows_a_burst_then_limits() {
+    let mut moderation = ChatModeration::default();
+    for i in 0..5 {
+        assert!(moderation.check(PLAYER, None, &format!("message {}", i)).is_ok());
+    }
+    assert!(moderation.check(PLAYER, None, "one too many").is_err());
+
+    // Every player has their own bucket
+    assert!(moderation.check(Id(2), None, "hello").is_ok());
+}
+
+#[test]
+fn forget_resets_the_bucket() {
+    let mut moderation = ChatModeration::default();
+    for i in 0..5 {
+        moderation.check(PLAYER, None, &format!("message {}", i)).unwrap();
+    }
+    moderation.forget(PLAYER);
+    assert!(moderation.check(PLAYER, None, "back again").is_ok());
+}
+
+#[test]
+fn rejects_duplicates_ignoring_case() {
+    let mut moderation = ChatModeration::default();
+    assert!(moderation.check(PLAYER, None, "gg").is_ok());
+    assert!(moderation.check(PLAYER, None, " GG ").is_err());
+    assert!(moderation.check(PLAYER, None, "gg wp").is_ok());
+}
+
+#[test]
+fn rejects_empty_and_long_messages() {
+    let mut moderation = ChatModeration::default();
+    assert!(moderation.check(PLAYER, None, "   ").is_err());
+    assert!(moderation.check(PLAYER, None, &"a".repeat(MAX_CHAT_MESSAGE_LENGTH + 1)).is_err());
+    assert!(moderation.check(PLAYER, None, &"a".repeat(MAX_CHAT_MESSAGE_LENGTH)).is_ok());
+}
+
+#[test]
+fn mutes_stay_with_the_address() {
+    let mut moderation = ChatModeration::default();
+    moderation.mute(ip("203.0.113.7").unwrap(), None);
+    assert!(moderation.check(PLAYER, ip("203.0.113.7"), "hello").is_err());
+
+    // Reconnecting gives a new id but the same address
+    moderation.forget(PLAYER);
+    assert!(moderation.check(Id(2), ip("203.0.113.7"), "hello").is_err());
+    assert!(moderation.check(Id(3), ip("198.51.100.1"), "hello").is_ok());
+
+    assert!(moderation.unmute(ip("203.0.113.7").unwrap()));
+    assert!(!moderation.unmute(ip("203.0.113.7").unwrap()));
+    assert!(moderation.check(Id(2), ip("203.0.113.7"), "hello").is_ok());
+}
+
+#[test]
+fn timed_mutes_run_out() {
+    let mut moderation = ChatModeration::default();
+    moderation.mute(ip("203.0.113.7").unwrap(), Some(Duration::ZERO));
+    assert!(moderation.check(PLAYER, ip("203.0.113.7"), "hello").is_ok());
+
+    moderation.mute(ip("203.0.113.7").unwrap(), Some(Duration::from_secs(60)));
+    assert_eq!(moderation.check(PLAYER, ip("203.0.113.7"), "hi").unwrap_err(), "You are muted for 1 more minute(s)");
+}
+
+#[test]
+fn masks_filtered_words() {
+    // Unique per process so parallel test runs don't share the file
+    let path = std::env::temp_dir().join(format!("mpclient_{}_masks_filtered_words.txt", std::process::id()));
+    fs::write(&path, "# comment\n\nDarn\n").unwrap();
+
+    let mut moderation = ChatModeration::default();
+    moderation.load_word_filter(path.to_str().unwrap());
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(moderation.check(PLAYER, None, "oh DARN it, darnit").unwrap(), "oh **** it, darnit");
+}
+
+#[test]
+fn commands_share_the_rate_limit() {
+    let mut moderation = ChatModeration::default();
+    for _ in 0..3 {
+        assert!(moderation.check_command(PLAYER, None).is_ok());
+    }
+    assert!(moderation.check(PLAYER, None, "hello").is_ok());
+    assert!(moderation.check_command(PLAYER, None).is_ok());
+    assert!(moderation.check_command(PLAYER, None).is_err());
+    assert!(moderation.check(PLAYER, None, "too fast").is_err());
+}
+
+#[test]
+fn commands_without_an_address_skip_the_mute() {
+    let mut moderation = ChatModeration::default();
+    moderation.mute(ip("203.0.113.7").unwrap(), None);
+    assert!(moderation.check_command(PLAYER, ip("203.0.113.7")).is_err());
+    assert!(moderation.check_command(PLAYER, None).is_ok());
+}
+
+#[test]
+fn command_text_is_checked_without_taking_another_token() {
+    let mut moderation = ChatModeration::default();
+    for _ in 0..5 {
+        moderation.check_command(PLAYER, None).unwrap();
+    }
+    assert!(moderation.check_command_text(PLAYER, "whispered").is_ok());
+    assert!(moderation.check_command_text(PLAYER, "Whispered").is_err());
+    assert!(moderation.check_command_text(PLAYER, &"a".repeat(MAX_CHAT_MESSAGE_LENGTH + 1)).is_err());
+}
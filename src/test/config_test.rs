@@ -0,0 +1,95 @@
+use std::path::PathBuf;
+use crate::config::{ServerArgs, ServerConfig, DEFAULT_GROUND_SIZE, MAX_PLAYERS_PER_LOBBY, MAX_TICK_RATE};
+
+const EXAMPLE_CONFIG: &str = include_str!("../../server.example.toml");
+
+#[test]
+fn defaults_are_valid() {
+    let config = ServerConfig::default();
+    assert_eq!(config.validate(), Ok(()));
+    assert_eq!(config.gameplay.ground_size, DEFAULT_GROUND_SIZE);
+}
+
+#[test]
+fn example_config_is_valid() {
+    let config: ServerConfig = toml::from_str(EXAMPLE_CONFIG).unwrap();
+    assert_eq!(config.validate(), Ok(()));
+    assert_eq!(config.network.port, 4444);
+    assert!(config.admin.chat_admins.is_empty());
+}
+
+#[test]
+fn missing_settings_keep_defaults() {
+    let config: ServerConfig = toml::from_str("[network]\nport = 5000\n").unwrap();
+    assert_eq!(config.network.port, 5000);
+    assert_eq!(config.simulation.tick_rate, ServerConfig::default().simulation.tick_rate);
+}
+
+#[test]
+fn unknown_settings_are_rejected() {
+    assert!(toml::from_str::<ServerConfig>("[network]\nprot = 5000\n").is_err());
+    assert!(toml::from_str::<ServerConfig>("[netwrok]\nport = 5000\n").is_err());
+}
+
+#[test]
+fn every_error_is_listed() {
+    let mut config = ServerConfig::default();
+    config.network.port = 0;
+    config.simulation.tick_rate = MAX_TICK_RATE + 1.0;
+    config.gameplay.ground_size = [40.0, -1.0];
+    config.logging.dir = " ".to_string();
+
+    let error = config.validate().unwrap_err();
+    assert!(error.contains("network.port"));
+    assert!(error.contains("simulation.tick_rate"));
+    assert!(error.contains("gameplay.ground_size"));
+    assert!(error.contains("logging.dir"));
+}
+
+#[test]
+fn min_players_fits_in_a_lobby() {
+    let mut config = ServerConfig::default();
+    config.gameplay.min_players = config.gameplay.max_players_per_lobby + 1;
+    assert!(config.validate().unwrap_err().contains("gameplay.min_players"));
+}
+
+#[test]
+fn chat_admins_only_take_addresses() {
+    let config: ServerConfig = toml::from_str("[admin]\nchat_admins = [\"203.0.113.7\", \"10.0.0.0/8\"]\n").unwrap();
+    assert_eq!(config.validate(), Ok(()));
+
+    let config: ServerConfig = toml::from_str("[admin]\nchat_admins = [\"name:Alice\"]\n").unwrap();
+    assert!(config.validate().unwrap_err().contains("admin.chat_admins"));
+
+    assert!(toml::from_str::<ServerConfig>("[admin]\nchat_admins = [\"not an address\"]\n").is_err());
+}
+
+#[test]
+fn flags_override_the_config_file() {
+    let args = ServerArgs {
+        config: PathBuf::from("does-not-exist.toml"),
+        bind: None,
+        port: Some(5000),
+        tick_rate: Some(30.0),
+        max_players: None,
+        log_dir: None,
+        admin_address: None,
+        no_console: true,
+    };
+    let config = args.load_config().unwrap();
+    assert_eq!(config.network.port, 5000);
+    assert_eq!(config.simulation.tick_rate, 30.0);
+    assert!(!config.admin.console);
+
+    let args = ServerArgs { tick_rate: Some(MAX_TICK_RATE * 2.0), ..args };
+    assert!(args.load_config().is_err());
+}
+
+#[test]
+fn lobby_size_is_capped() {
+    let mut config = ServerConfig::default();
+    config.gameplay.max_players_per_lobby = MAX_PLAYERS_PER_LOBBY;
+    assert_eq!(config.validate(), Ok(()));
+    config.gameplay.max_players_per_lobby = MAX_PLAYERS_PER_LOBBY + 1;
+    assert!(config.validate().unwrap_err().contains("gameplay.max_players_per_lobby"));
+}
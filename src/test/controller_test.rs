@@ -0,0 +1,181 @@
+use std::time::Duration;
+use avian3d::PhysicsPlugins;
+use avian3d::collision::CollisionDiagnostics;
+use avian3d::dynamics::solver::SolverDiagnostics;
+use avian3d::prelude::{SpatialQueryDiagnostics, Collider, Physics, PhysicsSchedule, RigidBody, SpatialQueryFilter, SpatialQueryPipeline};
+use bevy::mesh::MeshPlugin;
+use bevy::prelude::*;
+use bevy::scene::ScenePlugin;
+use crate::components::actions::{ActionFlags, AnalogMove};
+use crate::components::player::controller::{step_character, MotorState, MovementSettings};
+
+const DT: f32 = 1.0 / 60.0;
+/// Centre height of a standing capsule resting on y = 0, the controller keeps a small gap to the ground
+const STANDING_Y: f32 = 1.02;
+const FORWARD: AnalogMove = AnalogMove { x: 0, y: 127 };
+
+/// Static level geometry with the spatial query pipeline built, on top of a floor whose top is at y = 0
+fn level(shapes: &[(Collider, Vec3, Quat)]) -> App {
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        TransformPlugin::default(),
+        AssetPlugin::default(),
+        MeshPlugin,
+        ScenePlugin,
+        PhysicsPlugins::default(),
+    ));
+    app.insert_resource(CollisionDiagnostics::default());
+    app.insert_resource(SolverDiagnostics::default());
+    app.insert_resource(SpatialQueryDiagnostics::default());
+
+    app.world_mut().spawn((RigidBody::Static, Collider::cuboid(100.0, 1.0, 100.0), Transform::from_xyz(0.0, -0.5, 0.0)));
+    for (collider, position, rotation) in shapes {
+        app.world_mut().spawn((RigidBody::Static, collider.clone(), Transform::from_translation(*position).with_rotation(*rotation)));
+    }
+
+    app.update();
+    for _ in 0..2 {
+        app.world_mut().resource_mut::<Time<Physics>>().advance_by(Duration::from_secs_f32(DT));
+        app.world_mut().run_schedule(PhysicsSchedule);
+    }
+    app
+}
+
+/// Runs the controller like the server does, integrating the returned velocity
+struct Walker {
+    position: Vec3,
+    motor: MotorState,
+}
+
+impl Walker {
+    fn at(position: Vec3) -> Self {
+        Self { position, motor: MotorState::default() }
+    }
+
+    fn step(&mut self, app: &App, actions: ActionFlags, movement: AnalogMove) {
+        let pipeline = app.world().resource::<SpatialQueryPipeline>();
+        let mut hits = Vec::new();
+        let velocity = step_character(
+            actions,
+            movement,
+            0.0,
+            &mut self.motor,
+            &mut self.position,
+            &MovementSettings::default(),
+            pipeline,
+            &SpatialQueryFilter::default(),
+            &|_| Vec3::ZERO,
+            &mut hits,
+            DT,
+        );
+        self.position += velocity * DT;
+    }
+
+    /// Highest the centre got over the ticks
+    fn run(&mut self, app: &App, ticks: usize, actions: ActionFlags, movement: AnalogMove) -> f32 {
+        let mut highest = self.position.y;
+        for _ in 0..ticks {
+            self.step(app, actions, movement);
+            highest = highest.max(self.position.y);
+        }
+        highest
+    }
+}
+
+#[test]
+fn lands_and_stays_on_the_ground() {
+    let app = level(&[]);
+    let mut walker = Walker::at(Vec3::new(0.0, STANDING_Y + 0.5, 0.0));
+    walker.run(&app, 60, ActionFlags::empty(), AnalogMove::default());
+
+    assert!(walker.motor.grounded);
+    assert!((walker.position.y - STANDING_Y).abs() < 0.03, "{}", walker.position.y);
+}
+
+#[test]
+fn jump_only_on_the_press() {
+    let app = level(&[]);
+    let mut walker = Walker::at(Vec3::new(0.0, STANDING_Y, 0.0));
+    walker.run(&app, 10, ActionFlags::empty(), AnalogMove::default());
+
+    let highest = walker.run(&app, 120, ActionFlags::JUMP, AnalogMove::default());
+    assert!(highest > STANDING_Y + 0.5, "{}", highest);
+    // Still holding jump after landing doesn't jump again
+    assert!(walker.motor.grounded);
+    assert!(walker.run(&app, 30, ActionFlags::JUMP, AnalogMove::default()) < STANDING_Y + 0.03);
+}
+
+#[test]
+fn coyote_time_allows_a_late_jump() {
+    // Nothing below, as if the player just walked off a ledge
+    let app = level(&[]);
+    let mut walker = Walker::at(Vec3::new(0.0, 20.0, 0.0));
+    walker.motor.coyote_time = 0.1;
+    walker.step(&app, ActionFlags::empty(), AnalogMove::default());
+    walker.step(&app, ActionFlags::JUMP, AnalogMove::default());
+    assert!(walker.motor.vertical_speed > 0.0);
+
+    let mut walker = Walker::at(Vec3::new(0.0, 20.0, 0.0));
+    walker.motor.coyote_time = 0.1;
+    walker.run(&app, 10, ActionFlags::empty(), AnalogMove::default());
+    walker.step(&app, ActionFlags::JUMP, AnalogMove::default());
+    assert!(walker.motor.vertical_speed < 0.0);
+}
+
+#[test]
+fn jump_buffer_jumps_on_landing() {
+    let app = level(&[]);
+
+    // Pressed just before touching down
+    let mut walker = Walker::at(Vec3::new(0.0, STANDING_Y + 0.1, 0.0));
+    walker.motor.vertical_speed = -3.0;
+    let highest = walker.run(&app, 60, ActionFlags::JUMP, AnalogMove::default());
+    assert!(highest > STANDING_Y + 0.5, "{}", highest);
+
+    // Pressed too long before, holding it doesn't count as a new press
+    let mut walker = Walker::at(Vec3::new(0.0, STANDING_Y + 1.0, 0.0));
+    let highest = walker.run(&app, 90, ActionFlags::JUMP, AnalogMove::default());
+    assert!(highest <= STANDING_Y + 1.0, "{}", highest);
+    assert!(walker.motor.grounded);
+}
+
+#[test]
+fn walks_up_low_steps_only() {
+    let step = |height: f32| (Collider::cuboid(4.0, height, 4.0), Vec3::new(0.0, height / 2.0, -3.0), Quat::IDENTITY);
+
+    let app = level(&[step(0.3)]);
+    let mut walker = Walker::at(Vec3::new(0.0, STANDING_Y, 0.0));
+    walker.run(&app, 40, ActionFlags::SPRINT, FORWARD);
+    assert!(walker.position.z < -2.0, "{}", walker.position.z);
+    assert!((walker.position.y - (STANDING_Y + 0.3)).abs() < 0.05, "{}", walker.position.y);
+
+    let app = level(&[step(0.6)]);
+    let mut walker = Walker::at(Vec3::new(0.0, STANDING_Y, 0.0));
+    walker.run(&app, 60, ActionFlags::SPRINT, FORWARD);
+    assert!(walker.position.z > -1.0, "{}", walker.position.z);
+    assert!(walker.position.y < STANDING_Y + 0.03, "{}", walker.position.y);
+}
+
+#[test]
+fn too_steep_slopes_block() {
+    // An 8 m ramp rising away from the player, its low end on the floor a metre in front
+    let ramp = |degrees: f32| {
+        let angle = degrees.to_radians();
+        (
+            Collider::cuboid(4.0, 0.2, 8.0),
+            Vec3::new(0.0, 4.0 * angle.sin(), -1.0 - 4.0 * angle.cos()),
+            Quat::from_rotation_x(angle),
+        )
+    };
+
+    let app = level(&[ramp(30.0)]);
+    let mut walker = Walker::at(Vec3::new(0.0, STANDING_Y, 0.0));
+    let highest = walker.run(&app, 120, ActionFlags::SPRINT, FORWARD);
+    assert!(highest > STANDING_Y + 2.0, "{}", highest);
+
+    let app = level(&[ramp(60.0)]);
+    let mut walker = Walker::at(Vec3::new(0.0, STANDING_Y, 0.0));
+    let highest = walker.run(&app, 120, ActionFlags::SPRINT, FORWARD);
+    assert!(highest < STANDING_Y + 0.6, "{}", highest);
+}
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use crate::components::common::Id;
+use crate::components::game_mode::deathmatch::Deathmatch;
+use crate::components::game_mode::team_deathmatch::TeamDeathmatch;
+use crate::components::game_mode::{GameMode, MatchSettings, MatchWinner, Team};
+use crate::components::scoreboard::{ScoreEntry, Scoreboard};
+
+fn scoreboard(scores: &[(u32, i32)]) -> Scoreboard {
+    let mut scoreboard = Scoreboard::default();
+    for (id, score) in scores {
+        scoreboard.entries.insert(Id(*id), ScoreEntry { score: *score, ..Default::default() });
+    }
+    scoreboard
+}
+
+fn teams(red: &[u32], blue: &[u32]) -> HashMap<Id, Team> {
+    red.iter()
+        .map(|id| (Id(*id), Team::Red))
+        .chain(blue.iter().map(|id| (Id(*id), Team::Blue)))
+        .collect()
+}
+
+fn settings() -> MatchSettings {
+    MatchSettings { score_limit: 10, ..Default::default() }
+}
+
+#[test]
+fn deathmatch_winner_reaches_the_score_limit() {
+    let no_teams = HashMap::new();
+    assert_eq!(Deathmatch.winner(&scoreboard(&[(1, 9), (2, 3)]), &no_teams, &settings(), false), None);
+    assert_eq!(
+        Deathmatch.winner(&scoreboard(&[(1, 10), (2, 3)]), &no_teams, &settings(), false),
+        Some(MatchWinner::Player(Id(1)))
+    );
+    assert_eq!(Deathmatch.winner(&Scoreboard::default(), &no_teams, &settings(), true), None);
+}
+
+#[test]
+fn deathmatch_time_up_goes_to_the_leader_or_a_draw() {
+    let no_teams = HashMap::new();
+    assert_eq!(
+        Deathmatch.winner(&scoreboard(&[(1, 2), (2, 5)]), &no_teams, &settings(), true),
+        Some(MatchWinner::Player(Id(2)))
+    );
+    assert_eq!(Deathmatch.winner(&scoreboard(&[(1, 4), (2, 4)]), &no_teams, &settings(), true), Some(MatchWinner::Draw));
+}
+
+#[test]
+fn team_scores_add_up_members() {
+    let teams = teams(&[1, 2], &[3]);
+    let scores = TeamDeathmatch.team_scores(&scoreboard(&[(1, 3), (2, 4), (3, 5), (4, 100)]), &teams);
+    assert_eq!(scores, vec![(Team::Red, 7), (Team::Blue, 5)]);
+}
+
+#[test]
+fn team_deathmatch_winner() {
+    let teams = teams(&[1, 2], &[3, 4]);
+    assert_eq!(TeamDeathmatch.winner(&scoreboard(&[(1, 5), (3, 9)]), &teams, &settings(), false), None);
+    assert_eq!(
+        TeamDeathmatch.winner(&scoreboard(&[(1, 5), (2, 5), (3, 9)]), &teams, &settings(), false),
+        Some(MatchWinner::Team(Team::Red))
+    );
+    assert_eq!(
+        TeamDeathmatch.winner(&scoreboard(&[(1, 1), (3, 2)]), &teams, &settings(), true),
+        Some(MatchWinner::Team(Team::Blue))
+    );
+    assert_eq!(TeamDeathmatch.winner(&scoreboard(&[(1, 2), (4, 2)]), &teams, &settings(), true), Some(MatchWinner::Draw));
+}
+
+#[test]
+fn new_players_join_the_smaller_team() {
+    assert_eq!(TeamDeathmatch.assign_team(&HashMap::new()), Team::Red);
+    assert_eq!(TeamDeathmatch.assign_team(&teams(&[1], &[])), Team::Blue);
+    assert_eq!(TeamDeathmatch.assign_team(&teams(&[1], &[2])), Team::Red);
+    assert_eq!(Deathmatch.assign_team(&teams(&[1], &[2])), Team::None);
+}
+
+#[test]
+fn rebalance_evens_out_teams() {
+    assert!(TeamDeathmatch.rebalance(&teams(&[1, 2], &[3])).is_empty());
+    assert!(TeamDeathmatch.rebalance(&teams(&[1, 2], &[3, 4])).is_empty());
+
+    // Highest ids move first, so the same teams always rebalance the same way
+    assert_eq!(TeamDeathmatch.rebalance(&teams(&[1, 2, 3], &[4])), vec![(Id(3), Team::Blue)]);
+    assert_eq!(
+        TeamDeathmatch.rebalance(&teams(&[], &[1, 2, 3, 4, 5])),
+        vec![(Id(5), Team::Red), (Id(4), Team::Red)]
+    );
+    assert!(Deathmatch.rebalance(&teams(&[1, 2, 3], &[])).is_empty());
+}
+
+#[test]
+fn friendly_fire_follows_the_setting() {
+    let mut settings = settings();
+    assert!(!TeamDeathmatch.can_damage(Team::Red, Team::Red, &settings));
+    assert!(TeamDeathmatch.can_damage(Team::Red, Team::Blue, &settings));
+    assert!(Deathmatch.can_damage(Team::None, Team::None, &settings));
+    settings.friendly_fire = true;
+    assert!(TeamDeathmatch.can_damage(Team::Blue, Team::Blue, &settings));
+}
@@ -0,0 +1,25 @@
+use std::collections::HashSet;
+use crate::components::common::{Id, IdPool};
+
+#[test]
+fn ids_start_at_one() {
+    let mut pool = IdPool::default();
+    assert_eq!(pool.allocate(), Some(Id(1)));
+    assert_eq!(pool.allocate(), Some(Id(2)));
+}
+
+#[test]
+fn ids_are_unique() {
+    let mut pool = IdPool::default();
+    let ids: HashSet<Id> = (0..1000).map(|_| pool.allocate().unwrap()).collect();
+    assert_eq!(ids.len(), 1000);
+    assert!(!ids.contains(&Id(0)));
+}
+
+#[test]
+fn released_ids_are_not_handed_out_again_straight_away() {
+    let mut pool = IdPool::default();
+    let first = pool.allocate().unwrap();
+    pool.release(first);
+    assert_ne!(pool.allocate(), Some(first));
+}
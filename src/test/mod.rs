@@ -1 +1,14 @@
-mod physics_test;
\ No newline at end of file
+mod physics_test;
+mod ban_test;
+mod config_test;
+mod chat_command_test;
+mod id_pool_test;
+mod chat_moderation_test;
+mod actions_test;
+mod admin_test;
+mod snapshot_test;
+mod game_mode_test;
+mod weapon_test;
+mod chat_input_test;
+mod profile_test;
+mod controller_test;
@@ -0,0 +1,56 @@
+use crate::components::common::Id;
+use crate::components::profile::{sanitize_name, unique_name, PlayerProfile, PlayerProfiles, DEFAULT_PLAYER_NAME, MAX_PLAYER_NAME_LENGTH};
+
+fn profiles(names: &[(u32, &str)]) -> PlayerProfiles {
+    let mut profiles = PlayerProfiles::default();
+    for (id, name) in names {
+        profiles.profiles.insert(Id(*id), PlayerProfile { name: name.to_string(), ..Default::default() });
+    }
+    profiles
+}
+
+#[test]
+fn sanitize_keeps_letters_digits_and_separators() {
+    assert_eq!(sanitize_name("  Big   Bob_2-x "), "Big Bob_2-x");
+    assert_eq!(sanitize_name("<b>Bob</b>!"), "bBobb");
+    assert_eq!(sanitize_name("Zoë"), "Zoë");
+}
+
+#[test]
+fn sanitize_falls_back_to_the_default_name() {
+    assert_eq!(sanitize_name(""), DEFAULT_PLAYER_NAME);
+    assert_eq!(sanitize_name("!!! ***"), DEFAULT_PLAYER_NAME);
+}
+
+#[test]
+fn sanitize_cuts_long_names() {
+    let name = sanitize_name(&"x".repeat(MAX_PLAYER_NAME_LENGTH * 2));
+    assert_eq!(name.chars().count(), MAX_PLAYER_NAME_LENGTH);
+    // No trailing space left over from the cut
+    assert_eq!(sanitize_name("abcdefghijklmno pq"), "abcdefghijklmno");
+}
+
+#[test]
+fn unique_name_appends_a_number() {
+    let profiles = profiles(&[(1, "Bob"), (2, "bob 2")]);
+    assert_eq!(unique_name("Alice".to_string(), Id(3), &profiles), "Alice");
+    assert_eq!(unique_name("BOB".to_string(), Id(3), &profiles), "BOB 3");
+    // A player keeping their own name isn't a clash
+    assert_eq!(unique_name("Bob".to_string(), Id(1), &profiles), "Bob");
+}
+
+#[test]
+fn unique_name_stays_within_the_length_limit() {
+    let long = "x".repeat(MAX_PLAYER_NAME_LENGTH);
+    let profiles = profiles(&[(1, &long)]);
+    let name = unique_name(long.clone(), Id(2), &profiles);
+    assert_eq!(name, format!("{} 2", "x".repeat(MAX_PLAYER_NAME_LENGTH - 2)));
+    assert!(name.chars().count() <= MAX_PLAYER_NAME_LENGTH);
+}
+
+#[test]
+fn display_name_falls_back_to_the_id() {
+    let profiles = profiles(&[(1, "Bob")]);
+    assert_eq!(profiles.display_name(Id(1)), "Bob");
+    assert_eq!(profiles.display_name(Id(7)), "7");
+}
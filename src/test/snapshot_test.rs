@@ -0,0 +1,38 @@
+use std::collections::HashMap;
+use bevy::prelude::{Quat, Vec3};
+use bincode::config;
+use crate::components::common::Id;
+use crate::components::player::animation::AnimationState;
+use crate::components::player::PlayerState;
+use crate::components::prop::{PropKind, PropState};
+use crate::config::MAX_PLAYERS_PER_LOBBY;
+use crate::network::net_manage::MAX_DATAGRAM_SIZE;
+use crate::network::net_message::{NetworkMessage, SUdpType};
+
+// spawn_lobby_props puts two platforms and two crates in every lobby
+const LOBBY_PROPS: u32 = 4;
+
+#[test]
+fn full_lobby_snapshot_fits_in_a_datagram() {
+    let player = PlayerState::new(Vec3::splat(-1000.5), Vec3::splat(99.9), 123.4, -1.5, AnimationState::Dead, true, true, true);
+    let players: HashMap<Id, PlayerState> = (0..MAX_PLAYERS_PER_LOBBY as u32).map(|i| (Id(u32::MAX - i), player)).collect();
+
+    let prop = PropState {
+        kind: PropKind::Platform,
+        size: Vec3::splat(3.0),
+        position: Vec3::splat(-1000.5),
+        rotation: Quat::from_rotation_y(1.0),
+        linear_velocity: Vec3::splat(99.9),
+    };
+    let props: HashMap<u32, PropState> = (0..LOBBY_PROPS).map(|i| (i, prop)).collect();
+
+    // Everything the server can put in one datagram in the same tick
+    let messages = vec![
+        NetworkMessage(SUdpType::Sequence { sequence_number: u16::MAX }),
+        NetworkMessage(SUdpType::Players { players }),
+        NetworkMessage(SUdpType::Props { props }),
+        NetworkMessage(SUdpType::Pong { initiation_time: u32::MAX, server_received_time: u32::MAX }),
+    ];
+    let encoded = bincode::serde::encode_to_vec(&messages, config::standard()).unwrap();
+    assert!(encoded.len() <= MAX_DATAGRAM_SIZE, "{} bytes", encoded.len());
+}
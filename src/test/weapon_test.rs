@@ -0,0 +1,28 @@
+use std::f32::consts::FRAC_PI_2;
+use bevy::prelude::Vec3;
+use crate::components::weapon::aimed_at;
+
+#[test]
+fn aim_has_to_point_at_the_target() {
+    // Yaw 0 and pitch 0 look down -z
+    assert!(aimed_at(0.0, 0.0, Vec3::new(0.0, 0.0, -50.0)));
+    assert!(aimed_at(0.0, 0.0, Vec3::new(1.0, -0.5, -50.0)));
+    assert!(!aimed_at(0.0, 0.0, Vec3::new(10.0, 0.0, -50.0)));
+    assert!(!aimed_at(0.0, 0.0, Vec3::new(0.0, 0.0, 50.0)));
+}
+
+#[test]
+fn point_blank_shots_get_the_same_slack() {
+    assert!(aimed_at(0.0, 0.0, Vec3::new(1.0, 0.0, -1.0)));
+    assert!(!aimed_at(0.0, 0.0, Vec3::new(2.0, 0.0, -1.0)));
+}
+
+#[test]
+fn aim_follows_yaw_and_pitch() {
+    // A quarter turn to the left looks down -x
+    assert!(aimed_at(FRAC_PI_2, 0.0, Vec3::new(-30.0, 0.0, 0.0)));
+    assert!(!aimed_at(FRAC_PI_2, 0.0, Vec3::new(0.0, 0.0, -30.0)));
+    // Positive pitch looks down
+    assert!(aimed_at(0.0, 0.5, Vec3::new(0.0, -30.0 * 0.5f32.tan(), -30.0)));
+    assert!(!aimed_at(0.0, 0.5, Vec3::new(0.0, 30.0 * 0.5f32.tan(), -30.0)));
+}